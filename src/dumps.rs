@@ -33,7 +33,7 @@ use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 use std::fs::File;
 #[cfg(feature = "dump-load")]
-use std::io::BufRead;
+use std::io::{BufRead, Read};
 #[cfg(feature = "dump-create")]
 use std::io::{BufWriter, Write};
 use std::path::Path;
@@ -130,6 +130,16 @@ fn serialize_to_writer_impl<T: Serialize, W: Write>(
     }
 }
 
+/// Decompresses a dump made with [`dump_binary`], for decoding it in parts
+///
+/// [`dump_binary`]: fn.dump_binary.html
+#[cfg(feature = "dump-load")]
+pub(crate) fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(data).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
 /// Private low level helper function used to implement the public API.
 #[cfg(feature = "dump-load")]
 fn deserialize_from_reader_impl<T: DeserializeOwned, R: BufRead>(
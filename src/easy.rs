@@ -2,12 +2,17 @@
 //! files without caring about intermediate semantic representation
 //! and caching.
 
-use crate::highlighting::{HighlightIterator, HighlightState, Highlighter, Style, Theme};
-use crate::parsing::{ParseState, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet};
+use crate::highlighting::{
+    HighlightIterator, HighlightState, Highlighter, RangedHighlightIterator, Style, Theme,
+};
+use crate::parsing::{ParseState, Profiler, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet};
 use crate::Error;
 use std::fs::File;
 use std::io::{self, BufReader};
+use std::ops::Range;
 use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 // use util::debug_print_ops;
 
 /// Simple way to go directly from lines of text to colored tokens.
@@ -70,6 +75,25 @@ impl<'a> HighlightLines<'a> {
             .expect("`highlight` is deprecated, use `highlight_line` instead")
     }
 
+    /// Sets a flag that cancels highlighting when it is set to `true`, see
+    /// [`ParseState::set_cancellation_flag`]. [`highlight_line`] then returns a
+    /// [`ParsingError::Cancelled`].
+    ///
+    /// [`ParseState::set_cancellation_flag`]: ../parsing/struct.ParseState.html#method.set_cancellation_flag
+    /// [`highlight_line`]: #method.highlight_line
+    /// [`ParsingError::Cancelled`]: ../parsing/enum.ParsingError.html#variant.Cancelled
+    pub fn set_cancellation_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.parse_state.set_cancellation_flag(flag);
+    }
+
+    /// Collects statistics about the regex searches while highlighting, see
+    /// [`ParseState::set_profiler`].
+    ///
+    /// [`ParseState::set_profiler`]: ../parsing/struct.ParseState.html#method.set_profiler
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.parse_state.set_profiler(profiler);
+    }
+
     /// Highlights a line of a file
     pub fn highlight_line<'b>(
         &mut self,
@@ -172,6 +196,216 @@ impl<'a> HighlightFile<'a> {
     }
 }
 
+/// A text that is kept highlighted while it is edited
+///
+/// The [`ParseState`] and [`HighlightState`] at the start of every line are cached, so an edit only
+/// re-highlights lines from the first edited line until the states are the same as before the edit.
+/// [`edit`] returns the range of lines that were re-highlighted, which are the ones you need to
+/// redraw.
+///
+/// The text is split into lines after every `\n`, so the last line is empty if the text ends with
+/// a newline. Lines are highlighted including their newline, so the syntaxes should be loaded in
+/// newlines mode, like with [`SyntaxSet::load_defaults_newlines`].
+///
+/// [`ParseState`]: ../parsing/struct.ParseState.html
+/// [`HighlightState`]: ../highlighting/struct.HighlightState.html
+/// [`edit`]: #method.edit
+/// [`SyntaxSet::load_defaults_newlines`]: ../parsing/struct.SyntaxSet.html#method.load_defaults_newlines
+///
+/// # Examples
+///
+/// ```
+/// use syntect::easy::Document;
+/// use syntect::parsing::SyntaxSet;
+/// use syntect::highlighting::ThemeSet;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+///
+/// let text = "fn main() {\n    let x = 1;\n}\n";
+/// let mut doc = Document::new(text, syntax, &ts.themes["base16-ocean.dark"], &ss).unwrap();
+///
+/// // Starting a string changes the highlighting of all following lines
+/// let changed = doc.edit(16..16, "\"", &ss).unwrap();
+/// assert_eq!(changed, 1..4);
+/// assert_eq!(doc.line(1), Some("    \"let x = 1;\n"));
+/// ```
+pub struct Document<'a> {
+    highlighter: Highlighter<'a>,
+    text: String,
+    /// Byte index of the start of each line
+    line_starts: Vec<usize>,
+    /// The states at the start of each line
+    states: Vec<(ParseState, HighlightState)>,
+    /// The styled ranges of each line, relative to its start
+    styles: Vec<Vec<(Style, Range<usize>)>>,
+    /// The first line that couldn't be parsed, the states of the lines after it are outdated
+    stale_from: Option<usize>,
+}
+
+impl<'a> Document<'a> {
+    /// Highlights the whole text
+    pub fn new(
+        text: &str,
+        syntax: &SyntaxReference,
+        theme: &'a Theme,
+        syntax_set: &SyntaxSet,
+    ) -> Result<Document<'a>, Error> {
+        let highlighter = Highlighter::new(theme);
+        let highlight_state = HighlightState::new(&highlighter, ScopeStack::new());
+        let mut doc = Document {
+            highlighter,
+            text: String::new(),
+            line_starts: vec![0],
+            states: vec![(ParseState::new(syntax), highlight_state)],
+            styles: vec![Vec::new()],
+            stale_from: None,
+        };
+        doc.edit(0..0, text, syntax_set)?;
+        Ok(doc)
+    }
+
+    /// Replaces the text in the byte `range` with `replacement` and re-highlights the lines that
+    /// are affected by it.
+    ///
+    /// Returns the range of lines that were re-highlighted, as line indexes after the edit. Lines
+    /// outside of it keep their highlighting, apart from moving to another index if the edit
+    /// changed the number of lines.
+    ///
+    /// If a line can't be parsed, the error is returned and the following lines keep their
+    /// outdated highlighting until the next edit, which re-highlights them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds or doesn't lie on `char` boundaries, like
+    /// [`String::replace_range`].
+    pub fn edit(
+        &mut self,
+        range: Range<usize>,
+        replacement: &str,
+        syntax_set: &SyntaxSet,
+    ) -> Result<Range<usize>, Error> {
+        let first = self.line_of(range.start);
+        let last = self.line_of(range.end);
+        let removed_lines = self.text[range.clone()].matches('\n').count();
+        let added_lines = replacement.matches('\n').count();
+        self.text.replace_range(range, replacement);
+        self.line_starts.truncate(first + 1);
+        let first_start = self.line_starts[first];
+        self.line_starts.extend(
+            self.text[first_start..]
+                .match_indices('\n')
+                .map(|(i, _)| first_start + i + 1),
+        );
+
+        // The edited lines are re-highlighted anyway, only the state of the first one is kept
+        let placeholder = self.states[first].clone();
+        self.states
+            .splice(first + 1..=last, vec![placeholder; added_lines]);
+        self.styles
+            .splice(first + 1..=last, vec![Vec::new(); added_lines]);
+        let edited_end = first + added_lines + 1;
+        let (start, must_reach) = match self.stale_from.take() {
+            Some(stale) if stale <= first => (stale, edited_end),
+            Some(stale) if stale > last => {
+                let stale = stale - removed_lines + added_lines;
+                (first, edited_end.max(stale + 1))
+            }
+            _ => (first, edited_end),
+        };
+        self.highlight_from(start, must_reach, syntax_set)
+    }
+
+    /// Re-highlights lines starting at `start`, at least until `must_reach`, until the state after
+    /// a line is the cached state of the next line
+    fn highlight_from(
+        &mut self,
+        start: usize,
+        must_reach: usize,
+        syntax_set: &SyntaxSet,
+    ) -> Result<Range<usize>, Error> {
+        let mut index = start;
+        loop {
+            let (mut parse_state, mut highlight_state) = self.states[index].clone();
+            let line = &self.text[self.line_range(index)];
+            let ops = match parse_state.parse_line(line, syntax_set) {
+                Ok(ops) => ops,
+                Err(e) => {
+                    self.stale_from = Some(index);
+                    return Err(e.into());
+                }
+            };
+            self.styles[index] =
+                RangedHighlightIterator::new(&mut highlight_state, &ops, line, &self.highlighter)
+                    .map(|(style, _, range)| (style, range))
+                    .collect();
+            index += 1;
+            if index == self.line_count() {
+                return Ok(start..index);
+            }
+            let next = (parse_state, highlight_state);
+            if index >= must_reach && self.states[index] == next {
+                return Ok(start..index);
+            }
+            self.states[index] = next;
+        }
+    }
+
+    /// The index of the line containing the byte index
+    fn line_of(&self, byte: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= byte) - 1
+    }
+
+    fn line_range(&self, index: usize) -> Range<usize> {
+        let end = self
+            .line_starts
+            .get(index + 1)
+            .copied()
+            .unwrap_or(self.text.len());
+        self.line_starts[index]..end
+    }
+
+    /// The whole text
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The number of lines, which is one more than the number of newlines
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The text of a line, including its newline
+    pub fn line(&self, index: usize) -> Option<&str> {
+        if index < self.line_count() {
+            Some(&self.text[self.line_range(index)])
+        } else {
+            None
+        }
+    }
+
+    /// The highlighted pieces of a line, like [`HighlightLines::highlight_line`] returns them
+    ///
+    /// [`HighlightLines::highlight_line`]: struct.HighlightLines.html#method.highlight_line
+    pub fn highlighted_line(&self, index: usize) -> Option<Vec<(Style, &str)>> {
+        let line = self.line(index)?;
+        Some(
+            self.styles[index]
+                .iter()
+                .map(|(style, range)| (*style, &line[range.clone()]))
+                .collect(),
+        )
+    }
+
+    /// The parser and highlighter states at the start of a line
+    pub fn line_states(&self, index: usize) -> Option<(&ParseState, &HighlightState)> {
+        self.states
+            .get(index)
+            .map(|(parse_state, highlight_state)| (parse_state, highlight_state))
+    }
+}
+
 /// Iterator over the ranges of a line which a given the operation from the parser applies.
 ///
 /// Use [`ScopeRegionIterator`] to obtain directly regions (`&str`s) from the line.
@@ -305,6 +539,53 @@ mod tests {
         .unwrap();
     }
 
+    #[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
+    #[test]
+    fn can_edit_document() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let syntax = ss.find_syntax_by_extension("rs").unwrap();
+        let theme = &ts.themes["base16-ocean.dark"];
+        let highlighted = |doc: &Document<'_>| -> Vec<Vec<(Style, String)>> {
+            (0..doc.line_count())
+                .map(|i| {
+                    let line = doc.highlighted_line(i).unwrap();
+                    line.into_iter().map(|(s, t)| (s, t.to_owned())).collect()
+                })
+                .collect()
+        };
+        let fresh = |doc: &Document<'_>| {
+            highlighted(&Document::new(doc.text(), syntax, theme, &ss).unwrap())
+        };
+
+        let mut doc =
+            Document::new("fn a() {}\nfn b() {}\nfn c() {}\n", syntax, theme, &ss).unwrap();
+        assert_eq!(doc.line_count(), 4);
+        assert_eq!(doc.line(3), Some(""));
+        assert!(doc.line(4).is_none());
+
+        // Changes within a line don't affect the following lines
+        assert_eq!(doc.edit(13..14, "x", &ss).unwrap(), 1..2);
+        assert_eq!(doc.line(1), Some("fn x() {}\n"));
+        assert_eq!(highlighted(&doc), fresh(&doc));
+
+        // Opening a comment affects all following lines
+        assert_eq!(doc.edit(10..10, "/*", &ss).unwrap(), 1..4);
+        assert_eq!(highlighted(&doc), fresh(&doc));
+
+        // Closing it on a new line affects them again
+        assert_eq!(doc.edit(12..12, "*/\n", &ss).unwrap(), 1..5);
+        assert_eq!(doc.line_count(), 5);
+        assert_eq!(doc.line(2), Some("fn x() {}\n"));
+        assert_eq!(highlighted(&doc), fresh(&doc));
+
+        // Removing lines
+        assert_eq!(doc.edit(0..19, "", &ss).unwrap(), 0..1);
+        assert_eq!(doc.text(), "() {}\nfn c() {}\n");
+        assert_eq!(highlighted(&doc), fresh(&doc));
+        assert_eq!(doc.line_states(3), None);
+    }
+
     #[cfg(feature = "default-syntaxes")]
     #[test]
     fn can_find_regions() {
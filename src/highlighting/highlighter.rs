@@ -6,6 +6,8 @@
 use std::iter::Iterator;
 use std::ops::Range;
 
+use serde_derive::{Deserialize, Serialize};
+
 use super::selector::ScopeSelector;
 use super::style::{Color, FontStyle, Style, StyleModifier};
 use super::theme::{Theme, ThemeItem};
@@ -43,19 +45,25 @@ pub struct Highlighter<'a> {
 /// One reason this is exposed is that since it implements `Clone` you can actually cache these
 /// (probably along with a [`ParseState`]) and only re-start highlighting from the point of a
 /// change. You could also do something fancy like only highlight a bit past the end of a user's
-/// screen and resume highlighting when they scroll down on large files.
+/// screen and resume highlighting when they scroll down on large files. [`easy::Document`] caches
+/// both states for every line of a text that is edited.
 ///
 /// Alternatively you can save space by caching only the `path` field of this struct then re-create
 /// the `HighlightState` when needed by passing that stack as the `initial_stack` parameter to the
 /// [`new`] method. This takes less space but a small amount of time to re-create the style stack.
 ///
+/// The state can also be saved with serde, along with a [`SavedParseState`]. It doesn't refer to
+/// the syntax set, but its styles come from the theme, so it has to be used with the same theme.
+///
 /// **Note:** Caching is for advanced users who have tons of time to maximize performance or want to
 /// do so eventually. It is not recommended that you try caching the first time you implement
 /// highlighting.
 ///
 /// [`ParseState`]: ../parsing/struct.ParseState.html
+/// [`easy::Document`]: ../easy/struct.Document.html
+/// [`SavedParseState`]: ../parsing/struct.SavedParseState.html
 /// [`new`]: #method.new
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct HighlightState {
     styles: Vec<Style>,
     single_caches: Vec<ScoredStyle>,
@@ -235,7 +243,7 @@ impl<'a, 'b> Iterator for HighlightIterator<'a, 'b> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ScoredStyle {
     pub foreground: (MatchPower, Color),
     pub background: (MatchPower, Color),
@@ -290,7 +298,7 @@ impl<'a> Highlighter<'a> {
             }
         }
         // So that deeper matching selectors get checked first
-        single_selectors.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        single_selectors.sort_by_key(|a| std::cmp::Reverse(a.0.len()));
 
         Highlighter {
             theme,
@@ -12,6 +12,8 @@ use std::fmt::Write;
 
 use std::io::BufRead;
 use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
 /// Output HTML for a line of code with `<span>` elements using class names
 ///
@@ -285,8 +287,33 @@ pub fn highlighted_html_for_string(
     ss: &SyntaxSet,
     syntax: &SyntaxReference,
     theme: &Theme,
+) -> Result<String, Error> {
+    highlighted_html_for_lines(s, ss, HighlightLines::new(syntax, theme), theme)
+}
+
+/// Like [`highlighted_html_for_string`], but stops with a [`ParsingError::Cancelled`] once the
+/// `cancellation_flag` is set to `true`, for example from another thread.
+///
+/// [`highlighted_html_for_string`]: fn.highlighted_html_for_string.html
+/// [`ParsingError::Cancelled`]: ../parsing/enum.ParsingError.html#variant.Cancelled
+pub fn highlighted_html_for_string_cancellable(
+    s: &str,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    cancellation_flag: Arc<AtomicBool>,
 ) -> Result<String, Error> {
     let mut highlighter = HighlightLines::new(syntax, theme);
+    highlighter.set_cancellation_flag(cancellation_flag);
+    highlighted_html_for_lines(s, ss, highlighter, theme)
+}
+
+fn highlighted_html_for_lines(
+    s: &str,
+    ss: &SyntaxSet,
+    mut highlighter: HighlightLines<'_>,
+    theme: &Theme,
+) -> Result<String, Error> {
     let (mut output, bg) = start_highlighted_html_snippet(theme);
 
     for line in LinesWithEndings::from(s) {
@@ -617,6 +644,27 @@ mod tests {
         assert_eq!(html3, include_str!("../testdata/test4.html"));
     }
 
+    #[test]
+    fn can_cancel_highlighting() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let s = include_str!("../testdata/highlight_test.erb");
+        let syntax = ss.find_syntax_by_extension("erb").unwrap();
+        let theme = &ts.themes["base16-ocean.dark"];
+        let flag = Arc::new(AtomicBool::new(false));
+
+        let html = highlighted_html_for_string_cancellable(s, &ss, syntax, theme, flag.clone())
+            .expect("#[cfg(test)]");
+        assert_eq!(html, include_str!("../testdata/test3.html"));
+
+        flag.store(true, std::sync::atomic::Ordering::Relaxed);
+        let result = highlighted_html_for_string_cancellable(s, &ss, syntax, theme, flag);
+        assert!(matches!(
+            result,
+            Err(Error::ParsingError(crate::parsing::ParsingError::Cancelled))
+        ));
+    }
+
     #[test]
     fn tricky_test_syntax() {
         // This syntax I wrote tests edge cases of prototypes
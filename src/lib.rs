@@ -79,6 +79,10 @@ pub enum LoadingError {
     #[cfg(all(feature = "yaml-load", feature = "parsing"))]
     #[error("{1}: {0}")]
     ParseSyntax(#[source] crate::parsing::ParseSyntaxError, String),
+    /// a syntax file was invalid at a known place in it, with the path of the file
+    #[cfg(all(feature = "yaml-load", feature = "parsing"))]
+    #[error("{1}: {0}")]
+    LocatedParseSyntax(#[source] crate::parsing::LocatedSyntaxError, String),
     /// a metadata file was invalid in some way
     #[cfg(feature = "metadata")]
     #[error("Failed to parse JSON")]
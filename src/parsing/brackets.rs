@@ -0,0 +1,336 @@
+use super::scope_tree::ScopeTree;
+use crate::highlighting::ScopeSelectors;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// What a [`BracketPair`] consists of
+///
+/// [`BracketPair`]: struct.BracketPair.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PairKind {
+    /// One of the brackets of a [`BracketMatcher`]
+    ///
+    /// [`BracketMatcher`]: struct.BracketMatcher.html
+    Bracket,
+    /// An HTML or XML start tag and its end tag
+    Tag,
+}
+
+/// A bracket and the bracket that closes it, or a start tag and its end tag
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BracketPair {
+    /// The byte range of the opening bracket or start tag
+    pub open: Range<usize>,
+    /// The byte range of the closing bracket or end tag
+    pub close: Range<usize>,
+    /// Whether the pair is brackets or tags
+    pub kind: PairKind,
+}
+
+/// A bracket or end tag without a matching bracket or start tag
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum BracketDiagnostic {
+    /// An opening bracket that is never closed, with its byte range
+    Unclosed(Range<usize>),
+    /// A closing bracket or end tag without an opening bracket or start tag, with its byte range
+    Unopened(Range<usize>),
+}
+
+/// Finds the pairs of brackets and tags in a document, using its [`ScopeTree`] to tell which
+/// brackets are code
+///
+/// Brackets are matched up by nesting, leaving out the ones in scopes like strings and comments.
+/// Tags are found by their scopes and matched up by name. Since HTML allows leaving out end tags,
+/// start tags without an end tag are not reported as unbalanced, unlike end tags without a start
+/// tag.
+///
+/// [`ScopeTree`]: struct.ScopeTree.html
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{BracketMatcher, ScopeTreeBuilder, SyntaxSet};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let text = "f(\")\", [1]);\n";
+/// let mut builder = ScopeTreeBuilder::new(ss.find_syntax_by_extension("rs").unwrap());
+/// builder.parse_line(text, &ss).unwrap();
+/// let matches = BracketMatcher::default().find(&builder.build(), text);
+///
+/// // The `)` in the string is skipped
+/// assert_eq!(matches.matching(1).unwrap().close, 10..11);
+/// assert_eq!(matches.enclosing(9).unwrap().open, 7..8);
+/// assert!(matches.diagnostics().is_empty());
+/// ```
+#[derive(Debug, Clone)]
+pub struct BracketMatcher {
+    /// The opening and closing characters of each kind of bracket
+    pub brackets: Vec<(char, char)>,
+    /// Scopes in which brackets are not matched
+    pub ignored: ScopeSelectors,
+    /// The scopes of whole tags, including the `<` and `>`
+    pub tags: ScopeSelectors,
+}
+
+impl Default for BracketMatcher {
+    fn default() -> BracketMatcher {
+        BracketMatcher {
+            brackets: vec![('(', ')'), ('[', ']'), ('{', '}')],
+            ignored: ScopeSelectors::from_str("string, comment").unwrap(),
+            tags: ScopeSelectors::from_str("meta.tag").unwrap(),
+        }
+    }
+}
+
+impl BracketMatcher {
+    /// Finds the pairs and unbalanced brackets of a document with the scope tree built from its
+    /// text
+    pub fn find(&self, tree: &ScopeTree, text: &str) -> BracketMatches {
+        let mut pairs = Vec::new();
+        let mut diagnostics = Vec::new();
+
+        // The opening brackets with the index of their kind
+        let mut open: Vec<(usize, usize)> = Vec::new();
+        for (offset, c) in text.char_indices() {
+            let (kind, is_open) = match self.brackets.iter().position(|b| b.0 == c) {
+                Some(kind) => (kind, true),
+                None => match self.brackets.iter().position(|b| b.1 == c) {
+                    Some(kind) => (kind, false),
+                    None => continue,
+                },
+            };
+            if self.is_ignored(tree, offset) {
+                continue;
+            }
+            let range = offset..offset + c.len_utf8();
+            if is_open {
+                open.push((kind, offset));
+                continue;
+            }
+            // Brackets that are closed by an outer bracket were never closed themselves
+            match open.iter().rposition(|&(open_kind, _)| open_kind == kind) {
+                Some(index) => {
+                    for (open_kind, start) in open.drain(index + 1..) {
+                        let len = self.brackets[open_kind].0.len_utf8();
+                        diagnostics.push(BracketDiagnostic::Unclosed(start..start + len));
+                    }
+                    let (_, start) = open.pop().unwrap();
+                    pairs.push(BracketPair {
+                        open: start..start + self.brackets[kind].0.len_utf8(),
+                        close: range,
+                        kind: PairKind::Bracket,
+                    });
+                }
+                None => diagnostics.push(BracketDiagnostic::Unopened(range)),
+            }
+        }
+        for (kind, start) in open {
+            let len = self.brackets[kind].0.len_utf8();
+            diagnostics.push(BracketDiagnostic::Unclosed(start..start + len));
+        }
+
+        self.tag_pairs(tree, text, &mut pairs, &mut diagnostics);
+        pairs.sort_by_key(|pair| pair.open.start);
+        diagnostics.sort_by_key(|diagnostic| match diagnostic {
+            BracketDiagnostic::Unclosed(range) | BracketDiagnostic::Unopened(range) => range.start,
+        });
+        let mut by_close: Vec<usize> = (0..pairs.len()).collect();
+        by_close.sort_by_key(|&index| pairs[index].close.start);
+        BracketMatches {
+            pairs,
+            by_close,
+            diagnostics,
+        }
+    }
+
+    fn is_ignored(&self, tree: &ScopeTree, offset: usize) -> bool {
+        let scopes: Vec<_> = tree
+            .path_at(offset)
+            .into_iter()
+            .map(|index| tree.nodes()[index].scope)
+            .collect();
+        self.ignored.does_match(&scopes).is_some()
+    }
+
+    fn tag_pairs(
+        &self,
+        tree: &ScopeTree,
+        text: &str,
+        pairs: &mut Vec<BracketPair>,
+        diagnostics: &mut Vec<BracketDiagnostic>,
+    ) {
+        let mut open: Vec<(&str, Range<usize>)> = Vec::new();
+        let mut last_end = 0;
+        for index in tree.nodes_matching_any(&self.tags) {
+            let range = tree.nodes()[index].range.clone();
+            // Tags nested in a tag, if any, are part of it
+            if range.start < last_end {
+                continue;
+            }
+            last_end = range.end;
+            let tag = &text[range.clone()];
+            if let Some(rest) = tag.strip_prefix("</") {
+                let name = tag_name(rest);
+                // Tags that are closed by an outer tag had their end tag left out
+                match open.iter().rposition(|(open_name, _)| *open_name == name) {
+                    Some(index) => {
+                        open.truncate(index + 1);
+                        let (_, start_tag) = open.pop().unwrap();
+                        pairs.push(BracketPair {
+                            open: start_tag,
+                            close: range,
+                            kind: PairKind::Tag,
+                        });
+                    }
+                    None if !name.is_empty() => {
+                        diagnostics.push(BracketDiagnostic::Unopened(range));
+                    }
+                    None => {}
+                }
+            } else if let Some(rest) = tag.strip_prefix('<') {
+                let name = tag_name(rest);
+                if !name.is_empty() && !tag.trim_end().ends_with("/>") {
+                    open.push((name, range));
+                }
+            }
+        }
+    }
+}
+
+/// The name at the start of a tag after the `<` or `</`
+fn tag_name(tag: &str) -> &str {
+    let end = tag
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .unwrap_or(tag.len());
+    let name = &tag[..end];
+    // Things like `<!DOCTYPE` and `<?xml` aren't tags
+    if name.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == ':') {
+        name
+    } else {
+        ""
+    }
+}
+
+/// The pairs of brackets and tags in a document, see [`BracketMatcher`]
+///
+/// [`BracketMatcher`]: struct.BracketMatcher.html
+#[derive(Debug, Clone, Default)]
+pub struct BracketMatches {
+    /// Ordered by the start of the opening bracket
+    pairs: Vec<BracketPair>,
+    /// The indexes of the pairs ordered by the start of the closing bracket
+    by_close: Vec<usize>,
+    diagnostics: Vec<BracketDiagnostic>,
+}
+
+impl BracketMatches {
+    /// All pairs, ordered by where they start
+    pub fn pairs(&self) -> &[BracketPair] {
+        &self.pairs
+    }
+
+    /// The unbalanced brackets, ordered by where they are
+    pub fn diagnostics(&self) -> &[BracketDiagnostic] {
+        &self.diagnostics
+    }
+
+    /// Returns the pair with a bracket or tag at the byte offset, so that the other one is the
+    /// matching bracket or tag
+    pub fn matching(&self, offset: usize) -> Option<&BracketPair> {
+        let index = self.pairs.partition_point(|pair| pair.open.start <= offset);
+        if let Some(pair) = index.checked_sub(1).map(|i| &self.pairs[i]) {
+            if pair.open.contains(&offset) {
+                return Some(pair);
+            }
+        }
+        let index = self
+            .by_close
+            .partition_point(|&i| self.pairs[i].close.start <= offset);
+        let pair = &self.pairs[self.by_close[index.checked_sub(1)?]];
+        if pair.close.contains(&offset) {
+            Some(pair)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the innermost pair that encloses the byte offset, including the brackets or tags
+    /// themselves
+    pub fn enclosing(&self, offset: usize) -> Option<&BracketPair> {
+        let index = self.pairs.partition_point(|pair| pair.open.start <= offset);
+        self.pairs[..index]
+            .iter()
+            .rev()
+            .find(|pair| offset < pair.close.end)
+    }
+}
+
+#[cfg(feature = "yaml-load")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::scope_tree::tests::{syntax_set, tree};
+
+    fn find(text: &str) -> BracketMatches {
+        let ss = syntax_set(&[r#"
+                name: Test
+                scope: text.test
+                contexts:
+                  main:
+                    - match: '"[^"]*"'
+                      scope: string.quoted
+                    - match: </?\w+[^>]*>
+                      scope: meta.tag
+                "#]);
+        BracketMatcher::default().find(&tree(text, &ss.syntaxes()[0], &ss), text)
+    }
+
+    #[test]
+    fn can_match_brackets() {
+        let matches = find("a(b[\"]\"]\n)c]{");
+        let pairs: Vec<_> = matches
+            .pairs()
+            .iter()
+            .map(|pair| (pair.open.start, pair.close.start))
+            .collect();
+        assert_eq!(pairs, [(1, 9), (3, 7)]);
+        assert_eq!(matches.matching(3).unwrap().close, 7..8);
+        assert_eq!(matches.matching(9).unwrap().open, 1..2);
+        assert_eq!(matches.matching(2), None);
+        assert_eq!(matches.enclosing(5).unwrap().open, 3..4);
+        assert_eq!(matches.enclosing(8).unwrap().open, 1..2);
+        assert_eq!(matches.enclosing(11), None);
+        assert_eq!(
+            matches.diagnostics(),
+            [
+                BracketDiagnostic::Unopened(11..12),
+                BracketDiagnostic::Unclosed(12..13),
+            ]
+        );
+
+        // Brackets closed by an outer bracket are unclosed
+        let matches = find("([)");
+        assert_eq!(matches.pairs()[0].close, 2..3);
+        assert_eq!(matches.diagnostics(), [BracketDiagnostic::Unclosed(1..2)]);
+    }
+
+    #[test]
+    fn can_match_tags() {
+        let text = "<ul>\n  <li>one\n  <li>two <br/></li>\n</ul>\n</p>";
+        let matches = find(text);
+        let pairs: Vec<_> = matches
+            .pairs()
+            .iter()
+            .map(|pair| (&text[pair.open.clone()], pair.close.start, pair.kind))
+            .collect();
+        assert_eq!(
+            pairs,
+            [("<ul>", 36, PairKind::Tag), ("<li>", 30, PairKind::Tag)]
+        );
+        assert_eq!(matches.pairs()[1].open, 17..21);
+        assert_eq!(matches.matching(38).unwrap().open, 0..4);
+        assert_eq!(matches.diagnostics(), [BracketDiagnostic::Unopened(42..46)]);
+    }
+}
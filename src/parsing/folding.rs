@@ -0,0 +1,431 @@
+#[cfg(feature = "metadata")]
+use super::metadata::Metadata;
+use super::regex::Regex;
+#[cfg(feature = "metadata")]
+use super::scope::Scope;
+use super::scope_tree::ScopeTree;
+use crate::highlighting::ScopeSelectors;
+use std::cmp::Reverse;
+use std::str::FromStr;
+
+/// What a [`FoldRange`] folds
+///
+/// [`FoldRange`]: struct.FoldRange.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FoldKind {
+    /// A block of code, like the body of a function
+    Block,
+    /// A comment spanning several lines, or consecutive line comments
+    Comment,
+    /// The lines between markers like `// #region` and `// #endregion`
+    Region,
+    /// Consecutive import statements
+    Imports,
+}
+
+/// A range of lines that can be folded, with zero-based line numbers
+///
+/// The start line stays visible when the range is folded, like the line with the `{` of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FoldRange {
+    /// The first line of the range, which stays visible when it is folded
+    pub start_line: usize,
+    /// The last line of the range, like the line with the `}` of a block
+    pub end_line: usize,
+    /// What the range folds
+    pub kind: FoldKind,
+}
+
+/// Decides which parts of a document can be folded, based on the scopes of a [`ScopeTree`]
+///
+/// The defaults work for the usual scope naming conventions of syntaxes, and
+/// [`from_metadata`] uses the `foldScopes` and `indentationFoldingEnabled` settings of
+/// `.tmPreferences` files.
+///
+/// [`ScopeTree`]: struct.ScopeTree.html
+/// [`from_metadata`]: #method.from_metadata
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{FoldKind, FoldingRules, ScopeTreeBuilder, SyntaxSet};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let text = "import os\nimport sys\n\n# one\n# two\ndef main():\n    run()\n";
+/// let mut builder = ScopeTreeBuilder::new(ss.find_syntax_by_extension("py").unwrap());
+/// for line in text.split_inclusive('\n') {
+///     builder.parse_line(line, &ss).unwrap();
+/// }
+/// let folds = FoldingRules::default().fold_ranges(&builder.build(), text);
+///
+/// let folds: Vec<_> = folds.iter().map(|f| (f.start_line, f.end_line, f.kind)).collect();
+/// assert_eq!(
+///     folds,
+///     [
+///         (0, 1, FoldKind::Imports),
+///         (3, 4, FoldKind::Comment),
+///         // Python has no scopes for blocks, so these are folded by indentation
+///         (5, 6, FoldKind::Block),
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct FoldingRules {
+    /// Pairs of selectors for the tokens that start and end a block, which are matched up by
+    /// nesting like brackets
+    pub block_delimiters: Vec<(ScopeSelectors, ScopeSelectors)>,
+    /// Scopes that are folded as a block when they span several lines
+    pub blocks: ScopeSelectors,
+    /// Scopes of comments, which are folded when they span several lines or are on consecutive
+    /// lines
+    pub comments: ScopeSelectors,
+    /// Scopes of import statements, which are folded when they are on consecutive lines
+    pub imports: ScopeSelectors,
+    /// Matches the line that starts a region
+    pub region_start: Regex,
+    /// Matches the line that ends a region
+    pub region_end: Regex,
+    /// Whether to fold lines by indentation: a line folds the lines after it that are indented
+    /// more. This is also done for documents without any scopes of blocks or block delimiters.
+    pub indentation: bool,
+    /// The width of a tab when comparing the indentation of lines, with tabs going to the next
+    /// tab stop
+    pub tab_size: usize,
+}
+
+impl Default for FoldingRules {
+    fn default() -> FoldingRules {
+        let sections = [
+            "block", "braces", "brackets", "group", "parens", "sequence", "mapping",
+        ];
+        let block_delimiters = sections
+            .iter()
+            .map(|section| {
+                let begin = format!("punctuation.section.{}.begin", section);
+                let end = format!("punctuation.section.{}.end", section);
+                (selectors(&begin), selectors(&end))
+            })
+            .collect();
+        let marker = r"^\s*(?://|#|--|;|/\*|<!--)\s*#?(?:pragma\s+)?";
+        FoldingRules {
+            block_delimiters,
+            blocks: selectors("meta.block"),
+            comments: selectors("comment"),
+            imports: selectors(
+                "meta.import, meta.use, meta.statement.import, meta.preprocessor.include",
+            ),
+            region_start: Regex::new(format!(r"{}region\b", marker)),
+            region_end: Regex::new(format!(r"{}endregion\b", marker)),
+            indentation: false,
+            tab_size: 4,
+        }
+    }
+}
+
+fn selectors(s: &str) -> ScopeSelectors {
+    ScopeSelectors::from_str(s).expect("default selectors are valid")
+}
+
+impl FoldingRules {
+    /// Creates rules from the metadata that matches the scopes, usually the scope of a syntax.
+    ///
+    /// The `foldScopes` replace the default block delimiters, invalid selectors in them are
+    /// skipped.
+    #[cfg(feature = "metadata")]
+    pub fn from_metadata(metadata: &Metadata, scope: &[Scope]) -> FoldingRules {
+        let mut rules = FoldingRules::default();
+        let scoped = metadata.metadata_for_scope(scope);
+        if let Some(fold_scopes) = scoped.fold_scopes() {
+            rules.block_delimiters = fold_scopes
+                .iter()
+                .filter_map(|fold_scope| {
+                    let begin = ScopeSelectors::from_str(&fold_scope.begin).ok()?;
+                    let end = ScopeSelectors::from_str(&fold_scope.end).ok()?;
+                    Some((begin, end))
+                })
+                .collect();
+        }
+        if let Some(indentation) = scoped.indentation_folding_enabled() {
+            rules.indentation = indentation;
+        }
+        rules
+    }
+
+    /// Returns the fold ranges of a document with the scope tree built from its text.
+    ///
+    /// The ranges are ordered by their start line, with the longest first for the same start
+    /// line. Ranges with the same lines are only returned once, and ranges within a single line
+    /// are left out.
+    pub fn fold_ranges(&self, tree: &ScopeTree, text: &str) -> Vec<FoldRange> {
+        let lines = Lines::new(text);
+        let mut folds = Vec::new();
+        let mut push = |start_line: usize, end_line: usize, kind: FoldKind| {
+            if end_line > start_line {
+                folds.push(FoldRange {
+                    start_line,
+                    end_line,
+                    kind,
+                });
+            }
+        };
+
+        let mut region_starts = Vec::new();
+        let mut region_lines = Vec::new();
+        for (index, line) in text.split_inclusive('\n').enumerate() {
+            if self.region_start.is_match(line) {
+                region_starts.push(index);
+                region_lines.push(index);
+            } else if self.region_end.is_match(line) {
+                if let Some(start) = region_starts.pop() {
+                    push(start, index, FoldKind::Region);
+                }
+                region_lines.push(index);
+            }
+        }
+
+        // Comments spanning lines, and runs of comments that take up whole lines
+        let mut run: Option<(usize, usize)> = None;
+        for index in tree.nodes_matching_any(&self.comments) {
+            let range = &tree.nodes()[index].range;
+            let (start_line, end_line) = lines.span(range.start, range.end);
+            let whole_line = text[lines.start(start_line)..range.start].trim().is_empty()
+                && region_lines.binary_search(&start_line).is_err();
+            match run {
+                Some((run_start, run_end)) if whole_line && start_line == run_end + 1 => {
+                    run = Some((run_start, end_line));
+                    continue;
+                }
+                Some((run_start, run_end)) => push(run_start, run_end, FoldKind::Comment),
+                None => {}
+            }
+            if whole_line {
+                run = Some((start_line, end_line));
+            } else {
+                run = None;
+                push(start_line, end_line, FoldKind::Comment);
+            }
+        }
+        if let Some((run_start, run_end)) = run {
+            push(run_start, run_end, FoldKind::Comment);
+        }
+
+        let mut run: Option<(usize, usize)> = None;
+        for index in tree.nodes_matching_any(&self.imports) {
+            let range = &tree.nodes()[index].range;
+            let (start_line, end_line) = lines.span(range.start, range.end);
+            run = match run {
+                Some((run_start, run_end)) if start_line <= run_end + 1 => {
+                    Some((run_start, run_end.max(end_line)))
+                }
+                Some((run_start, run_end)) => {
+                    push(run_start, run_end, FoldKind::Imports);
+                    Some((start_line, end_line))
+                }
+                None => Some((start_line, end_line)),
+            };
+        }
+        if let Some((run_start, run_end)) = run {
+            push(run_start, run_end, FoldKind::Imports);
+        }
+
+        let mut has_blocks = false;
+        for (begin, end) in &self.block_delimiters {
+            let mut delimiters: Vec<(usize, bool)> = tree
+                .nodes_matching_any(begin)
+                .into_iter()
+                .map(|index| (index, true))
+                .chain(tree.nodes_matching_any(end).into_iter().map(|i| (i, false)))
+                .collect();
+            // The nodes are in the order they start in
+            delimiters.sort_unstable();
+            has_blocks |= !delimiters.is_empty();
+            let mut open = Vec::new();
+            for (index, is_begin) in delimiters {
+                let offset = tree.nodes()[index].range.start;
+                if is_begin {
+                    open.push(offset);
+                } else if let Some(start) = open.pop() {
+                    push(lines.line(start), lines.line(offset), FoldKind::Block);
+                }
+            }
+        }
+        for index in tree.nodes_matching_any(&self.blocks) {
+            let range = &tree.nodes()[index].range;
+            let (start_line, end_line) = lines.span(range.start, range.end);
+            push(start_line, end_line, FoldKind::Block);
+            has_blocks = true;
+        }
+
+        if self.indentation || !has_blocks {
+            indentation_folds(text, self.tab_size, &mut push);
+        }
+
+        // Sorting is stable, so the first kind pushed for the same lines is kept
+        folds.sort_by_key(|fold| (fold.start_line, Reverse(fold.end_line)));
+        folds.dedup_by_key(|fold| (fold.start_line, fold.end_line));
+        folds
+    }
+}
+
+/// Folds each line with the following lines that are indented more, ignoring blank lines
+fn indentation_folds(text: &str, tab_size: usize, push: &mut impl FnMut(usize, usize, FoldKind)) {
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    let mut last_line = 0;
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let indent = indent_width(line, tab_size);
+        while let Some(&(open_indent, open_line)) = open.last() {
+            if indent > open_indent {
+                break;
+            }
+            open.pop();
+            push(open_line, last_line, FoldKind::Block);
+        }
+        open.push((indent, index));
+        last_line = index;
+    }
+    for (_, open_line) in open {
+        push(open_line, last_line, FoldKind::Block);
+    }
+}
+
+/// Returns the width of the indentation of a line, with tabs going to the next tab stop
+pub(crate) fn indent_width(line: &str, tab_size: usize) -> usize {
+    let tab_size = tab_size.max(1);
+    let mut width = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width += tab_size - width % tab_size,
+            _ => break,
+        }
+    }
+    width
+}
+
+/// The byte offsets where the lines of a text start
+pub(crate) struct Lines {
+    starts: Vec<usize>,
+}
+
+impl Lines {
+    pub(crate) fn new(text: &str) -> Lines {
+        let mut starts = vec![0];
+        starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        Lines { starts }
+    }
+
+    pub(crate) fn start(&self, line: usize) -> usize {
+        self.starts[line]
+    }
+
+    /// The line containing the byte offset
+    pub(crate) fn line(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// The first and last line of a byte range
+    pub(crate) fn span(&self, start: usize, end: usize) -> (usize, usize) {
+        let start_line = self.line(start);
+        (start_line, self.line(end.saturating_sub(1)).max(start_line))
+    }
+}
+
+#[cfg(feature = "yaml-load")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::scope_tree::tests::{syntax_set, tree};
+
+    fn folds(text: &str, syntax_name: &str, rules: &FoldingRules) -> Vec<(usize, usize, FoldKind)> {
+        let ss = syntax_set(&[
+            r#"
+                name: Test
+                scope: source.test
+                file_extensions: [test]
+                contexts:
+                  main:
+                    - match: ^import\b.*\n
+                      scope: meta.import
+                    - match: //.*\n
+                      scope: comment.line
+                    - match: \{
+                      scope: punctuation.section.block.begin
+                    - match: \}
+                      scope: punctuation.section.block.end
+                "#,
+            "name: Plain\nscope: text.plain\ncontexts: {main: []}",
+        ]);
+        let tree = tree(text, ss.find_syntax_by_name(syntax_name).unwrap(), &ss);
+        rules
+            .fold_ranges(&tree, text)
+            .iter()
+            .map(|fold| (fold.start_line, fold.end_line, fold.kind))
+            .collect()
+    }
+
+    #[test]
+    fn can_find_fold_ranges() {
+        let text = "\
+import a
+import b
+// one
+// two
+f {
+  g { }
+  x // not folded with the next line
+  // three
+  // #region setup
+  {
+  }
+  // #endregion
+}
+";
+        assert_eq!(
+            folds(text, "Test", &FoldingRules::default()),
+            [
+                (0, 1, FoldKind::Imports),
+                (2, 3, FoldKind::Comment),
+                (4, 12, FoldKind::Block),
+                (8, 11, FoldKind::Region),
+                (9, 10, FoldKind::Block),
+            ]
+        );
+    }
+
+    #[test]
+    fn can_fold_by_indentation() {
+        let text = "a\n  b\n\n    c\n  d\ne\n  f\n";
+        let expected = [
+            (0, 4, FoldKind::Block),
+            (1, 3, FoldKind::Block),
+            (5, 6, FoldKind::Block),
+        ];
+        assert_eq!(folds(text, "Plain", &FoldingRules::default()), expected);
+
+        // Only if there are no blocks, unless enabled
+        let text = "{\n}\nx\n  y\n";
+        assert_eq!(
+            folds(text, "Test", &FoldingRules::default()),
+            [(0, 1, FoldKind::Block)]
+        );
+        let rules = FoldingRules {
+            indentation: true,
+            ..FoldingRules::default()
+        };
+        assert_eq!(
+            folds(text, "Test", &rules),
+            [(0, 1, FoldKind::Block), (2, 3, FoldKind::Block)]
+        );
+
+        // A tab is as wide as the spaces up to the next tab stop
+        let text = "a\n\tb\n    c\n  \td\n     e\n";
+        assert_eq!(
+            folds(text, "Plain", &FoldingRules::default()),
+            [(0, 4, FoldKind::Block), (3, 4, FoldKind::Block)]
+        );
+    }
+}
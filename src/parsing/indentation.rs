@@ -0,0 +1,324 @@
+use super::folding::indent_width;
+use super::metadata::ScopedMetadata;
+use super::scope::Scope;
+use super::scope_tree::ScopeTreeBuilder;
+use super::syntax_set::{SyntaxReference, SyntaxSet};
+use crate::Error;
+
+/// How an [`Indenter`] indents lines
+///
+/// [`Indenter`]: struct.Indenter.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndentSettings {
+    /// The width of a tab and of a level of indentation
+    pub tab_size: usize,
+    /// Whether to indent with spaces instead of tabs
+    pub translate_tabs_to_spaces: bool,
+}
+
+impl Default for IndentSettings {
+    fn default() -> IndentSettings {
+        IndentSettings {
+            tab_size: 4,
+            translate_tabs_to_spaces: true,
+        }
+    }
+}
+
+impl IndentSettings {
+    /// Returns the whitespace for a level of indentation
+    pub fn indentation(&self, level: usize) -> String {
+        if self.translate_tabs_to_spaces {
+            " ".repeat(level * self.tab_size)
+        } else {
+            "\t".repeat(level)
+        }
+    }
+
+    /// Returns the level of indentation of a line, with tabs going to the next tab stop and
+    /// partial levels rounded down
+    pub fn level_of(&self, line: &str) -> usize {
+        indent_width(line, self.tab_size) / self.tab_size.max(1)
+    }
+}
+
+/// Computes the indentation of lines with the indentation patterns of `.tmPreferences` files,
+/// like TextMate and Sublime Text do
+///
+/// The indentation of a line depends on the previous line that isn't blank or matched by an
+/// `unIndentedLinePattern`:
+///
+/// - A previous line matched by an `increaseIndentPattern` indents it one more level.
+/// - A previous line matched by a `bracketIndentNextLinePattern`, like an `if` without braces,
+///   also does, unless the line itself is matched by a `disableIndentNextLinePattern`. The lines
+///   after it go back to the level before the lines that were indented this way.
+/// - A line matched by a `decreaseIndentPattern` is indented one level less.
+///
+/// The patterns of a line are the ones of the metadata for the scopes at its first character
+/// that isn't whitespace, so lines in strings and comments can have other rules.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{Indenter, IndentSettings, SyntaxSet};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+/// let indenter = Indenter::new(syntax, &ss, IndentSettings::default());
+///
+/// let text = "fn main() {\nif x {\ny();\n}\n}\n";
+/// assert_eq!(indenter.indent_level(text, 2).unwrap(), 1);
+/// assert_eq!(
+///     indenter.reindent(text).unwrap(),
+///     "fn main() {\n    if x {\n        y();\n    }\n}\n"
+/// );
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Indenter<'a> {
+    syntax: &'a SyntaxReference,
+    syntax_set: &'a SyntaxSet,
+    settings: IndentSettings,
+}
+
+/// A line with the scopes at its first character that isn't whitespace
+struct Line<'a> {
+    /// The text without the line ending
+    text: &'a str,
+    scopes: Vec<Scope>,
+}
+
+impl<'a> Indenter<'a> {
+    /// Creates an indenter for a syntax with the metadata of the syntax set
+    pub fn new(
+        syntax: &'a SyntaxReference,
+        syntax_set: &'a SyntaxSet,
+        settings: IndentSettings,
+    ) -> Indenter<'a> {
+        Indenter {
+            syntax,
+            syntax_set,
+            settings,
+        }
+    }
+
+    /// The settings the indenter indents lines with
+    pub fn settings(&self) -> IndentSettings {
+        self.settings
+    }
+
+    /// Returns the level of indentation that a line of a text should have, given the indentation
+    /// the lines before it have, like when the line was just typed.
+    ///
+    /// The line is a zero-based index, and can be the one after the last line. Lines matched by
+    /// an `unIndentedLinePattern` keep their level.
+    pub fn indent_level(&self, text: &str, line: usize) -> Result<usize, Error> {
+        let lines = self.lines(text, line + 1)?;
+        let levels: Vec<usize> = lines
+            .iter()
+            .map(|line| self.settings.level_of(line.text))
+            .collect();
+        Ok(self.level(&lines, &levels, line))
+    }
+
+    /// Indents all lines of a text again, one after the other, and returns the new text.
+    ///
+    /// Blank lines lose their whitespace, and lines matched by an `unIndentedLinePattern` are
+    /// kept as they are.
+    pub fn reindent(&self, text: &str) -> Result<String, Error> {
+        let lines = self.lines(text, text.split_inclusive('\n').count())?;
+        let mut levels = Vec::with_capacity(lines.len());
+        let mut result = String::with_capacity(text.len());
+        for (index, raw) in text.split_inclusive('\n').enumerate() {
+            let line = &lines[index];
+            let content = line.text.trim_start();
+            let ending = &raw[line.text.len()..];
+            if content.is_empty() {
+                levels.push(0);
+                result.push_str(ending);
+                continue;
+            }
+            if self.metadata(line).unindented_line(line.text) {
+                levels.push(self.settings.level_of(line.text));
+                result.push_str(raw);
+                continue;
+            }
+            let level = self.level(&lines, &levels, index);
+            levels.push(level);
+            result.push_str(&self.settings.indentation(level));
+            result.push_str(content);
+            result.push_str(ending);
+        }
+        Ok(result)
+    }
+
+    /// Parses the first lines of a text, with empty lines after the end of the text to make up
+    /// the count
+    fn lines<'t>(&self, text: &'t str, count: usize) -> Result<Vec<Line<'t>>, Error> {
+        let mut builder = ScopeTreeBuilder::new(self.syntax);
+        let mut starts = Vec::new();
+        let mut offset = 0;
+        for line in text.split_inclusive('\n').take(count) {
+            builder.parse_line(line, self.syntax_set)?;
+            starts.push((offset, line.trim_end_matches(['\n', '\r'])));
+            offset += line.len();
+        }
+        // An empty line after the text is in the scopes that are still open at its end
+        let end_scopes = builder.scope_stack().as_slice().to_vec();
+        let tree = builder.build();
+        let mut lines: Vec<Line<'t>> = starts
+            .into_iter()
+            .map(|(start, text)| {
+                let indent = text.len() - text.trim_start().len();
+                let scopes = tree
+                    .path_at(start + indent)
+                    .into_iter()
+                    .map(|index| tree.nodes()[index].scope)
+                    .collect();
+                Line { text, scopes }
+            })
+            .collect();
+        while lines.len() < count {
+            lines.push(Line {
+                text: "",
+                scopes: end_scopes.clone(),
+            });
+        }
+        Ok(lines)
+    }
+
+    fn metadata(&self, line: &Line) -> ScopedMetadata<'a> {
+        self.syntax_set.metadata().metadata_for_scope(&line.scopes)
+    }
+
+    /// The index of the line before the given one that decides its indentation
+    fn previous(&self, lines: &[Line], index: usize) -> Option<usize> {
+        (0..index).rev().find(|&i| {
+            let line = &lines[i];
+            !line.text.trim().is_empty() && !self.metadata(line).unindented_line(line.text)
+        })
+    }
+
+    /// The level of a line, with the levels of the lines before it
+    fn level(&self, lines: &[Line], levels: &[usize], index: usize) -> usize {
+        let line = &lines[index];
+        let metadata = self.metadata(line);
+        if metadata.unindented_line(line.text) {
+            return self.settings.level_of(line.text);
+        }
+        let Some(previous) = self.previous(lines, index) else {
+            return 0;
+        };
+        let previous_metadata = self.metadata(&lines[previous]);
+        let previous_text = lines[previous].text;
+        let mut level = levels[previous];
+        if previous_metadata.increase_indent(previous_text) {
+            level += 1;
+        } else if previous_metadata.bracket_increase(previous_text) {
+            if !metadata.disable_indent_next_line(line.text) {
+                level += 1;
+            }
+        } else {
+            // Go back to the level before the lines indented for only the line after them
+            let mut current = previous;
+            while let Some(before) = self.previous(lines, current) {
+                let before_metadata = self.metadata(&lines[before]);
+                let before_text = lines[before].text;
+                if before_metadata.increase_indent(before_text)
+                    || !before_metadata.bracket_increase(before_text)
+                    || levels[current] <= levels[before]
+                {
+                    break;
+                }
+                level = levels[before];
+                current = before;
+            }
+        }
+        if metadata.decrease_indent(line.text) {
+            level = level.saturating_sub(1);
+        }
+        level
+    }
+}
+
+#[cfg(feature = "yaml-load")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::scope_tree::tests;
+    use crate::parsing::{Metadata, MetadataSet};
+
+    fn syntax_set() -> SyntaxSet {
+        let mut ss = tests::syntax_set(&[r#"
+                name: Test
+                scope: source.test
+                contexts:
+                  main:
+                    - match: /\*
+                      push:
+                        - meta_scope: comment.block
+                        - match: \*/
+                          pop: true
+                "#]);
+        let set = |selector: &str, items: serde_json::Value| {
+            MetadataSet::from_raw((selector.into(), items.as_object().cloned().unwrap())).unwrap()
+        };
+        ss.set_metadata(Metadata {
+            scoped_metadata: vec![
+                set(
+                    "source.test",
+                    serde_json::json!({
+                        "increaseIndentPattern": r"^.*\{\s*$",
+                        "decreaseIndentPattern": r"^\s*\}",
+                        "bracketIndentNextLinePattern": r"^\s*if\b[^{]*$",
+                        "disableIndentNextLinePattern": r"^\s*\{\s*$",
+                        "unIndentedLinePattern": r"^\s*@",
+                    }),
+                ),
+                // Lines starting in comments keep their indentation
+                set(
+                    "source.test comment.block",
+                    serde_json::json!({ "unIndentedLinePattern": "^" }),
+                ),
+            ],
+        });
+        ss
+    }
+
+    #[test]
+    fn can_reindent() {
+        let ss = syntax_set();
+        let settings = IndentSettings {
+            tab_size: 2,
+            translate_tabs_to_spaces: true,
+        };
+        let indenter = Indenter::new(&ss.syntaxes()[0], &ss, settings);
+        let text = "fn {\nif a\nb\nif c\n{\nd\n}\n  @raw\ne\n   \n/* x {\n      y\n*/\n}\n";
+        // The line after `b` goes back to the level of the `if` before it
+        let expected =
+            "fn {\n  if a\n    b\n  if c\n  {\n    d\n  }\n  @raw\n  e\n\n/* x {\n      y\n*/\n}\n";
+        assert_eq!(indenter.reindent(text).unwrap(), expected);
+        assert_eq!(indenter.reindent(expected).unwrap(), expected);
+        // Without a line ending at the end
+        assert_eq!(indenter.reindent("fn {\nx").unwrap(), "fn {\n  x");
+
+        // The lines before keep their indentation
+        assert_eq!(indenter.indent_level(text, 2).unwrap(), 1);
+        assert_eq!(indenter.indent_level("fn {\n", 1).unwrap(), 1);
+        // The line after the text is still in the comment
+        assert_eq!(indenter.indent_level("fn {\n/* a\n", 2).unwrap(), 0);
+    }
+
+    #[test]
+    fn can_measure_indentation() {
+        let settings = IndentSettings {
+            tab_size: 4,
+            translate_tabs_to_spaces: false,
+        };
+        assert_eq!(settings.indentation(2), "\t\t");
+        assert_eq!(settings.level_of("\t  x"), 1);
+        assert_eq!(settings.level_of("  \t\tx"), 2);
+        assert_eq!(settings.level_of("       x"), 1);
+        assert_eq!(settings.level_of(""), 0);
+        assert_eq!(IndentSettings::default().indentation(1), "    ");
+    }
+}
@@ -0,0 +1,190 @@
+//! Loading of TextMate grammars in JSON format, as used by VS Code (`.tmLanguage.json` files)
+use super::syntax_definition::SyntaxDefinition;
+use super::tmlanguage_load::load_textmate_grammar;
+use super::yaml_load::{LocatedSyntaxError, ParseSyntaxError};
+use serde_json::Value;
+
+impl SyntaxDefinition {
+    /// Loads a TextMate grammar in JSON format, like the `.tmLanguage.json` files of VS Code
+    /// extensions.
+    ///
+    /// The grammar is translated the same way as by [`load_from_tmlanguage`]. References like
+    /// `source.x` and `source.x#repo` become [`ContextReference::ByScope`] references to the
+    /// `main` context or the repository entry of the grammar with that scope.
+    ///
+    /// A grammar with an `injectionSelector` is hidden and keeps the selector as its
+    /// [`injection_selector`], so that its patterns are injected into the other syntaxes of a
+    /// [`SyntaxSet`].
+    ///
+    /// See [`load_from_str`] for the other parameters.
+    ///
+    /// [`load_from_tmlanguage`]: #method.load_from_tmlanguage
+    /// [`load_from_str`]: #method.load_from_str
+    /// [`injection_selector`]: #structfield.injection_selector
+    /// [`ContextReference::ByScope`]: enum.ContextReference.html#variant.ByScope
+    /// [`SyntaxSet`]: ../struct.SyntaxSet.html
+    pub fn load_from_tmlanguage_json(
+        s: &str,
+        lines_include_newline: bool,
+        fallback_name: Option<&str>,
+    ) -> Result<SyntaxDefinition, ParseSyntaxError> {
+        let grammar: Value = serde_json::from_str(s)?;
+        load_textmate_grammar(&grammar, lines_include_newline, fallback_name, None)
+            .map_err(LocatedSyntaxError::into_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::syntax_definition::*;
+    use crate::parsing::{ParseState, Scope, ScopeStackOp, SyntaxSetBuilder};
+
+    #[test]
+    fn can_load_tmlanguage_json() {
+        let defn = SyntaxDefinition::load_from_tmlanguage_json(
+            r##"{
+                "name": "Outer",
+                "scopeName": "source.outer",
+                "patterns": [
+                    {"include": "$self"},
+                    {"include": "source.inner"},
+                    {"include": "source.inner#numbers"}
+                ]
+            }"##,
+            true,
+            None,
+        )
+        .unwrap();
+        assert!(defn.injection_selector.is_none());
+        let patterns = &defn.contexts["main"].patterns;
+        assert_eq!(
+            patterns[0],
+            Pattern::Include(ContextReference::Named("main".to_owned()))
+        );
+        assert_eq!(
+            patterns[1],
+            Pattern::Include(ContextReference::ByScope {
+                scope: Scope::new("source.inner").unwrap(),
+                sub_context: None,
+                with_escape: false,
+            })
+        );
+        assert_eq!(
+            patterns[2],
+            Pattern::Include(ContextReference::ByScope {
+                scope: Scope::new("source.inner").unwrap(),
+                sub_context: Some("numbers".to_owned()),
+                with_escape: false,
+            })
+        );
+    }
+
+    #[test]
+    fn can_inject_grammar_into_other_syntaxes() {
+        let host = SyntaxDefinition::load_from_tmlanguage_json(
+            r##"{
+                "name": "Host",
+                "scopeName": "source.host",
+                "patterns": [
+                    {"begin": "#", "end": "$", "name": "comment.host"},
+                    {"match": "\\w+", "name": "word.host"}
+                ]
+            }"##,
+            true,
+            None,
+        )
+        .unwrap();
+        let injection = SyntaxDefinition::load_from_tmlanguage_json(
+            r##"{
+                "scopeName": "todo.injection",
+                "injectionSelector": "L:source.host comment",
+                "patterns": [{"match": "TODO", "name": "todo"}]
+            }"##,
+            true,
+            Some("Todo"),
+        )
+        .unwrap();
+        assert!(injection.hidden);
+        assert_eq!(
+            injection.injection_selector.as_deref(),
+            Some("L:source.host comment")
+        );
+
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(host);
+        builder.add(injection);
+        let ss = builder.build();
+        let mut state = ParseState::new(ss.find_syntax_by_name("Host").unwrap());
+        let ops = state.parse_line("TODO # TODO\n", &ss).unwrap();
+        let todo = Scope::new("todo").unwrap();
+        let word = Scope::new("word.host").unwrap();
+        assert!(ops.contains(&(0, ScopeStackOp::Push(word))));
+        assert!(ops.contains(&(7, ScopeStackOp::Push(todo))));
+        assert!(!ops.contains(&(0, ScopeStackOp::Push(todo))));
+    }
+
+    #[test]
+    fn can_include_base_syntax() {
+        let outer = SyntaxDefinition::load_from_tmlanguage_json(
+            r##"{
+                "name": "Outer",
+                "scopeName": "source.outer",
+                "patterns": [
+                    {"include": "source.inner"},
+                    {"match": "x", "name": "keyword.outer"}
+                ]
+            }"##,
+            true,
+            None,
+        )
+        .unwrap();
+        let inner = SyntaxDefinition::load_from_tmlanguage_json(
+            r##"{
+                "name": "Inner",
+                "scopeName": "source.inner",
+                "patterns": [{"include": "#parens"}],
+                "repository": {
+                    "parens": {
+                        "begin": "\\(",
+                        "end": "\\)",
+                        "name": "paren.inner",
+                        "patterns": [{"include": "$base"}]
+                    }
+                }
+            }"##,
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            inner.contexts["main"].patterns[0],
+            Pattern::Include(ContextReference::Named("parens".to_owned()))
+        );
+
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(outer);
+        builder.add(inner);
+        let ss = builder.build();
+        let keyword = (2, ScopeStackOp::Push(Scope::new("keyword.outer").unwrap()));
+        let paren = (1, ScopeStackOp::Push(Scope::new("paren.inner").unwrap()));
+
+        // `$base` is the syntax that includes the grammar
+        let mut state = ParseState::new(ss.find_syntax_by_name("Outer").unwrap());
+        let ops = state.parse_line("((x))\n", &ss).unwrap();
+        assert!(ops.contains(&keyword));
+        assert!(ops.contains(&paren));
+
+        // and the grammar itself when it is used on its own
+        let mut state = ParseState::new(ss.find_syntax_by_name("Inner").unwrap());
+        let ops = state.parse_line("((x))\n", &ss).unwrap();
+        assert!(!ops.contains(&keyword));
+        assert!(ops.contains(&paren));
+    }
+
+    #[test]
+    fn errors_on_invalid_json() {
+        let result = SyntaxDefinition::load_from_tmlanguage_json("{", true, None);
+        assert!(matches!(result, Err(ParseSyntaxError::InvalidJson(_))));
+    }
+}
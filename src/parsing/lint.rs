@@ -0,0 +1,779 @@
+//! Static checks for syntax definitions.
+//!
+//! Loading a syntax only fails for errors that make it unusable, like invalid regexes. The
+//! functions here find problems in syntaxes that load fine, but don't behave as intended, like
+//! contexts that are never used. Each problem is reported as a [`Diagnostic`], and the ones with
+//! [`Severity::Error`] are likely to be bugs.
+//!
+//! [`Diagnostic`]: struct.Diagnostic.html
+//! [`Severity::Error`]: enum.Severity.html#variant.Error
+use super::regex::Region;
+use super::syntax_definition::*;
+use super::syntax_set::SyntaxSet;
+#[cfg(feature = "yaml-load")]
+use super::yaml_load::{load_merged_yaml, ParseSyntaxError, META_KEYS};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+#[cfg(feature = "yaml-load")]
+use yaml_rust::Yaml;
+
+/// A problem found in a syntax
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Diagnostic {
+    /// What the problem is
+    pub kind: DiagnosticKind,
+    /// The name of the syntax
+    pub syntax: String,
+    /// The name of the context, anonymous contexts have generated names like `#anon_main_0`
+    pub context: Option<String>,
+    /// The index of the pattern in the context. For the named contexts checked by
+    /// [`lint_sublime_syntax`], this is the index of the entry in the source instead, which also
+    /// counts entries like `meta_scope`. For syntaxes that use `extends`, it's the index in the
+    /// context as merged with the parents.
+    ///
+    /// [`lint_sublime_syntax`]: fn.lint_sublime_syntax.html
+    pub pattern: Option<usize>,
+    /// The line and column in the source, both starting at 1. Only known for the problems found
+    /// while loading the source with [`lint_sublime_syntax`], and not for the ones in a parent
+    /// syntax.
+    ///
+    /// [`lint_sublime_syntax`]: fn.lint_sublime_syntax.html
+    pub position: Option<(usize, usize)>,
+}
+
+impl Diagnostic {
+    /// How likely the problem is a bug, see [`DiagnosticKind::severity`]
+    ///
+    /// [`DiagnosticKind::severity`]: enum.DiagnosticKind.html#method.severity
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.syntax)?;
+        if let Some(ref context) = self.context {
+            write!(f, ", context {}", context)?;
+        }
+        if let Some(pattern) = self.pattern {
+            write!(f, ", pattern {}", pattern)?;
+        }
+        if let Some((line, column)) = self.position {
+            write!(f, ": line {}, column {}", line, column)?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+/// The kinds of problems found by the checks
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DiagnosticKind {
+    /// The context can't be reached from the `main` context of any syntax
+    UnreachableContext,
+    /// The pattern never matches, because the pattern with the given index always matches first
+    ShadowedPattern(usize),
+    /// The pattern pushes or pops contexts without consuming any text, which ends up in the same
+    /// state again. The parser has to break out of this loop by skipping the pattern.
+    NonConsumingLoop,
+    /// A regex or variable uses a variable that isn't defined
+    UndefinedVariable(String),
+    /// An `embed` without the mandatory `escape`
+    EmbedWithoutEscape,
+    /// A reference to a context that doesn't exist
+    UnresolvedReference(String),
+    /// Any other error while loading the syntax, which skips the pattern
+    #[cfg(feature = "yaml-load")]
+    InvalidSyntax(ParseSyntaxError),
+}
+
+impl DiagnosticKind {
+    /// Unreachable contexts and shadowed patterns are warnings, since they do no harm besides
+    /// being dead code. All other problems are errors.
+    pub fn severity(&self) -> Severity {
+        match *self {
+            DiagnosticKind::UnreachableContext | DiagnosticKind::ShadowedPattern(_) => {
+                Severity::Warning
+            }
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DiagnosticKind::UnreachableContext => write!(f, "Context is never used"),
+            DiagnosticKind::ShadowedPattern(index) => {
+                write!(
+                    f,
+                    "Pattern is shadowed by pattern {}, which always matches",
+                    index
+                )
+            }
+            DiagnosticKind::NonConsumingLoop => {
+                write!(f, "Pattern loops without consuming any text")
+            }
+            DiagnosticKind::UndefinedVariable(ref name) => write!(f, "Unknown variable: {}", name),
+            DiagnosticKind::EmbedWithoutEscape => write!(f, "embed without escape"),
+            DiagnosticKind::UnresolvedReference(ref reference) => {
+                write!(f, "Unresolved context reference {}", reference)
+            }
+            #[cfg(feature = "yaml-load")]
+            DiagnosticKind::InvalidSyntax(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// How serious a [`Diagnostic`] is, ordered from the least serious
+///
+/// [`Diagnostic`]: struct.Diagnostic.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Something that has no effect, like dead code
+    Warning,
+    /// Something that likely doesn't work as intended
+    Error,
+}
+
+/// Checks all the syntaxes of a set, including the references between them.
+///
+/// Only the patterns of the contexts themselves are considered, not the ones added by the
+/// `prototype` or `with_prototype`.
+pub fn lint_syntax_set(syntax_set: &SyntaxSet) -> Vec<Diagnostic> {
+    let syntaxes = syntax_set
+        .syntaxes()
+        .iter()
+        .map(|syntax| {
+            let mut contexts: Vec<(&str, &Context)> = Vec::new();
+            let all_contexts = syntax.contexts();
+            let mut names = vec![""; all_contexts.len()];
+            for (name, id) in syntax.context_ids() {
+                names[id.context_index] = name;
+            }
+            contexts.extend(names.into_iter().zip(all_contexts));
+            (syntax.name.as_str(), contexts)
+        })
+        .collect();
+    Linter::new(syntaxes).lint()
+}
+
+/// Checks a single syntax. References to other syntaxes aren't checked.
+///
+/// Since the variables in regexes are already substituted when loading, only the variables used
+/// by other variables are checked. Use [`lint_sublime_syntax`] to check the source of a syntax.
+///
+/// [`lint_sublime_syntax`]: fn.lint_sublime_syntax.html
+pub fn lint_syntax_definition(syntax: &SyntaxDefinition) -> Vec<Diagnostic> {
+    let mut contexts: Vec<(&str, &Context)> = syntax
+        .contexts
+        .iter()
+        .map(|(name, context)| (name.as_str(), context))
+        .collect();
+    contexts.sort_by_key(|&(name, _)| name);
+    let mut diagnostics = Linter::new(vec![(syntax.name.as_str(), contexts)]).lint();
+
+    let mut names: Vec<&String> = syntax.variables.keys().collect();
+    names.sort();
+    let mut reported = HashSet::new();
+    for name in names {
+        for used in variable_uses(&syntax.variables[name]) {
+            if !syntax.variables.contains_key(used) && reported.insert(used) {
+                diagnostics.push(Diagnostic {
+                    kind: DiagnosticKind::UndefinedVariable(used.to_owned()),
+                    syntax: syntax.name.clone(),
+                    context: None,
+                    pattern: None,
+                    position: None,
+                });
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Loads the source of a `.sublime-syntax` file leniently and checks it.
+///
+/// Besides the diagnostics of [`lint_syntax_definition`], this reports the patterns that can't be
+/// loaded, like an `embed` without `escape`, and variables that aren't defined. If the syntax
+/// can't be loaded at all, the error is the only diagnostic.
+///
+/// The parents of syntaxes that use `extends` are found with `resolve_parent`, like for
+/// [`SyntaxDefinition::load_from_str_with_parents`]. The contexts are checked after merging them
+/// with the ones of the parents, so the patterns of the parents are reported too.
+///
+/// [`lint_syntax_definition`]: fn.lint_syntax_definition.html
+/// [`SyntaxDefinition::load_from_str_with_parents`]: ../struct.SyntaxDefinition.html#method.load_from_str_with_parents
+#[cfg(feature = "yaml-load")]
+pub fn lint_sublime_syntax<F>(
+    source: &str,
+    lines_include_newline: bool,
+    mut resolve_parent: F,
+) -> Vec<Diagnostic>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let (syntax, errors) = match SyntaxDefinition::load_from_str_lenient(
+        source,
+        lines_include_newline,
+        None,
+        &mut resolve_parent,
+    ) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            let location = e.location().clone();
+            return vec![Diagnostic {
+                kind: DiagnosticKind::InvalidSyntax(e.into_error()),
+                syntax: String::new(),
+                context: location.context,
+                pattern: location.pattern,
+                position: location.position.filter(|_| location.file.is_none()),
+            }];
+        }
+    };
+
+    let mut diagnostics: Vec<Diagnostic> = errors
+        .into_iter()
+        .map(|e| {
+            let location = e.location().clone();
+            let kind = match e.into_error() {
+                ParseSyntaxError::UnknownVariable(name) => DiagnosticKind::UndefinedVariable(name),
+                // `escape` is only mandatory for `embed`
+                ParseSyntaxError::MissingMandatoryKey("escape") => {
+                    DiagnosticKind::EmbedWithoutEscape
+                }
+                error => DiagnosticKind::InvalidSyntax(error),
+            };
+            Diagnostic {
+                kind,
+                syntax: syntax.name.clone(),
+                context: location.context,
+                pattern: location.pattern,
+                position: location.position.filter(|_| location.file.is_none()),
+            }
+        })
+        .collect();
+    let entries = match load_merged_yaml(source, &mut resolve_parent) {
+        Ok(doc) => source_entries(&doc, &diagnostics),
+        Err(_) => HashMap::new(),
+    };
+    let entry = |context: &Option<String>, index: usize| {
+        context
+            .as_ref()
+            .and_then(|name| entries.get(name.as_str()))
+            .and_then(|indexes| indexes.get(index).copied())
+            .unwrap_or(index)
+    };
+    for mut diagnostic in lint_syntax_definition(&syntax) {
+        diagnostic.pattern = diagnostic.pattern.map(|i| entry(&diagnostic.context, i));
+        if let DiagnosticKind::ShadowedPattern(ref mut i) = diagnostic.kind {
+            *i = entry(&diagnostic.context, *i);
+        }
+        let reported = diagnostics
+            .iter()
+            .any(|d| match (&d.kind, &diagnostic.kind) {
+                (DiagnosticKind::UndefinedVariable(a), DiagnosticKind::UndefinedVariable(b)) => {
+                    a == b
+                }
+                _ => false,
+            });
+        if !reported {
+            diagnostics.push(diagnostic);
+        }
+    }
+    diagnostics
+}
+
+/// For each named context of the source, the indexes of the entries that were loaded as patterns.
+/// Entries that set the meta keys aren't patterns, and entries that can't be loaded are skipped.
+#[cfg(feature = "yaml-load")]
+fn source_entries(doc: &Yaml, diagnostics: &[Diagnostic]) -> HashMap<String, Vec<usize>> {
+    let contexts = match doc["contexts"] {
+        Yaml::Hash(ref contexts) => contexts,
+        _ => return HashMap::new(),
+    };
+    let skipped: HashSet<(&str, usize)> = diagnostics
+        .iter()
+        .filter(|d| !matches!(d.kind, DiagnosticKind::UndefinedVariable(_)))
+        .filter_map(|d| Some((d.context.as_deref()?, d.pattern?)))
+        .collect();
+
+    let mut entries = HashMap::new();
+    for (name, context) in contexts {
+        let (name, context) = match (name.as_str(), context.as_vec()) {
+            (Some(name), Some(context)) => (name, context),
+            _ => continue,
+        };
+        let indexes = context
+            .iter()
+            .enumerate()
+            .filter(|&(index, entry)| {
+                let is_meta = entry.as_hash().is_some_and(|map| {
+                    META_KEYS
+                        .iter()
+                        .any(|&key| map.contains_key(&Yaml::String(key.to_owned())))
+                });
+                !is_meta && !skipped.contains(&(name, index))
+            })
+            .map(|(index, _)| index)
+            .collect();
+        entries.insert(name.to_owned(), indexes);
+    }
+    entries
+}
+
+/// The variables used in a regex or variable
+fn variable_uses(regex: &str) -> Vec<&str> {
+    regex
+        .split("{{")
+        .skip(1)
+        .filter_map(|rest| rest.split_once("}}").map(|(name, _)| name))
+        .filter(|name| {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+        .collect()
+}
+
+/// Texts that the patterns are matched against, to find out which patterns match anywhere and
+/// which ones can match without consuming text
+const PROBES: &[&str] = &[
+    "", "\n", " \n", "\t\n", "a\n", "Z\n", "_\n", "0\n", "(\n", ")\n", "[\n", "]\n", "{\n", "}\n",
+    "<\n", ">\n", "\"\n", "'\n", "`\n", "/\n", "\\\n", "#\n", ";\n", ",\n", ".\n", ":\n", "=\n",
+    "+\n", "-\n", "*\n", "$\n", "@\n", "!\n", "?\n", "|\n", "&\n", "%\n", "^\n", "~\n", "é\n",
+];
+
+/// A context, identified by the index of its syntax and its index in that syntax
+type Node = (usize, usize);
+
+struct Linter<'a> {
+    syntaxes: Vec<(&'a str, Vec<(&'a str, &'a Context)>)>,
+    names: Vec<HashMap<&'a str, usize>>,
+    /// For every pattern, where it ends when matching at the start of each probe
+    probe_matches: HashMap<*const MatchPattern, Vec<Option<usize>>>,
+    reported_loops: HashSet<(Node, usize)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    fn new(syntaxes: Vec<(&'a str, Vec<(&'a str, &'a Context)>)>) -> Linter<'a> {
+        let names = syntaxes
+            .iter()
+            .map(|(_, contexts)| {
+                contexts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &(name, _))| (name, i))
+                    .collect()
+            })
+            .collect();
+        Linter {
+            syntaxes,
+            names,
+            probe_matches: HashMap::new(),
+            reported_loops: HashSet::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn lint(mut self) -> Vec<Diagnostic> {
+        self.check_references();
+        self.check_reachability();
+        for syntax in 0..self.syntaxes.len() {
+            for context in 0..self.syntaxes[syntax].1.len() {
+                self.check_shadowing((syntax, context));
+                self.check_loops((syntax, context));
+            }
+        }
+        self.diagnostics
+    }
+
+    fn context(&self, node: Node) -> &'a Context {
+        self.syntaxes[node.0].1[node.1].1
+    }
+
+    fn report(&mut self, kind: DiagnosticKind, node: Node, pattern: Option<usize>) {
+        let (syntax, ref contexts) = self.syntaxes[node.0];
+        self.diagnostics.push(Diagnostic {
+            kind,
+            syntax: syntax.to_owned(),
+            context: Some(contexts[node.1].0.to_owned()),
+            pattern,
+            position: None,
+        });
+    }
+
+    /// The context a reference points to. References to other syntaxes are only resolved once
+    /// they are linked.
+    fn resolve(&self, syntax: usize, reference: &ContextReference) -> Option<Node> {
+        match *reference {
+            ContextReference::Named(ref name) | ContextReference::Inline(ref name) => {
+                // Includes of the top level syntax are only known once they are linked
+                let name = match name.as_str() {
+                    "$base" | "$top_level_main" => "main",
+                    name => name,
+                };
+                self.names[syntax].get(name).map(|&i| (syntax, i))
+            }
+            ContextReference::Direct(id) => Some((id.syntax_index, id.context_index)),
+            ContextReference::ByScope { .. } | ContextReference::File { .. } => None,
+        }
+    }
+
+    /// The references of a pattern with the index of the pattern
+    fn references(context: &'a Context) -> Vec<(usize, &'a ContextReference)> {
+        let mut refs = Vec::new();
+        for (index, pattern) in context.patterns.iter().enumerate() {
+            match *pattern {
+                Pattern::Include(ref reference) => refs.push((index, reference)),
+                Pattern::Match(ref pattern) => {
+                    refs.extend(operation_references(&pattern.operation).map(|r| (index, r)));
+                    refs.extend(pattern.with_prototype.iter().map(|r| (index, r)));
+                }
+            }
+        }
+        refs
+    }
+
+    fn check_references(&mut self) {
+        for syntax in 0..self.syntaxes.len() {
+            for context in 0..self.syntaxes[syntax].1.len() {
+                let node = (syntax, context);
+                for (index, reference) in Linter::references(self.context(node)) {
+                    let resolved = match *reference {
+                        // Only a syntax set can tell whether these exist
+                        ContextReference::ByScope { .. } | ContextReference::File { .. }
+                            if self.syntaxes.len() == 1 =>
+                        {
+                            true
+                        }
+                        _ => self.resolve(syntax, reference).is_some(),
+                    };
+                    if !resolved {
+                        let kind = DiagnosticKind::UnresolvedReference(format!("{:?}", reference));
+                        self.report(kind, node, Some(index));
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_reachability(&mut self) {
+        let mut reached = HashSet::new();
+        let mut queue = VecDeque::new();
+        for (syntax, names) in self.names.iter().enumerate() {
+            for root in ["__start", "__main", "main", "prototype"] {
+                if let Some(&context) = names.get(root) {
+                    queue.push_back((syntax, context));
+                }
+            }
+        }
+        while let Some(node) = queue.pop_front() {
+            if !reached.insert(node) {
+                continue;
+            }
+            let context = self.context(node);
+            let prototype = context
+                .prototype
+                .map(|id| (id.syntax_index, id.context_index));
+            queue.extend(prototype);
+            for (_, reference) in Linter::references(context) {
+                queue.extend(self.resolve(node.0, reference));
+            }
+        }
+
+        for syntax in 0..self.syntaxes.len() {
+            for context in 0..self.syntaxes[syntax].1.len() {
+                if !reached.contains(&(syntax, context)) {
+                    self.report(DiagnosticKind::UnreachableContext, (syntax, context), None);
+                }
+            }
+        }
+    }
+
+    /// The match patterns of a context with the indexes of the entries they belong to, following
+    /// includes. Each pattern also comes with the context that defines it and its index there.
+    fn match_patterns(&self, node: Node) -> Vec<(usize, (Node, usize), &'a MatchPattern)> {
+        fn collect<'a>(
+            linter: &Linter<'a>,
+            node: Node,
+            index: Option<usize>,
+            visited: &mut HashSet<Node>,
+            patterns: &mut Vec<(usize, (Node, usize), &'a MatchPattern)>,
+        ) {
+            if !visited.insert(node) {
+                return;
+            }
+            for (i, pattern) in linter.context(node).patterns.iter().enumerate() {
+                let index = index.unwrap_or(i);
+                match *pattern {
+                    Pattern::Match(ref pattern) => patterns.push((index, (node, i), pattern)),
+                    Pattern::Include(ref reference) => {
+                        if let Some(included) = linter.resolve(node.0, reference) {
+                            collect(linter, included, Some(index), visited, patterns);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut patterns = Vec::new();
+        collect(self, node, None, &mut HashSet::new(), &mut patterns);
+        patterns
+    }
+
+    /// Where the pattern ends when matching at the start of each probe, or `None` if it doesn't
+    /// match there
+    fn probe(&mut self, pattern: &MatchPattern) -> &[Option<usize>] {
+        self.probe_matches
+            .entry(pattern as *const MatchPattern)
+            .or_insert_with(|| {
+                // Regexes with backreferences to the captures of another regex can't be matched
+                // on their own
+                if pattern.has_captures
+                    || super::regex::Regex::try_compile(pattern.regex.regex_str()).is_some()
+                {
+                    return vec![None; PROBES.len()];
+                }
+                let mut region = Region::new();
+                PROBES
+                    .iter()
+                    .map(|probe| {
+                        if pattern
+                            .regex
+                            .search(probe, 0, probe.len(), Some(&mut region))
+                        {
+                            match region.pos(0) {
+                                Some((0, end)) => Some(end),
+                                _ => None,
+                            }
+                        } else {
+                            None
+                        }
+                    })
+                    .collect()
+            })
+    }
+
+    fn check_shadowing(&mut self, node: Node) {
+        let patterns = self.match_patterns(node);
+        let shadowing = patterns
+            .iter()
+            .find(|&&(_, _, pattern)| self.probe(pattern).iter().all(Option::is_some));
+        if let Some(&(shadowing, _, _)) = shadowing {
+            for index in shadowing + 1..self.context(node).patterns.len() {
+                self.report(
+                    DiagnosticKind::ShadowedPattern(shadowing),
+                    node,
+                    Some(index),
+                );
+            }
+        }
+    }
+
+    /// Simulates the parser at the start of each probe, starting in the context, to find
+    /// patterns that change the stack without consuming text until they are back in the same
+    /// state. The loop is reported at the pattern that leaves the repeated state, in the context
+    /// that defines it.
+    fn check_loops(&mut self, node: Node) {
+        const MAX_STEPS: usize = 32;
+
+        for probe in 0..PROBES.len() {
+            let mut stack = vec![node];
+            let mut seen = vec![stack.clone()];
+            // The pattern taken from each of the seen states
+            let mut steps = Vec::new();
+            let looped = loop {
+                let top = *stack.last().unwrap();
+                let patterns = self.match_patterns(top);
+                let chosen = patterns
+                    .into_iter()
+                    .find(|&(_, _, pattern)| self.probe(pattern)[probe].is_some());
+                let (owner, pattern) = match chosen {
+                    Some((_, owner, pattern)) if self.probe(pattern)[probe] == Some(0) => {
+                        (owner, pattern)
+                    }
+                    _ => break None,
+                };
+                steps.push(owner);
+
+                let (pop, refs) = match pattern.operation {
+                    MatchOperation::Push(ref refs) => (0, &refs[..]),
+                    MatchOperation::Set(ref refs) => (1, &refs[..]),
+                    MatchOperation::Pop => (1, &[][..]),
+                    MatchOperation::PopMultiple(n) => (n, &[][..]),
+                    MatchOperation::PopAndPush(n, ref refs) => (n, &refs[..]),
+                    MatchOperation::PopAndSet(n, ref refs) => (n + 1, &refs[..]),
+                    // The first alternative is tried first
+                    MatchOperation::Branch { ref contexts, .. } => (0, &contexts[..1]),
+                    MatchOperation::Fail(_) | MatchOperation::None => break None,
+                };
+                if pop >= stack.len() {
+                    // Where this ends up depends on the contexts below
+                    break None;
+                }
+                stack.truncate(stack.len() - pop);
+                let pushed: Option<Vec<Node>> =
+                    refs.iter().map(|r| self.resolve(owner.0 .0, r)).collect();
+                match pushed {
+                    Some(pushed) => stack.extend(pushed),
+                    None => break None,
+                }
+                if let Some(repeated) = seen.iter().position(|s| *s == stack) {
+                    break Some(steps[repeated]);
+                }
+                if seen.len() > MAX_STEPS {
+                    // The stack keeps growing
+                    break Some(owner);
+                }
+                seen.push(stack.clone());
+            };
+            if let Some((context, index)) = looped {
+                if self.reported_loops.insert((context, index)) {
+                    self.report(DiagnosticKind::NonConsumingLoop, context, Some(index));
+                }
+                return;
+            }
+        }
+    }
+}
+
+fn operation_references(operation: &MatchOperation) -> impl Iterator<Item = &ContextReference> {
+    let refs: &[ContextReference] = match *operation {
+        MatchOperation::Push(ref refs)
+        | MatchOperation::Set(ref refs)
+        | MatchOperation::PopAndPush(_, ref refs)
+        | MatchOperation::PopAndSet(_, ref refs)
+        | MatchOperation::Branch {
+            contexts: ref refs, ..
+        } => refs,
+        MatchOperation::Pop
+        | MatchOperation::PopMultiple(_)
+        | MatchOperation::Fail(_)
+        | MatchOperation::None => &[],
+    };
+    refs.iter()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::SyntaxSetBuilder;
+
+    fn summary(diagnostics: &[Diagnostic]) -> Vec<String> {
+        let mut summary: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        summary.sort();
+        summary
+    }
+
+    #[cfg(feature = "yaml-load")]
+    #[test]
+    fn can_lint_sublime_syntax() {
+        let source = r#"
+        name: Lint
+        scope: source.lint
+        variables:
+          ident: '\w{{letter}}'
+        contexts:
+          main:
+            - match: '{{ident}}'
+            - match: '<'
+              embed: scope:source.other
+            - match: '(?=\()'
+              push: group
+            - match: '(?=\S)'
+              push: missing
+          group:
+            - match: '\w'
+            - match: '(?=\S)'
+              pop: true
+          unused:
+            - match: ''
+              pop: true
+            - match: x
+            - include: group
+        "#;
+        let diagnostics = lint_sublime_syntax(source, true, |_| None);
+        assert_eq!(
+            summary(&diagnostics),
+            vec![
+                "Lint, context main, pattern 0: line 8, column 15: Unknown variable: letter",
+                "Lint, context main, pattern 1: line 9, column 15: embed without escape",
+                "Lint, context main, pattern 2: Pattern loops without consuming any text",
+                "Lint, context main, pattern 3: Unresolved context reference Named(\"missing\")",
+                "Lint, context unused, pattern 1: Pattern is shadowed by pattern 0, which always matches",
+                "Lint, context unused, pattern 2: Pattern is shadowed by pattern 0, which always matches",
+                "Lint, context unused: Context is never used",
+            ]
+        );
+        let errors = diagnostics
+            .iter()
+            .filter(|d| d.severity() == Severity::Error)
+            .count();
+        assert_eq!(errors, 4);
+
+        let diagnostics = lint_sublime_syntax("contexts: {main: []}", true, |_| None);
+        assert!(matches!(
+            diagnostics[..],
+            [Diagnostic {
+                kind: DiagnosticKind::InvalidSyntax(_),
+                ..
+            }]
+        ));
+    }
+
+    #[cfg(feature = "yaml-load")]
+    #[test]
+    fn can_lint_extended_sublime_syntax() {
+        let source = "name: Child\nscope: source.child\nextends: Parent.sublime-syntax\ncontexts:\n  main:\n    - meta_append: true\n    - match: '{{missing}}'";
+        let diagnostics = lint_sublime_syntax(source, true, |name| {
+            (name == "Parent.sublime-syntax").then(|| {
+                "scope: source.parent\ncontexts: {main: [{match: '(?=a)', push: main}], unused: []}"
+                    .to_owned()
+            })
+        });
+        assert_eq!(
+            summary(&diagnostics),
+            vec![
+                "Child, context main, pattern 1: Pattern loops without consuming any text",
+                "Child, context main, pattern 2: line 7, column 7: Unknown variable: missing",
+                "Child, context unused: Context is never used",
+            ]
+        );
+    }
+
+    #[cfg(feature = "yaml-load")]
+    #[test]
+    fn can_lint_syntax_set() {
+        let a = SyntaxDefinition::load_from_str(
+            "name: A\nscope: source.a\ncontexts: {main: [{match: a, push: 'scope:source.b#shared'}]}",
+            true,
+            None,
+        )
+        .unwrap();
+        let b = SyntaxDefinition::load_from_str(
+            "name: B\nscope: source.b\ncontexts: {main: [], shared: [{match: '', pop: true}], unused: []}",
+            true,
+            None,
+        )
+        .unwrap();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(b.clone());
+        builder.add(a);
+        let ss = builder.build();
+        assert_eq!(
+            summary(&lint_syntax_set(&ss)),
+            vec!["B, context unused: Context is never used"]
+        );
+
+        // On its own, the context used by the other syntax is unreachable
+        assert_eq!(
+            summary(&lint_syntax_definition(&b)),
+            vec![
+                "B, context shared: Context is never used",
+                "B, context unused: Context is never used",
+            ]
+        );
+    }
+}
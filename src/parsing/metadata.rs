@@ -14,6 +14,7 @@ use serde_derive::{Deserialize, Serialize};
 use super::super::highlighting::settings::*;
 use super::super::highlighting::ScopeSelectors;
 use super::super::LoadingError;
+use super::outline::SymbolTransformation;
 use super::regex::Regex;
 use super::scope::{MatchPower, Scope};
 
@@ -25,7 +26,7 @@ type Dict = serde_json::Map<String, Settings>;
 type SelectorString = String;
 
 /// A collection of all loaded metadata
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone)]
 pub struct Metadata {
     pub scoped_metadata: Vec<MetadataSet>,
 }
@@ -55,6 +56,7 @@ pub struct MetadataItems {
     pub decrease_indent_pattern: Option<Regex>,
     pub bracket_indent_next_line_pattern: Option<Regex>,
     pub disable_indent_next_line_pattern: Option<Regex>,
+    #[serde(alias = "unIndentedLinePattern")]
     pub unindented_line_pattern: Option<Regex>,
     pub indent_parens: Option<bool>,
     #[serde(default)]
@@ -66,6 +68,32 @@ pub struct MetadataItems {
     /// The first pair of `TM_COMMENT_START` and `TM_COMMENT_END` items in
     /// `shell_variables`, if they exist.
     pub block_comment: Option<(String, String)>,
+    /// The tokens that start and end foldable regions, see [`FoldingRules`]
+    ///
+    /// [`FoldingRules`]: struct.FoldingRules.html
+    pub fold_scopes: Option<Vec<FoldScope>>,
+    /// Whether lines can be folded by their indentation
+    pub indentation_folding_enabled: Option<bool>,
+    /// Whether the scope is a symbol in the list of symbols of a file, see [`OutlineRules`]
+    ///
+    /// [`OutlineRules`]: struct.OutlineRules.html
+    pub show_in_symbol_list: Option<bool>,
+    /// Whether the scope is a symbol in the index of the symbols of all files
+    pub show_in_indexed_symbol_list: Option<bool>,
+    /// Changes the text of a symbol before it is shown in the symbol list
+    pub symbol_transformation: Option<SymbolTransformation>,
+    /// Changes the text of a symbol before it is added to the index
+    pub symbol_index_transformation: Option<SymbolTransformation>,
+}
+
+/// A `foldScopes` entry, with selectors for the tokens that start and end a foldable region
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FoldScope {
+    /// The selector of the tokens that start a region
+    pub begin: SelectorString,
+    /// The selector of the tokens that end a region
+    pub end: SelectorString,
 }
 
 /// A type that can be deserialized from a `.tmPreferences` file.
@@ -93,8 +121,25 @@ const KEYS_WE_USE: &[&str] = &[
     "unIndentedLinePattern",
     "indentParens",
     "shellVariables",
+    "foldScopes",
+    "indentationFoldingEnabled",
+    "showInSymbolList",
+    "showInIndexedSymbolList",
+    "symbolTransformation",
+    "symbolIndexTransformation",
 ];
 
+// Keys that are booleans, but often written as integers in `.tmPreferences` files
+const FLAG_KEYS: &[&str] = &[
+    "indentParens",
+    "indentationFoldingEnabled",
+    "showInSymbolList",
+    "showInIndexedSymbolList",
+];
+
+// Keys of symbol transformations, which are skipped on their own if they are invalid
+const TRANSFORMATION_KEYS: &[&str] = &["symbolTransformation", "symbolIndexTransformation"];
+
 impl LoadMetadata {
     /// Adds the provided `RawMetadataEntry`
     ///
@@ -256,6 +301,21 @@ impl MetadataSet {
             .get("shellVariables")
             .and_then(|v| v.as_object())
             .and_then(MetadataSet::get_block_comment_markers);
+        for key in FLAG_KEYS {
+            if let Some(value) = settings.get_mut(*key) {
+                if let Some(flag) = value.as_i64() {
+                    *value = (flag != 0).into();
+                }
+            }
+        }
+        for key in TRANSFORMATION_KEYS {
+            if let Some(value) = settings.get(*key) {
+                if let Err(e) = serde_json::from_value::<SymbolTransformation>(value.clone()) {
+                    eprintln!("skipping invalid {} in {}: {}", key, path, e);
+                    settings.remove(*key);
+                }
+            }
+        }
 
         let mut items: MetadataItems =
             serde_json::from_value(settings.into()).map_err(|e| format!("{}: {:?}", path, e))?;
@@ -407,6 +467,50 @@ impl<'a> ScopedMetadata<'a> {
             .map(|(a, b)| (a.as_str(), b.as_str()))
     }
 
+    /// The fold scopes of the best match that has them
+    pub fn fold_scopes(&self) -> Option<&'a [FoldScope]> {
+        let idx = self
+            .items
+            .iter()
+            .position(|m| m.1.items.fold_scopes.is_some())?;
+        self.items[idx].1.items.fold_scopes.as_deref()
+    }
+
+    /// Whether the best match that has the setting allows folding lines by their indentation
+    pub fn indentation_folding_enabled(&self) -> Option<bool> {
+        self.best_match(|ind| ind.indentation_folding_enabled)
+    }
+
+    /// Whether the best match that has the setting shows the scope in the list of symbols of a
+    /// file
+    pub fn show_in_symbol_list(&self) -> Option<bool> {
+        self.best_match(|ind| ind.show_in_symbol_list)
+    }
+
+    /// Whether the best match that has the setting adds the scope to the index of the symbols
+    /// of all files
+    pub fn show_in_indexed_symbol_list(&self) -> Option<bool> {
+        self.best_match(|ind| ind.show_in_indexed_symbol_list)
+    }
+
+    /// The symbol transformation of the best match that has one
+    pub fn symbol_transformation(&self) -> Option<&'a SymbolTransformation> {
+        let idx = self
+            .items
+            .iter()
+            .position(|m| m.1.items.symbol_transformation.is_some())?;
+        self.items[idx].1.items.symbol_transformation.as_ref()
+    }
+
+    /// The symbol index transformation of the best match that has one
+    pub fn symbol_index_transformation(&self) -> Option<&'a SymbolTransformation> {
+        let idx = self
+            .items
+            .iter()
+            .position(|m| m.1.items.symbol_index_transformation.is_some())?;
+        self.items[idx].1.items.symbol_index_transformation.as_ref()
+    }
+
     fn best_match<T, F>(&self, f: F) -> Option<T>
     where
         F: FnMut(&MetadataItems) -> Option<T>,
@@ -485,6 +589,219 @@ impl<'de> Deserialize<'de> for MetadataSet {
     }
 }
 
+/// The items of a [`MetadataSet`] in dumps of [`Metadata`], which are the items that dumps had
+/// before the ones in [`DumpedExtras`] were added
+#[derive(Serialize, Deserialize)]
+struct DumpedItems {
+    increase_indent_pattern: Option<Regex>,
+    decrease_indent_pattern: Option<Regex>,
+    bracket_indent_next_line_pattern: Option<Regex>,
+    disable_indent_next_line_pattern: Option<Regex>,
+    unindented_line_pattern: Option<Regex>,
+    indent_parens: Option<bool>,
+    shell_variables: BTreeMap<String, String>,
+    line_comment: Option<String>,
+    block_comment: Option<(String, String)>,
+}
+
+/// The items of a [`MetadataSet`] that were added to dumps of [`Metadata`] later. They come
+/// after all sets, so that older dumps, which end after the sets, can still be loaded.
+#[derive(Default, Serialize, Deserialize)]
+struct DumpedExtras {
+    fold_scopes: Option<Vec<FoldScope>>,
+    indentation_folding_enabled: Option<bool>,
+    show_in_symbol_list: Option<bool>,
+    show_in_indexed_symbol_list: Option<bool>,
+    symbol_transformation: Option<SymbolTransformation>,
+    symbol_index_transformation: Option<SymbolTransformation>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DumpedMetadataSet {
+    selector_string: String,
+    items: Option<DumpedItems>,
+}
+
+impl MetadataSet {
+    fn to_dumped(&self) -> (DumpedMetadataSet, DumpedExtras) {
+        let MetadataItems {
+            increase_indent_pattern,
+            decrease_indent_pattern,
+            bracket_indent_next_line_pattern,
+            disable_indent_next_line_pattern,
+            unindented_line_pattern,
+            indent_parens,
+            shell_variables,
+            line_comment,
+            block_comment,
+            fold_scopes,
+            indentation_folding_enabled,
+            show_in_symbol_list,
+            show_in_indexed_symbol_list,
+            symbol_transformation,
+            symbol_index_transformation,
+        } = self.items.clone();
+        let set = DumpedMetadataSet {
+            selector_string: self.selector_string.clone(),
+            items: Some(DumpedItems {
+                increase_indent_pattern,
+                decrease_indent_pattern,
+                bracket_indent_next_line_pattern,
+                disable_indent_next_line_pattern,
+                unindented_line_pattern,
+                indent_parens,
+                shell_variables,
+                line_comment,
+                block_comment,
+            }),
+        };
+        let extras = DumpedExtras {
+            fold_scopes,
+            indentation_folding_enabled,
+            show_in_symbol_list,
+            show_in_indexed_symbol_list,
+            symbol_transformation,
+            symbol_index_transformation,
+        };
+        (set, extras)
+    }
+
+    fn from_dumped(set: DumpedMetadataSet, extras: DumpedExtras) -> Result<MetadataSet, String> {
+        let DumpedMetadataSet {
+            selector_string,
+            items,
+        } = set;
+        let selector =
+            ScopeSelectors::from_str(&selector_string).map_err(|e| format!("{:?}", e))?;
+        let DumpedItems {
+            increase_indent_pattern,
+            decrease_indent_pattern,
+            bracket_indent_next_line_pattern,
+            disable_indent_next_line_pattern,
+            unindented_line_pattern,
+            indent_parens,
+            shell_variables,
+            line_comment,
+            block_comment,
+        } = items.ok_or_else(|| "no metadata items".to_string())?;
+        let DumpedExtras {
+            fold_scopes,
+            indentation_folding_enabled,
+            show_in_symbol_list,
+            show_in_indexed_symbol_list,
+            symbol_transformation,
+            symbol_index_transformation,
+        } = extras;
+        Ok(MetadataSet {
+            selector_string,
+            selector,
+            items: MetadataItems {
+                increase_indent_pattern,
+                decrease_indent_pattern,
+                bracket_indent_next_line_pattern,
+                disable_indent_next_line_pattern,
+                unindented_line_pattern,
+                indent_parens,
+                shell_variables,
+                line_comment,
+                block_comment,
+                fold_scopes,
+                indentation_folding_enabled,
+                show_in_symbol_list,
+                show_in_indexed_symbol_list,
+                symbol_transformation,
+                symbol_index_transformation,
+            },
+        })
+    }
+}
+
+impl Serialize for Metadata {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let (sets, extras): (Vec<_>, Vec<_>) = self
+            .scoped_metadata
+            .iter()
+            .map(MetadataSet::to_dumped)
+            .unzip();
+        let mut state = serializer.serialize_struct("Metadata", 2)?;
+        state.serialize_field("scoped_metadata", &sets)?;
+        state.serialize_field("extras", &extras)?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Metadata {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::{Error, IgnoredAny, MapAccess, SeqAccess, Visitor};
+
+        struct MetadataVisitor;
+
+        impl MetadataVisitor {
+            fn metadata<E: Error>(
+                sets: Vec<DumpedMetadataSet>,
+                extras: Option<Vec<DumpedExtras>>,
+            ) -> Result<Metadata, E> {
+                let mut extras = extras.unwrap_or_default().into_iter();
+                let scoped_metadata = sets
+                    .into_iter()
+                    .map(|set| MetadataSet::from_dumped(set, extras.next().unwrap_or_default()))
+                    .collect::<Result<_, _>>()
+                    .map_err(Error::custom)?;
+                Ok(Metadata { scoped_metadata })
+            }
+        }
+
+        impl<'de> Visitor<'de> for MetadataVisitor {
+            type Value = Metadata;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                formatter.write_str("metadata")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Metadata, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let sets = seq
+                    .next_element()?
+                    .ok_or_else(|| Error::invalid_length(0, &"2 fields"))?;
+                // Older dumps end before the extras, so reading them fails at the end of the data
+                let extras = seq.next_element().ok().flatten();
+                MetadataVisitor::metadata(sets, extras)
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Metadata, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut sets = None;
+                let mut extras = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "scoped_metadata" => sets = Some(map.next_value()?),
+                        "extras" => extras = Some(map.next_value()?),
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+                let sets = sets.ok_or_else(|| Error::missing_field("scoped_metadata"))?;
+                MetadataVisitor::metadata(sets, extras)
+            }
+        }
+
+        const FIELDS: &[&str] = &["scoped_metadata", "extras"];
+        deserializer.deserialize_struct("Metadata", FIELDS, MetadataVisitor)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -531,6 +848,98 @@ mod tests {
         assert!(rust_meta.items.line_comment.is_some());
     }
 
+    #[test]
+    fn load_fold_settings() {
+        let settings = serde_json::json!({
+            "foldScopes": [
+                {"begin": "punctuation.section.block.begin", "end": "punctuation.section.block.end"},
+                {"begin": "too.many.atoms.for.a.single.scope.here.x", "end": "x"},
+            ],
+            "indentationFoldingEnabled": true,
+        });
+        let settings = settings.as_object().cloned().unwrap();
+        let metaset = MetadataSet::from_raw(("source.test".into(), settings)).unwrap();
+        let metadata = Metadata {
+            scoped_metadata: vec![metaset],
+        };
+        let scope = [Scope::new("source.test").unwrap()];
+        let fold_scopes = metadata.metadata_for_scope(&scope).fold_scopes().unwrap();
+        assert_eq!(fold_scopes.len(), 2);
+        assert_eq!(fold_scopes[0].end, "punctuation.section.block.end");
+
+        let rules = crate::parsing::FoldingRules::from_metadata(&metadata, &scope);
+        assert_eq!(rules.block_delimiters.len(), 1);
+        assert!(rules.indentation);
+    }
+
+    #[test]
+    fn load_symbol_settings() {
+        let settings = serde_json::json!({
+            "showInSymbolList": 1,
+            "showInIndexedSymbolList": 0,
+            "symbolTransformation": "s/^(\\w+)\\s+(\\w+)/$2 (${1})/;\n# comment\ns/X/y/gi",
+        });
+        let settings = settings.as_object().cloned().unwrap();
+        let metaset = MetadataSet::from_raw(("entity.name".into(), settings)).unwrap();
+        let metadata = Metadata {
+            scoped_metadata: vec![metaset],
+        };
+        let scope = [Scope::new("entity.name.function").unwrap()];
+        let scoped = metadata.metadata_for_scope(&scope);
+        assert_eq!(scoped.show_in_symbol_list(), Some(true));
+        assert_eq!(scoped.show_in_indexed_symbol_list(), Some(false));
+        assert!(scoped.symbol_index_transformation().is_none());
+        let transformation = scoped.symbol_transformation().unwrap();
+        assert_eq!(transformation.apply("fn xmax x"), "ymay (fn) y");
+        assert_eq!(transformation.apply("xx"), "yy");
+
+        let dumped = crate::dumps::dump_binary(&metadata);
+        let loaded: Metadata = crate::dumps::from_binary(&dumped);
+        assert_eq!(
+            loaded.scoped_metadata[0].items,
+            metadata.scoped_metadata[0].items
+        );
+    }
+
+    #[test]
+    fn skips_invalid_symbol_transformations() {
+        let settings = serde_json::json!({
+            "showInSymbolList": 1,
+            "symbolTransformation": "s/(/x/",
+            "symbolIndexTransformation": "s/a/b/",
+        });
+        let settings = settings.as_object().cloned().unwrap();
+        let metaset = MetadataSet::from_raw(("entity.name".into(), settings)).unwrap();
+        assert_eq!(metaset.items.show_in_symbol_list, Some(true));
+        assert!(metaset.items.symbol_transformation.is_none());
+        assert_eq!(
+            metaset.items.symbol_index_transformation.unwrap().as_str(),
+            "s/a/b/"
+        );
+    }
+
+    #[test]
+    fn can_load_dumps_without_extras() {
+        // Dumps made before the items in `DumpedExtras` were added
+        #[derive(Serialize)]
+        struct OldMetadata {
+            scoped_metadata: Vec<DumpedMetadataSet>,
+        }
+        let settings = serde_json::json!({ "indentParens": true, "showInSymbolList": true });
+        let settings = settings.as_object().cloned().unwrap();
+        let metaset = MetadataSet::from_raw(("source.test".into(), settings)).unwrap();
+        let old = crate::dumps::dump_binary(&OldMetadata {
+            scoped_metadata: vec![metaset.to_dumped().0],
+        });
+        let loaded: Metadata = crate::dumps::from_binary(&old);
+        assert_eq!(loaded.scoped_metadata[0].items.indent_parens, Some(true));
+        assert_eq!(loaded.scoped_metadata[0].items.show_in_symbol_list, None);
+
+        let defaults: Metadata =
+            crate::dumps::from_binary(include_bytes!("../../assets/default_metadata.packdump"));
+        assert!(!defaults.scoped_metadata.is_empty());
+    }
+
     #[test]
     fn parse_yaml_meta() {
         let path = "testdata/Packages/YAML/Indentation Rules.tmPreferences";
@@ -4,30 +4,68 @@
 //!
 //! [`SyntaxSet`]: struct.SyntaxSet.html
 
+#[cfg(feature = "parsing")]
+mod brackets;
+#[cfg(feature = "parsing")]
+mod folding;
+#[cfg(feature = "metadata")]
+mod indentation;
+#[cfg(all(feature = "parsing", feature = "yaml-load"))]
+mod json_load;
+#[cfg(feature = "parsing")]
+pub mod lint;
 #[cfg(feature = "metadata")]
 pub mod metadata;
 #[cfg(feature = "parsing")]
+mod outline;
+#[cfg(feature = "parsing")]
 mod parser;
+#[cfg(all(feature = "parsing", feature = "regex-hybrid"))]
+mod pattern_set;
+#[cfg(feature = "parsing")]
+mod profiler;
+#[cfg(feature = "parsing")]
+mod scope_tree;
 #[cfg(feature = "parsing")]
 pub mod syntax_definition;
 #[cfg(feature = "parsing")]
 mod syntax_set;
 #[cfg(all(feature = "parsing", feature = "yaml-load"))]
+mod tmlanguage_load;
+#[cfg(all(feature = "parsing", feature = "yaml-load"))]
+mod yaml_emit;
+#[cfg(all(feature = "parsing", feature = "yaml-load"))]
 mod yaml_load;
 
+#[cfg(any(feature = "parsing", feature = "yaml-load", feature = "metadata"))]
+mod prefilter;
 #[cfg(any(feature = "parsing", feature = "yaml-load", feature = "metadata"))]
 mod regex;
 mod scope;
 
+#[cfg(feature = "parsing")]
+pub use self::brackets::*;
+#[cfg(feature = "parsing")]
+pub use self::folding::*;
+#[cfg(feature = "metadata")]
+pub use self::indentation::*;
 #[cfg(feature = "metadata")]
 pub use self::metadata::*;
 #[cfg(feature = "parsing")]
+pub use self::outline::*;
+#[cfg(feature = "parsing")]
 pub use self::parser::*;
 #[cfg(feature = "parsing")]
+pub use self::profiler::*;
+#[cfg(feature = "parsing")]
+pub use self::scope_tree::*;
+#[cfg(feature = "parsing")]
 pub use self::syntax_definition::SyntaxDefinition;
 #[cfg(feature = "parsing")]
 pub use self::syntax_set::*;
 #[cfg(all(feature = "parsing", feature = "yaml-load"))]
+pub use self::yaml_emit::*;
+#[cfg(all(feature = "parsing", feature = "yaml-load"))]
 pub use self::yaml_load::*;
 
 #[cfg(any(feature = "parsing", feature = "yaml-load", feature = "metadata"))]
@@ -0,0 +1,676 @@
+use super::folding::Lines;
+#[cfg(feature = "metadata")]
+use super::metadata::Metadata;
+use super::regex::{Regex, Region};
+use super::scope::Scope;
+use super::scope_tree::{ScopeTree, ScopeTreeBuilder};
+use super::syntax_set::{SyntaxReference, SyntaxSet};
+use crate::highlighting::ScopeSelectors;
+use crate::Error;
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::ops::Range;
+use std::str::FromStr;
+
+/// A symbol of a document, like a function or a type, in an [`Outline`]
+///
+/// [`Outline`]: struct.Outline.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    /// The name shown in the symbol list, after the symbol transformation
+    pub name: String,
+    /// The name in an index of the symbols of all files, if the symbol is shown there
+    pub index_name: Option<String>,
+    /// The scope of the name, which tells what kind of symbol it is, like
+    /// `entity.name.function.rust`
+    pub scope: Scope,
+    /// The byte range of the name
+    pub name_range: Range<usize>,
+    /// The byte range of the whole symbol, which contains the symbols nested in it
+    pub range: Range<usize>,
+    /// The index of the symbol this one is nested in, if any
+    pub parent: Option<usize>,
+    /// The indexes of the symbols nested in this one, in order
+    pub children: Vec<usize>,
+}
+
+/// The symbols of a document as a tree, built with [`OutlineRules`]
+///
+/// [`OutlineRules`]: struct.OutlineRules.html
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Outline {
+    symbols: Vec<Symbol>,
+    roots: Vec<usize>,
+}
+
+impl Outline {
+    /// All symbols in the order of their names, which the indexes in the symbols refer to
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// The indexes of the symbols that aren't nested in another symbol, in order
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    /// Returns the indexes of the symbols whose range contains the byte offset, outermost first,
+    /// like for breadcrumbs
+    pub fn path_at(&self, offset: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        let mut siblings = &self.roots;
+        while let Some(&index) = siblings
+            .iter()
+            .rev()
+            .find(|&&index| self.symbols[index].range.contains(&offset))
+        {
+            path.push(index);
+            siblings = &self.symbols[index].children;
+        }
+        path
+    }
+}
+
+/// The symbol list settings for the scopes that a selector matches, like the ones of a
+/// `.tmPreferences` file
+#[derive(Debug, Clone)]
+pub struct SymbolRule {
+    /// The scopes the settings apply to
+    pub selector: ScopeSelectors,
+    /// Whether the scopes are symbols in the list of symbols of a file
+    pub show_in_symbol_list: Option<bool>,
+    /// Whether the scopes are symbols in the index of the symbols of all files
+    pub show_in_indexed_symbol_list: Option<bool>,
+    /// Changes the text of a symbol before it is shown in the symbol list
+    pub symbol_transformation: Option<SymbolTransformation>,
+    /// Changes the text of a symbol before it is added to the index
+    pub symbol_index_transformation: Option<SymbolTransformation>,
+}
+
+impl SymbolRule {
+    /// Creates a rule without any settings
+    pub fn new(selector: ScopeSelectors) -> SymbolRule {
+        SymbolRule {
+            selector,
+            show_in_symbol_list: None,
+            show_in_indexed_symbol_list: None,
+            symbol_transformation: None,
+            symbol_index_transformation: None,
+        }
+    }
+}
+
+/// Finds the symbols of a document, based on the scopes of a [`ScopeTree`]
+///
+/// Each setting of a symbol comes from the rule with the best matching selector that has it, and
+/// the later rule if several match equally well, like with `.tmPreferences` files. Nodes that
+/// the rules show in the symbol list are symbols, unless their name is empty after the
+/// transformation.
+///
+/// The range of a symbol is the node its name is nested in if that starts on the same line, like
+/// the `meta.function` of a function, continued by adjacent nodes with the same scope and by the
+/// lines after it that are indented more. Symbols are nested in the symbols whose range contains
+/// their name.
+///
+/// [`ScopeTree`]: struct.ScopeTree.html
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{OutlineRules, SyntaxSet};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let text = "class A:\n    def b(self):\n        pass\ndef c():\n    pass\n";
+/// let syntax = ss.find_syntax_by_extension("py").unwrap();
+/// let outline = OutlineRules::default().parse_outline(text, syntax, &ss).unwrap();
+///
+/// let names: Vec<_> = outline.symbols().iter().map(|s| s.name.as_str()).collect();
+/// assert_eq!(names, ["A", "b", "c"]);
+/// assert_eq!(outline.roots(), [0, 2]);
+/// assert_eq!(outline.symbols()[1].parent, Some(0));
+/// assert_eq!(outline.path_at(30), [0, 1]);
+/// assert_eq!(outline.symbols()[2].scope.to_string(), "entity.name.function.python");
+/// ```
+#[derive(Debug, Clone)]
+pub struct OutlineRules {
+    /// The rules, with later rules taking precedence over earlier ones that match equally well
+    pub rules: Vec<SymbolRule>,
+}
+
+impl Default for OutlineRules {
+    fn default() -> OutlineRules {
+        let selector = ScopeSelectors::from_str(
+            "entity.name.function, entity.name.type, entity.name.class, entity.name.struct, \
+             entity.name.enum, entity.name.union, entity.name.trait, entity.name.interface, \
+             entity.name.impl, entity.name.namespace, entity.name.module, \
+             entity.name.section, meta.toc-list",
+        )
+        .expect("default selectors are valid");
+        let mut rule = SymbolRule::new(selector);
+        rule.show_in_symbol_list = Some(true);
+        rule.show_in_indexed_symbol_list = Some(true);
+        OutlineRules { rules: vec![rule] }
+    }
+}
+
+impl OutlineRules {
+    /// Creates rules from the `showInSymbolList`, `showInIndexedSymbolList`,
+    /// `symbolTransformation` and `symbolIndexTransformation` settings of metadata, which come
+    /// after the default rule.
+    #[cfg(feature = "metadata")]
+    pub fn from_metadata(metadata: &Metadata) -> OutlineRules {
+        let mut rules = OutlineRules::default();
+        for set in &metadata.scoped_metadata {
+            let items = &set.items;
+            if items.show_in_symbol_list.is_none()
+                && items.show_in_indexed_symbol_list.is_none()
+                && items.symbol_transformation.is_none()
+                && items.symbol_index_transformation.is_none()
+            {
+                continue;
+            }
+            rules.rules.push(SymbolRule {
+                selector: set.selector.clone(),
+                show_in_symbol_list: items.show_in_symbol_list,
+                show_in_indexed_symbol_list: items.show_in_indexed_symbol_list,
+                symbol_transformation: items.symbol_transformation.clone(),
+                symbol_index_transformation: items.symbol_index_transformation.clone(),
+            });
+        }
+        rules
+    }
+
+    /// Parses a text with a syntax from the start and returns its outline
+    pub fn parse_outline(
+        &self,
+        text: &str,
+        syntax: &SyntaxReference,
+        syntax_set: &SyntaxSet,
+    ) -> Result<Outline, Error> {
+        let mut builder = ScopeTreeBuilder::new(syntax);
+        for line in text.split_inclusive('\n') {
+            builder.parse_line(line, syntax_set)?;
+        }
+        Ok(self.outline(&builder.build(), text))
+    }
+
+    /// Returns the outline of a document with the scope tree built from its text
+    pub fn outline(&self, tree: &ScopeTree, text: &str) -> Outline {
+        let lines = Lines::new(text);
+        let mut candidates: Vec<usize> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.show_in_symbol_list == Some(true))
+            .flat_map(|rule| tree.nodes_matching_any(&rule.selector))
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut outline = Outline::default();
+        // The symbols that can contain the next one, outermost first
+        let mut open: Vec<usize> = Vec::new();
+        for index in candidates {
+            let node = &tree.nodes()[index];
+            let last = outline.symbols.last();
+            // Nodes in the name of a symbol are part of it
+            if last.is_some_and(|symbol| node.range.start < symbol.name_range.end) {
+                continue;
+            }
+            let stack = tree.scope_stack(index);
+            let stack = stack.as_slice();
+            if self.best(stack, |rule| rule.show_in_symbol_list) != Some(true) {
+                continue;
+            }
+            let text_of_name = &text[node.range.clone()];
+            let name = match self.best(stack, |rule| rule.symbol_transformation.as_ref()) {
+                Some(transformation) => transformation.apply(text_of_name),
+                None => text_of_name.to_string(),
+            };
+            if name.is_empty() {
+                continue;
+            }
+            let index_name = match self.best(stack, |rule| rule.show_in_indexed_symbol_list) {
+                Some(true) => Some(
+                    match self.best(stack, |rule| rule.symbol_index_transformation.as_ref()) {
+                        Some(transformation) => transformation.apply(text_of_name),
+                        None => text_of_name.to_string(),
+                    },
+                ),
+                _ => None,
+            };
+
+            let mut range = node.range.clone();
+            let line = lines.line(node.range.start);
+            // The parent is the construct of the name, unless it's the whole document or it
+            // contains the name of the previous symbol too
+            let construct = node.parent.filter(|&parent| {
+                let parent = &tree.nodes()[parent];
+                parent.parent.is_some()
+                    && lines.line(parent.range.start) == line
+                    && last
+                        .into_iter()
+                        .all(|symbol| symbol.name_range.start < parent.range.start)
+            });
+            if let Some(construct) = construct {
+                range.start = range.start.min(tree.nodes()[construct].range.start);
+                range.end = range.end.max(continued_end(tree, construct));
+            }
+            range.end = range.end.max(indented_end(text, lines.start(line)));
+
+            while let Some(&parent) = open.last() {
+                if outline.symbols[parent].range.contains(&node.range.start) {
+                    break;
+                }
+                open.pop();
+            }
+            let symbol_index = outline.symbols.len();
+            let parent = open.last().copied();
+            match parent {
+                Some(parent) => outline.symbols[parent].children.push(symbol_index),
+                None => outline.roots.push(symbol_index),
+            }
+            outline.symbols.push(Symbol {
+                name,
+                index_name,
+                scope: node.scope,
+                name_range: node.range.clone(),
+                range,
+                parent,
+                children: Vec::new(),
+            });
+            open.push(symbol_index);
+        }
+        outline
+    }
+
+    /// The setting of the rule with the best match for the scopes that has it
+    fn best<'a, T>(
+        &'a self,
+        stack: &[Scope],
+        setting: impl Fn(&'a SymbolRule) -> Option<T>,
+    ) -> Option<T> {
+        self.rules
+            .iter()
+            .filter_map(|rule| Some((rule.selector.does_match(stack)?, setting(rule)?)))
+            // The last of equally good matches is returned
+            .max_by_key(|(power, _)| *power)
+            .map(|(_, value)| value)
+    }
+}
+
+/// The end of a node with the adjacent nodes after it that have the same scope
+fn continued_end(tree: &ScopeTree, index: usize) -> usize {
+    let node = &tree.nodes()[index];
+    let siblings = match node.parent {
+        Some(parent) => &tree.nodes()[parent].children,
+        None => tree.roots(),
+    };
+    let position = siblings.iter().position(|&i| i == index).unwrap();
+    let mut end = node.range.end;
+    for &sibling in &siblings[position + 1..] {
+        let sibling = &tree.nodes()[sibling];
+        if sibling.scope != node.scope || sibling.range.start != end {
+            break;
+        }
+        end = sibling.range.end;
+    }
+    end
+}
+
+/// The end of the last line after the one at the offset that is indented more than it, without
+/// the line ending, ignoring blank lines
+fn indented_end(text: &str, line_start: usize) -> usize {
+    let mut lines = text[line_start..].split_inclusive('\n');
+    let Some(first) = lines.next() else {
+        return line_start;
+    };
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let first_indent = indent(first);
+    let mut offset = line_start + first.len();
+    let mut end = line_start + first.trim_end().len();
+    for line in lines {
+        if !line.trim().is_empty() {
+            if indent(line) <= first_indent {
+                break;
+            }
+            end = offset + line.trim_end().len();
+        }
+        offset += line.len();
+    }
+    end
+}
+
+/// A `symbolTransformation` or `symbolIndexTransformation`, which changes the text of a symbol
+/// with substitutions like `s/^\s*(?:pub\s+)?//;`
+///
+/// Substitutions are separated by `;` or new lines and `#` starts a comment. The replacement can
+/// refer to groups with `$1` or `${1}`, and the flags `g` for replacing all matches and `i` for
+/// ignoring case are supported.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::SymbolTransformation;
+///
+/// let transformation: SymbolTransformation = r"
+///     s/^\s+//;       # leading whitespace
+///     s/(\w+)\s*\(.*/$1()/;
+/// "
+/// .parse()
+/// .unwrap();
+/// assert_eq!(transformation.apply("  main(args)"), "main()");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolTransformation {
+    source: String,
+    substitutions: Vec<Substitution>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Substitution {
+    regex: Regex,
+    replacement: Vec<ReplacementPart>,
+    global: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ReplacementPart {
+    Text(String),
+    Group(usize),
+}
+
+/// An error parsing a [`SymbolTransformation`]
+///
+/// [`SymbolTransformation`]: struct.SymbolTransformation.html
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum SymbolTransformationError {
+    #[error("Expected a substitution at byte {0}")]
+    ExpectedSubstitution(usize),
+    #[error("Unterminated substitution at byte {0}")]
+    UnterminatedSubstitution(usize),
+    #[error("Invalid regex in substitution: {0}")]
+    InvalidRegex(String),
+}
+
+impl SymbolTransformation {
+    /// The text the transformation was parsed from
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Applies the substitutions to a symbol in order
+    pub fn apply(&self, symbol: &str) -> String {
+        self.substitutions
+            .iter()
+            .fold(symbol.to_string(), |text, substitution| {
+                substitution.apply(&text)
+            })
+    }
+}
+
+impl FromStr for SymbolTransformation {
+    type Err = SymbolTransformationError;
+
+    fn from_str(s: &str) -> Result<SymbolTransformation, SymbolTransformationError> {
+        let mut substitutions = Vec::new();
+        let mut chars = s.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            match c {
+                ';' => {}
+                c if c.is_whitespace() => {}
+                '#' => while chars.next_if(|&(_, c)| c != '\n').is_some() {},
+                's' if chars.next_if(|&(_, c)| c == '/').is_some() => {
+                    let mut parts = [String::new(), String::new()];
+                    for part in &mut parts {
+                        loop {
+                            match chars.next() {
+                                Some((_, '/')) => break,
+                                // The delimiter is unescaped, other escapes are kept for the
+                                // regex and the replacement
+                                Some((_, '\\')) => match chars.next() {
+                                    Some((_, '/')) => part.push('/'),
+                                    Some((_, c)) => {
+                                        part.push('\\');
+                                        part.push(c);
+                                    }
+                                    None => part.push('\\'),
+                                },
+                                Some((_, c)) => part.push(c),
+                                None => {
+                                    return Err(
+                                        SymbolTransformationError::UnterminatedSubstitution(start),
+                                    )
+                                }
+                            }
+                        }
+                    }
+                    let [mut regex, replacement] = parts;
+                    let mut global = false;
+                    while let Some((_, flag)) = chars.next_if(|&(_, c)| c.is_ascii_alphabetic()) {
+                        match flag {
+                            'g' => global = true,
+                            'i' => regex.insert_str(0, "(?i)"),
+                            _ => {}
+                        }
+                    }
+                    if let Some(error) = Regex::try_compile(&regex) {
+                        return Err(SymbolTransformationError::InvalidRegex(error.to_string()));
+                    }
+                    substitutions.push(Substitution {
+                        regex: Regex::new(regex),
+                        replacement: parse_replacement(&replacement),
+                        global,
+                    });
+                }
+                _ => return Err(SymbolTransformationError::ExpectedSubstitution(start)),
+            }
+        }
+        Ok(SymbolTransformation {
+            source: s.to_string(),
+            substitutions,
+        })
+    }
+}
+
+fn parse_replacement(s: &str) -> Vec<ReplacementPart> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some(c) => text.push(c),
+                None => text.push('\\'),
+            },
+            '$' => {
+                let braced = chars.next_if_eq(&'{').is_some();
+                let mut digits = String::new();
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(digit);
+                }
+                let closed = !braced || chars.next_if_eq(&'}').is_some();
+                match digits.parse() {
+                    Ok(group) if closed => {
+                        if !text.is_empty() {
+                            parts.push(ReplacementPart::Text(std::mem::take(&mut text)));
+                        }
+                        parts.push(ReplacementPart::Group(group));
+                    }
+                    // Not a reference to a group, so it's kept as it is
+                    _ => {
+                        text.push('$');
+                        if braced {
+                            text.push('{');
+                        }
+                        text.push_str(&digits);
+                    }
+                }
+            }
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        parts.push(ReplacementPart::Text(text));
+    }
+    parts
+}
+
+impl Substitution {
+    fn apply(&self, text: &str) -> String {
+        let mut region = Region::new();
+        let mut result = String::new();
+        // The end of the text that was already copied or replaced
+        let mut copied = 0;
+        let mut search_start = 0;
+        while self
+            .regex
+            .search(text, search_start, text.len(), Some(&mut region))
+        {
+            let (start, end) = region.pos(0).unwrap();
+            result.push_str(&text[copied..start]);
+            for part in &self.replacement {
+                match part {
+                    ReplacementPart::Text(s) => result.push_str(s),
+                    ReplacementPart::Group(group) => {
+                        if let Some((start, end)) = region.pos(*group) {
+                            result.push_str(&text[start..end]);
+                        }
+                    }
+                }
+            }
+            copied = end;
+            if !self.global {
+                break;
+            }
+            // Empty matches would be found again at the same position
+            search_start = match text[end..].chars().next() {
+                Some(c) if start == end => end + c.len_utf8(),
+                Some(_) => end,
+                None => break,
+            };
+        }
+        result.push_str(&text[copied..]);
+        result
+    }
+}
+
+impl Serialize for SymbolTransformation {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for SymbolTransformation {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+        let source = String::deserialize(deserializer)?;
+        SymbolTransformation::from_str(&source).map_err(Error::custom)
+    }
+}
+
+#[cfg(feature = "yaml-load")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::scope_tree::tests::syntax_set;
+
+    #[test]
+    fn can_build_outline() {
+        let ss = syntax_set(&[r#"
+                name: Test
+                scope: source.test
+                contexts:
+                  main:
+                    - match: '\b(mod)\s+(\w+)\s*\{'
+                      captures:
+                        1: keyword
+                        2: entity.name.namespace
+                      push: module
+                    - match: '\b(fn)\s+(\w+)'
+                      captures:
+                        1: keyword
+                        2: entity.name.function
+                  module:
+                    - meta_scope: meta.module
+                    - match: '\}'
+                      pop: true
+                    - include: main
+                "#]);
+
+        let selectors = |s: &str| ScopeSelectors::from_str(s).unwrap();
+        let mut rules = OutlineRules::default();
+        let mut functions = SymbolRule::new(selectors("entity.name.function"));
+        functions.show_in_indexed_symbol_list = Some(false);
+        functions.symbol_transformation = Some("s/$/()/".parse().unwrap());
+        rules.rules.push(functions);
+        let mut nested = SymbolRule::new(selectors("meta.module meta.module entity.name.function"));
+        nested.show_in_symbol_list = Some(false);
+        rules.rules.push(nested);
+
+        let text = "fn a\nmod m {\n  fn b\n    x\n  mod n { fn c }\n}\nfn d\n";
+        let outline = rules.parse_outline(text, &ss.syntaxes()[0], &ss).unwrap();
+        let symbols: Vec<_> = outline
+            .symbols()
+            .iter()
+            .map(|s| {
+                (
+                    s.name.as_str(),
+                    s.index_name.as_deref(),
+                    s.range.clone(),
+                    s.parent,
+                )
+            })
+            .collect();
+        assert_eq!(
+            symbols,
+            [
+                ("a()", None, 3..4, None),
+                ("m", Some("m"), 5..44, None),
+                // Continued by the line that is indented more
+                ("b()", None, 18..25, Some(1)),
+                ("n", Some("n"), 28..42, Some(1)),
+                ("d()", None, 48..49, None),
+            ]
+        );
+        assert_eq!(outline.roots(), [0, 1, 4]);
+        assert_eq!(outline.symbols()[1].children, [2, 3]);
+        assert_eq!(outline.symbols()[1].name_range, 9..10);
+        assert_eq!(
+            outline.symbols()[1].scope,
+            Scope::new("entity.name.namespace").unwrap()
+        );
+        assert_eq!(outline.path_at(40), [1, 3]);
+        assert_eq!(outline.path_at(22), [1, 2]);
+        assert_eq!(outline.path_at(44), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn parse_symbol_transformations() {
+        let transformation: SymbolTransformation = r"s/a\/b/\$1\/${x}$/g".parse().unwrap();
+        assert_eq!(transformation.apply("a/ba/b"), "$1/${x}$$1/${x}$");
+        let transformation: SymbolTransformation = "s/^/-/g; s/$/\\n/".parse().unwrap();
+        assert_eq!(transformation.apply("ab"), "-ab\n");
+
+        assert!(matches!(
+            "s/a/b".parse::<SymbolTransformation>(),
+            Err(SymbolTransformationError::UnterminatedSubstitution(0))
+        ));
+        assert!(matches!(
+            "s/a//; y/a/b/".parse::<SymbolTransformation>(),
+            Err(SymbolTransformationError::ExpectedSubstitution(7))
+        ));
+        assert!(matches!(
+            "s/(//".parse::<SymbolTransformation>(),
+            Err(SymbolTransformationError::InvalidRegex(_))
+        ));
+    }
+}
@@ -10,16 +10,23 @@
 // See https://github.com/rust-lang/rust/blob/1.54.0/library/core/src/hash/mod.rs#L717-L725
 #![allow(clippy::mutable_key_type)]
 
+#[cfg(feature = "regex-hybrid")]
+use super::pattern_set::PatternSet;
+use super::profiler::{Profiler, SearchStats};
 use super::regex::Region;
 use super::scope::*;
 use super::syntax_definition::*;
 use crate::parsing::syntax_definition::ContextId;
 use crate::parsing::syntax_set::{SyntaxReference, SyntaxSet};
 use fnv::FnvHasher;
+use serde_derive::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::hash::BuildHasherDefault;
-use std::i32;
-use std::usize;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Errors that can occur while parsing.
 #[derive(Debug, thiserror::Error)]
@@ -35,6 +42,269 @@ pub enum ParsingError {
     BadMatchIndex(usize),
     #[error("Tried to use a ContextReference that has not bee resolved yet: {0:?}")]
     UnresolvedContextReference(ContextReference),
+    /// The cancellation flag was set, see [`ParseState::set_cancellation_flag`]
+    ///
+    /// [`ParseState::set_cancellation_flag`]: struct.ParseState.html#method.set_cancellation_flag
+    #[error("Parsing was cancelled")]
+    Cancelled,
+    /// A [`SavedParseState`] was restored with another syntax set than the one it was saved with
+    ///
+    /// [`SavedParseState`]: struct.SavedParseState.html
+    #[error("The parse state was saved with a different syntax set")]
+    SyntaxSetMismatch,
+    /// A [`SavedParseState`] is inconsistent, for example because it was modified or corrupted
+    /// after it was saved
+    ///
+    /// [`SavedParseState`]: struct.SavedParseState.html
+    #[error("The saved parse state is invalid")]
+    InvalidSavedState,
+}
+
+/// Limits for parsing a single line, so that very long lines or regexes that take very long to
+/// match can't make parsing hang. See [`ParseState::set_limits`].
+///
+/// When a limit is exceeded, the rest of the line is left with the current scope stack and
+/// [`ParseState::exceeded_limit`] says which limit it was. Parsing continues normally on the next
+/// line. This is similar to how Sublime Text doesn't highlight lines that are too long.
+///
+/// The number of searches and the time are only checked between the tokens of a line, so a single
+/// regex search that takes very long can't be interrupted.
+///
+/// [`ParseState::set_limits`]: struct.ParseState.html#method.set_limits
+/// [`ParseState::exceeded_limit`]: struct.ParseState.html#method.exceeded_limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseLimits {
+    /// Lines that are longer than this many bytes aren't parsed at all
+    pub max_line_length: Option<usize>,
+    /// The maximum number of regex searches for a line
+    pub max_searches: Option<usize>,
+    /// The maximum time to spend on a line. This uses `std::time::Instant`, which isn't available
+    /// on targets like `wasm32-unknown-unknown`.
+    pub max_duration: Option<Duration>,
+}
+
+/// A limit of [`ParseLimits`] that was exceeded
+///
+/// [`ParseLimits`]: struct.ParseLimits.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseLimit {
+    /// The line was longer than `max_line_length` and wasn't parsed
+    LineLength,
+    /// Parsing the line needed more than `max_searches` regex searches
+    Searches,
+    /// Parsing the line took longer than `max_duration`
+    Duration,
+}
+
+/// Receives the steps of the parser, see [`ParseState::parse_line_with_observer`]
+///
+/// This is implemented for closures, so a closure taking a `&ParseEvent` can be passed directly.
+///
+/// [`ParseState::parse_line_with_observer`]: struct.ParseState.html#method.parse_line_with_observer
+pub trait ParseObserver {
+    /// Called for each step, in the order the parser took them
+    fn event(&mut self, event: &ParseEvent<'_>);
+}
+
+impl<F: FnMut(&ParseEvent<'_>)> ParseObserver for F {
+    fn event(&mut self, event: &ParseEvent<'_>) {
+        self(event)
+    }
+}
+
+/// A step of the parser, for debugging syntax definitions
+///
+/// The positions are byte indexes in the line that was parsed. After a `fail` rewound into a
+/// previous line (see [`ParseState::set_branch_lookahead`]), they are in that line until the
+/// parser gets back to the current one.
+///
+/// [`ParseState::set_branch_lookahead`]: struct.ParseState.html#method.set_branch_lookahead
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseEvent<'a> {
+    /// A context was pushed onto the stack
+    ContextEntered(TracedContext<'a>),
+    /// A context was popped off the stack
+    ContextLeft(TracedContext<'a>),
+    /// A pattern matched and its operation is performed
+    PatternMatched {
+        /// The context that contains the pattern, which may be included by the current context
+        context: TracedContext<'a>,
+        /// The index of the pattern in its context
+        pattern: usize,
+        /// The regex of the pattern, before backreferences are substituted
+        regex: &'a str,
+        /// The byte range of the match
+        range: Range<usize>,
+        /// Whether the pattern is from the context itself or from a prototype
+        origin: MatchOrigin,
+    },
+    /// A pattern matched without consuming anything and would have resulted in a loop, so the
+    /// parser skipped to the next character instead. This happens for a pop right after a push at
+    /// the same position, and for a `fail` without a branch point to rewind to.
+    LoopPrevented {
+        /// The context that contains the pattern
+        context: TracedContext<'a>,
+        /// The index of the pattern in its context
+        pattern: usize,
+        /// The byte index the parser skipped from
+        position: usize,
+    },
+}
+
+/// A context in a [`ParseEvent`]
+///
+/// [`ParseEvent`]: enum.ParseEvent.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TracedContext<'a> {
+    /// The ID of the context in the syntax set
+    pub id: ContextId,
+    /// The name of the syntax the context belongs to
+    pub syntax: &'a str,
+    /// The name of the context, anonymous contexts have generated names starting with `#anon_`
+    pub name: &'a str,
+}
+
+/// Where a matched pattern came from, see [`ParseEvent::PatternMatched`]
+///
+/// [`ParseEvent::PatternMatched`]: enum.ParseEvent.html#variant.PatternMatched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MatchOrigin {
+    /// The current context
+    Context,
+    /// The `prototype` of the current context
+    Prototype,
+    /// A `with_prototype` of a pattern that pushed one of the contexts on the stack
+    WithPrototype,
+}
+
+/// A [`ParseState`] that can be saved with serde, for example to continue parsing a large file
+/// after restarting the program
+///
+/// Since the state refers to the contexts of the syntaxes by index, it can only be restored with
+/// the same syntax set it was saved with. This is checked with [`SyntaxSet::fingerprint`].
+///
+/// The limits of the state are saved, but not its cancellation flag or profiler.
+///
+/// [`ParseState`]: struct.ParseState.html
+/// [`SyntaxSet::fingerprint`]: struct.SyntaxSet.html#method.fingerprint
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{ParseState, SavedParseState, SyntaxSet};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+/// state.parse_line("/* unfinished comment\n", &ss).unwrap();
+///
+/// let json = serde_json::to_string(&state.save(&ss)).unwrap();
+/// let saved: SavedParseState = serde_json::from_str(&json).unwrap();
+/// assert_eq!(saved.restore(&ss).unwrap(), state);
+///
+/// // A state can't be restored with another syntax set
+/// let saved: SavedParseState = serde_json::from_str(&json).unwrap();
+/// assert!(saved.restore(&SyntaxSet::load_defaults_nonewlines()).is_err());
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedParseState {
+    fingerprint: u64,
+    stack: Vec<StateLevel>,
+    first_line: bool,
+    proto_starts: Vec<usize>,
+    branch_points: Vec<BranchPoint>,
+    branch_lookahead: usize,
+    branch_lines: Vec<String>,
+    limits: ParseLimits,
+}
+
+impl SavedParseState {
+    /// The [`SyntaxSet::fingerprint`] of the syntax set that the state was saved with
+    ///
+    /// [`SyntaxSet::fingerprint`]: struct.SyntaxSet.html#method.fingerprint
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Restores the state, which fails if the syntax set is not the one it was saved with, or if
+    /// the state refers to contexts, lines or positions that don't exist.
+    pub fn restore(self, syntax_set: &SyntaxSet) -> Result<ParseState, ParsingError> {
+        if self.fingerprint != syntax_set.fingerprint() {
+            return Err(ParsingError::SyntaxSetMismatch);
+        }
+        // The fingerprint could still match by accident, or the data could be corrupted
+        check_saved_stack(&self.stack, &self.proto_starts, syntax_set)?;
+        for bp in &self.branch_points {
+            check_saved_stack(&bp.stack, &bp.proto_starts, syntax_set)?;
+            // Branch points are kept for the lines after them, until they're out of the lookahead
+            let line = self.branch_lines.len().checked_sub(bp.lines_back);
+            let valid = (1..=self.branch_lookahead).contains(&bp.lines_back)
+                && line.is_some_and(|line| self.branch_lines[line].is_char_boundary(bp.pos))
+                && bp.alternative < bp.alternatives;
+            if !valid {
+                return Err(ParsingError::InvalidSavedState);
+            }
+        }
+        Ok(ParseState {
+            stack: self.stack,
+            first_line: self.first_line,
+            proto_starts: self.proto_starts,
+            branch_points: self.branch_points,
+            branch_lookahead: self.branch_lookahead,
+            branch_lines: self.branch_lines,
+            rewound_lines: None,
+            branch_retry: None,
+            limits: self.limits,
+            exceeded_limit: None,
+            line_budget: None,
+            cancellation_flag: None,
+            profiler: None,
+            trace: None,
+            match_starts: None,
+        })
+    }
+}
+
+/// Checks that the contexts of a saved stack exist and that the starts of the `with_prototype`s
+/// can be used with it
+fn check_saved_stack(
+    stack: &[StateLevel],
+    proto_starts: &[usize],
+    syntax_set: &SyntaxSet,
+) -> Result<(), ParsingError> {
+    for level in stack {
+        syntax_set.get_context(&level.context)?;
+        for prototype in &level.prototypes {
+            syntax_set.get_context(prototype)?;
+        }
+    }
+    // Starts beyond the stack are only removed from the end, so they have to be in order
+    if stack.is_empty() || proto_starts.windows(2).any(|w| w[0] > w[1]) {
+        return Err(ParsingError::InvalidSavedState);
+    }
+    Ok(())
+}
+
+/// A cancellation flag, which is compared by identity so that states with the same flag are equal
+#[derive(Debug, Clone)]
+struct CancellationFlag(Arc<AtomicBool>);
+
+impl PartialEq for CancellationFlag {
+    fn eq(&self, other: &CancellationFlag) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CancellationFlag {}
+
+/// How much of the limits is used up by the line that is being parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LineBudget {
+    /// The searches of the parts of the line that are already parsed, see `SearchCache`
+    searches: usize,
+    deadline: Option<Instant>,
 }
 
 /// Keeps the current parser state (the internal syntax interpreter stack) between lines of parsing.
@@ -46,7 +316,8 @@ pub enum ParsingError {
 ///
 /// One reason this is exposed is that since it implements `Clone` you can actually cache
 /// these (probably along with a [`HighlightState`]) and only re-start parsing from the point of a change.
-/// See the docs for [`HighlightState`] for more in-depth discussion of caching.
+/// See the docs for [`HighlightState`] for more in-depth discussion of caching, and
+/// [`easy::Document`] for a text that is kept highlighted this way while it is edited.
 ///
 /// This state doesn't keep track of the current scope stack and parsing only returns changes to this stack
 /// so if you want to construct scope stacks you'll need to keep track of that as well.
@@ -55,7 +326,28 @@ pub enum ParsingError {
 /// **Note:** Caching is for advanced users who have tons of time to maximize performance or want to do so eventually.
 /// It is not recommended that you try caching the first time you implement highlighting.
 ///
+/// # Syntax versions
+///
+/// Syntaxes with `version: 2` get Sublime Text 4's scoping rules instead of the ones of version 1:
+///
+/// - `embed_scope` replaces the top level scope of the embedded syntax instead of being combined with it
+/// - the text matched by a `set` only gets the meta scopes of the new contexts, not the ones of the
+///   context that is set away from
+/// - when pushing multiple contexts, the `clear_scopes` of a context also clears the
+///   `meta_content_scope` of the contexts pushed before it
+///
+/// # Branch points
+///
+/// A `fail` rewinds the parser to the last `branch_point` with the same name and tries the next
+/// context of its `branch`. A branch point is forgotten once the context containing it is popped.
+///
+/// By default, a `fail` can only rewind to a branch point on the same line, because the results
+/// for previous lines have already been returned. Use [`set_branch_lookahead`] if you are able to
+/// update previous lines.
+///
 /// [`HighlightState`]: ../highlighting/struct.HighlightState.html
+/// [`easy::Document`]: ../easy/struct.Document.html
+/// [`set_branch_lookahead`]: #method.set_branch_lookahead
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ParseState {
     stack: Vec<StateLevel>,
@@ -63,26 +355,177 @@ pub struct ParseState {
     // See issue #101. Contains indices of frames pushed by `with_prototype`s.
     // Doesn't look at `with_prototype`s below top of stack.
     proto_starts: Vec<usize>,
+    // Branch points that a `fail` can rewind to, oldest first.
+    branch_points: Vec<BranchPoint>,
+    // How many previous lines a `fail` can rewind into.
+    branch_lookahead: usize,
+    // The previous lines that branch points are on, the last one is the line before the current one.
+    branch_lines: Vec<String>,
+    // New results for previous lines after a `fail` rewound into them, oldest first.
+    rewound_lines: Option<Vec<Vec<(usize, ScopeStackOp)>>>,
+    // Lines back, position and alternative to use for the branch point that was rewound to.
+    // Only set while parsing a line.
+    branch_retry: Option<(usize, usize, usize)>,
+    limits: ParseLimits,
+    // The limit that the last line exceeded.
+    exceeded_limit: Option<ParseLimit>,
+    // Only set while parsing a line, if there are limits.
+    line_budget: Option<LineBudget>,
+    cancellation_flag: Option<CancellationFlag>,
+    profiler: Option<Profiler>,
+    // Only set while parsing a line with an observer.
+    trace: Option<Vec<TraceRecord>>,
+    // Only set while parsing a line with `parse_line_with_match_starts`.
+    match_starts: Option<Vec<usize>>,
 }
 
+/// A `ParseEvent` that is recorded while parsing, and reported after the line is parsed
 #[derive(Debug, Clone, Eq, PartialEq)]
+enum TraceRecord {
+    ContextEntered(ContextId),
+    ContextLeft(ContextId),
+    PatternMatched {
+        context: ContextId,
+        pattern: usize,
+        range: Range<usize>,
+        origin: MatchOrigin,
+    },
+    LoopPrevented {
+        context: ContextId,
+        pattern: usize,
+        position: usize,
+    },
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 struct StateLevel {
     context: ContextId,
     prototypes: Vec<ContextId>,
-    captures: Option<(Region, String)>,
+    // The texts of the capture groups of the match that pushed the context, by index, for the
+    // backreferences of its patterns.
+    captures: Option<Vec<Option<String>>>,
+}
+
+/// The state of the parser before the match of a `branch_point`
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+struct BranchPoint {
+    name: String,
+    /// Index of the context of the `branch` that is currently tried
+    alternative: usize,
+    alternatives: usize,
+    /// How many lines before the current one the match is on
+    lines_back: usize,
+    /// Byte index of the match in its line
+    pos: usize,
+    /// Number of ops of the line before the match
+    ops_len: usize,
+    /// Ops of the line before the match, saved when the line is finished
+    line_ops: Option<Vec<(usize, ScopeStackOp)>>,
+    stack: Vec<StateLevel>,
+    proto_starts: Vec<usize>,
+    non_consuming_push_at: (usize, usize),
+}
+
+/// The result of `ParseState::parse_next_token`
+enum TokenResult {
+    Continue,
+    EndOfLine,
+    /// A `fail` has to rewind to the branch point with this index
+    Rewind(usize),
 }
 
 #[derive(Debug)]
 struct RegexMatch<'a> {
     regions: Region,
     context: &'a Context,
+    /// The ID of `context`, which is only needed for tracing
+    context_id: Option<ContextId>,
     pat_index: usize,
-    from_with_prototype: bool,
+    origin: MatchOrigin,
     would_loop: bool,
 }
 
-/// Maps the pattern to the start index, which is -1 if not found.
-type SearchCache = HashMap<*const MatchPattern, Option<Region>, BuildHasherDefault<FnvHasher>>;
+/// The results of the regex searches while parsing the rest of a line
+struct SearchCache {
+    /// Maps the pattern to its match, which is `None` if not found.
+    regions: HashMap<*const MatchPattern, Option<Region>, BuildHasherDefault<FnvHasher>>,
+    /// The number of searches that weren't cached
+    searches: usize,
+    /// The statistics for the profiler, if there is one
+    stats: Option<HashMap<*const MatchPattern, SearchStats, BuildHasherDefault<FnvHasher>>>,
+    /// Maps the pattern set to the start of its match and the index of the pattern that matched
+    #[cfg(feature = "regex-hybrid")]
+    sets: HashMap<*const PatternSet, Option<(usize, usize)>, BuildHasherDefault<FnvHasher>>,
+}
+
+/// Searching the patterns of a [`PatternSet`] together while looking for the best match in a
+/// context
+#[cfg(feature = "regex-hybrid")]
+struct SetSearch<'a> {
+    set: Option<&'a PatternSet>,
+    /// The index of the pattern of the set that matches first, once the set has been searched
+    found: Option<Option<usize>>,
+    /// The index of the pattern of the context that is searched
+    index: Option<usize>,
+}
+
+#[cfg(feature = "regex-hybrid")]
+impl<'a> SetSearch<'a> {
+    /// Whether the next pattern of the context can't be the best match, since another pattern of
+    /// the set matches before it
+    fn skips_next(&mut self, line: &str, start: usize, search_cache: &mut SearchCache) -> bool {
+        let index = self.index.map_or(0, |index| index + 1);
+        self.index = Some(index);
+        let set = match self.set {
+            Some(set) if set.contains(index) => set,
+            _ => return false,
+        };
+        // The set is only searched once one of its patterns is reached, since a pattern before
+        // could match at the start
+        let found = match self.found {
+            Some(found) => found,
+            None => {
+                let set_ptr = set as *const PatternSet;
+                let found = match search_cache.sets.get(&set_ptr) {
+                    Some(&Some((match_start, found))) if match_start >= start => Some(found),
+                    Some(&None) => None,
+                    _ => {
+                        search_cache.searches += 1;
+                        match set.find(line, start) {
+                            Ok(found) => {
+                                if found.is_none() {
+                                    search_cache.sets.insert(set_ptr, None);
+                                }
+                                found
+                            }
+                            Err(_) => {
+                                // Search the patterns one by one instead
+                                self.set = None;
+                                return false;
+                            }
+                        }
+                    }
+                };
+                self.found = Some(found);
+                found
+            }
+        };
+        found != Some(index)
+    }
+
+    /// Remembers where the pattern of the set that matches first matches, which is only known
+    /// from searching the pattern itself
+    fn found(&self, match_start: usize, search_cache: &mut SearchCache) {
+        if let (Some(set), Some(index)) = (self.set, self.index) {
+            if self.found == Some(Some(index)) {
+                let set_ptr = set as *const PatternSet;
+                search_cache
+                    .sets
+                    .insert(set_ptr, Some((match_start, index)));
+            }
+        }
+    }
+}
 
 // To understand the implementation of this, here's an introduction to how
 // Sublime Text syntax definitions work.
@@ -189,12 +632,166 @@ impl ParseState {
             stack: vec![start_state],
             first_line: true,
             proto_starts: Vec::new(),
+            branch_points: Vec::new(),
+            branch_lookahead: 0,
+            branch_lines: Vec::new(),
+            rewound_lines: None,
+            branch_retry: None,
+            limits: ParseLimits::default(),
+            exceeded_limit: None,
+            line_budget: None,
+            cancellation_flag: None,
+            profiler: None,
+            trace: None,
+            match_starts: None,
+        }
+    }
+
+    /// Creates a state that starts in the given contexts instead of at the start of a syntax, e.g.
+    /// for highlighting a snippet that is a single expression.
+    ///
+    /// The contexts are pushed in order, so the last one is where parsing starts. They are
+    /// resolved like [`ContextReference::resolve`], e.g. parsed from `scope:source.js#expression`,
+    /// except that a plain context name is looked up in the syntax of the context before it. The
+    /// meta scopes of the contexts are applied to `scope_stack`, which can already contain the
+    /// scopes of the surrounding text. A match that pops the first context ends parsing: the rest
+    /// of its line is left without scopes and the next [`parse_line`] fails with
+    /// [`ParsingError::MissingMainContext`].
+    ///
+    /// Contexts that refer to captures of the match that pushed them don't have that match, so
+    /// these references match empty text.
+    ///
+    /// [`ContextReference::resolve`]: syntax_definition/enum.ContextReference.html#method.resolve
+    /// [`parse_line`]: #method.parse_line
+    /// [`ParsingError::MissingMainContext`]: enum.ParsingError.html#variant.MissingMainContext
+    pub fn with_contexts(
+        contexts: &[ContextReference],
+        scope_stack: &mut ScopeStack,
+        syntax_set: &SyntaxSet,
+    ) -> Result<ParseState, ParsingError> {
+        let mut stack = Vec::with_capacity(contexts.len());
+        let mut syntax_index = None;
+        for context_ref in contexts {
+            let context_id = match *context_ref {
+                ContextReference::Direct(context_id) => Some(context_id),
+                _ => syntax_set.find_context_id(context_ref, syntax_index),
+            }
+            .ok_or_else(|| ParsingError::UnresolvedContextReference(context_ref.clone()))?;
+            let context = syntax_set.get_context(&context_id)?;
+            syntax_index = Some(context_id.syntax_index);
+
+            if let Some(clear_amount) = context.clear_scopes {
+                // Only restoring can fail
+                let _ = scope_stack.apply(&ScopeStackOp::Clear(clear_amount));
+            }
+            for scope in context.meta_scope.iter().chain(&context.meta_content_scope) {
+                scope_stack.push(*scope);
+            }
+            stack.push(StateLevel {
+                context: context_id,
+                prototypes: Vec::new(),
+                captures: context.uses_backrefs.then(Vec::new),
+            });
         }
+        if stack.is_empty() {
+            return Err(ParsingError::MissingMainContext);
+        }
+        Ok(ParseState {
+            stack,
+            first_line: false,
+            proto_starts: Vec::new(),
+            branch_points: Vec::new(),
+            branch_lookahead: 0,
+            branch_lines: Vec::new(),
+            rewound_lines: None,
+            branch_retry: None,
+            limits: ParseLimits::default(),
+            exceeded_limit: None,
+            line_budget: None,
+            cancellation_flag: None,
+            profiler: None,
+            trace: None,
+            match_starts: None,
+        })
+    }
+
+    /// Allows a `fail` to rewind to a `branch_point` up to `lines` lines before the current one.
+    ///
+    /// When that happens, [`parse_line`] parses the affected lines again and their new results can
+    /// be retrieved with [`take_rewound_lines`]. Only use this if you can update previous lines.
+    /// The default of 0 only allows rewinding within a line.
+    ///
+    /// [`parse_line`]: #method.parse_line
+    /// [`take_rewound_lines`]: #method.take_rewound_lines
+    pub fn set_branch_lookahead(&mut self, lines: usize) {
+        self.branch_lookahead = lines;
+    }
+
+    /// Returns the new results for previous lines if the last call to [`parse_line`] had to parse
+    /// them again because of a `fail`, see [`set_branch_lookahead`].
+    ///
+    /// The results are in order, the last one is for the line before the one passed to
+    /// [`parse_line`]. They replace the results previously returned for these lines.
+    ///
+    /// [`parse_line`]: #method.parse_line
+    /// [`set_branch_lookahead`]: #method.set_branch_lookahead
+    pub fn take_rewound_lines(&mut self) -> Option<Vec<Vec<(usize, ScopeStackOp)>>> {
+        self.rewound_lines.take()
+    }
+
+    /// Sets the limits for parsing each line, by default there are none.
+    pub fn set_limits(&mut self, limits: ParseLimits) {
+        self.limits = limits;
+    }
+
+    /// Returns the limit that the last call to [`parse_line`] exceeded, see [`ParseLimits`].
+    ///
+    /// [`parse_line`]: #method.parse_line
+    /// [`ParseLimits`]: struct.ParseLimits.html
+    pub fn exceeded_limit(&self) -> Option<ParseLimit> {
+        self.exceeded_limit
+    }
+
+    /// Saves the state with the fingerprint of the syntax set, see [`SavedParseState`].
+    ///
+    /// [`SavedParseState`]: struct.SavedParseState.html
+    pub fn save(&self, syntax_set: &SyntaxSet) -> SavedParseState {
+        SavedParseState {
+            fingerprint: syntax_set.fingerprint(),
+            stack: self.stack.clone(),
+            first_line: self.first_line,
+            proto_starts: self.proto_starts.clone(),
+            branch_points: self.branch_points.clone(),
+            branch_lookahead: self.branch_lookahead,
+            branch_lines: self.branch_lines.clone(),
+            limits: self.limits,
+        }
+    }
+
+    /// Sets a flag that cancels parsing when it is set to `true`, for example from another thread.
+    ///
+    /// The flag is checked before each regex search, and [`parse_line`] returns
+    /// [`ParsingError::Cancelled`] once it is set. The state is then left somewhere in the middle of
+    /// the line, so it shouldn't be used to parse further lines.
+    ///
+    /// [`parse_line`]: #method.parse_line
+    /// [`ParsingError::Cancelled`]: enum.ParsingError.html#variant.Cancelled
+    pub fn set_cancellation_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.cancellation_flag = Some(CancellationFlag(flag));
+    }
+
+    /// Collects statistics about the regex searches of each pattern in `profiler`, see
+    /// [`Profiler`].
+    ///
+    /// [`Profiler`]: struct.Profiler.html
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
     }
 
     /// Parses a single line of the file. Because of the way regex engines work you unfortunately
     /// have to pass in a single line contiguous in memory. This can be bad for really long lines.
     /// Sublime Text avoids this by just not highlighting lines that are too long (thousands of characters).
+    /// Use [`set_limits`] to do the same.
     ///
     /// For efficiency reasons this returns only the changes to the current scope at each point in the line.
     /// You can use [`ScopeStack::apply`] on each operation in succession to get the stack for a given point.
@@ -208,6 +805,7 @@ impl ParseState {
     /// wrong result or even panic. The reason for this is that contexts within the [`SyntaxSet`]
     /// are referenced via indexes.
     ///
+    /// [`set_limits`]: #method.set_limits
     /// [`ScopeStack::apply`]: struct.ScopeStack.html#method.apply
     /// [`SyntaxSet`]: struct.SyntaxSet.html
     /// [`ParseState`]: struct.ParseState.html
@@ -231,36 +829,244 @@ impl ParseState {
             self.first_line = false;
         }
 
-        let mut regions = Region::new();
-        let fnv = BuildHasherDefault::<FnvHasher>::default();
-        let mut search_cache: SearchCache = HashMap::with_capacity_and_hasher(128, fnv);
+        self.exceeded_limit = None;
+        if self.limits != ParseLimits::default() {
+            if self
+                .limits
+                .max_line_length
+                .is_some_and(|max| line.len() > max)
+            {
+                // Nothing is parsed, since the limit is checked before each token
+                self.exceeded_limit = Some(ParseLimit::LineLength);
+            }
+            self.line_budget = Some(LineBudget {
+                searches: 0,
+                deadline: self.limits.max_duration.map(|d| Instant::now() + d),
+            });
+        }
+
         // Used for detecting loops with push/pop, see long comment above.
         let mut non_consuming_push_at = (0, 0);
+        // The line being parsed as the number of lines before `line`, only non-zero after a
+        // `fail` rewound to a previous line
+        let mut lines_back = 0;
+        // New results for previous lines, the first one is `rewound_from` lines back
+        let mut rewound_lines = Vec::new();
+        let mut rewound_from = 0;
+        self.rewound_lines = None;
+
+        loop {
+            let cur_line = if lines_back == 0 {
+                Cow::Borrowed(line)
+            } else {
+                Cow::Owned(self.branch_lines[self.branch_lines.len() - lines_back].clone())
+            };
+            let rewind = self.parse_rest_of_line(
+                &cur_line,
+                lines_back,
+                syntax_set,
+                &mut match_start,
+                &mut non_consuming_push_at,
+                &mut res,
+            );
+            let rewind = match rewind {
+                Ok(rewind) => rewind,
+                Err(e) => {
+                    self.line_budget = None;
+                    return Err(e);
+                }
+            };
+            if let Some(index) = rewind {
+                let bp = self.branch_points.split_off(index).swap_remove(0);
+                if bp.lines_back > lines_back {
+                    if !rewound_lines.is_empty() && bp.lines_back <= rewound_from {
+                        rewound_lines.truncate(rewound_from - bp.lines_back);
+                    } else {
+                        rewound_lines.clear();
+                        rewound_from = bp.lines_back;
+                    }
+                    res = bp.line_ops.unwrap_or_default();
+                } else {
+                    res.truncate(bp.ops_len);
+                }
+                // The matches of previous lines aren't needed, since their ops aren't returned
+                if let Some(ref mut starts) = self.match_starts {
+                    starts.retain(|&start| bp.lines_back == 0 && start < res.len());
+                }
+                lines_back = bp.lines_back;
+                match_start = bp.pos;
+                non_consuming_push_at = bp.non_consuming_push_at;
+                self.stack = bp.stack;
+                self.proto_starts = bp.proto_starts;
+                self.branch_retry = Some((bp.lines_back, bp.pos, bp.alternative + 1));
+            } else if lines_back > 0 {
+                self.finish_branch_line(lines_back, &res);
+                rewound_lines.push(std::mem::take(&mut res));
+                if let Some(ref mut starts) = self.match_starts {
+                    starts.clear();
+                }
+                lines_back -= 1;
+                match_start = 0;
+                non_consuming_push_at = (0, 0);
+            } else {
+                break;
+            }
+        }
 
-        while self.parse_next_token(
-            line,
-            syntax_set,
-            &mut match_start,
-            &mut search_cache,
-            &mut regions,
-            &mut non_consuming_push_at,
-            &mut res,
-        )? {}
+        self.line_budget = None;
+        self.finish_branch_line(0, &res);
+        if !rewound_lines.is_empty() {
+            self.rewound_lines = Some(rewound_lines);
+        }
+        let lookahead = self.branch_lookahead;
+        for bp in self.branch_points.iter_mut() {
+            bp.lines_back += 1;
+        }
+        self.branch_points.retain(|bp| bp.lines_back <= lookahead);
+        let needed_lines = self
+            .branch_points
+            .iter()
+            .map(|bp| bp.lines_back)
+            .max()
+            .unwrap_or(0);
+        if needed_lines > 0 {
+            self.branch_lines.push(line.to_owned());
+        }
+        let unneeded_lines = self.branch_lines.len().saturating_sub(needed_lines);
+        self.branch_lines.drain(..unneeded_lines);
 
         Ok(res)
     }
 
+    /// Parses a line like [`parse_line`], and reports each step of the parser to `observer`.
+    ///
+    /// This is for debugging syntax definitions: the events say which context and pattern
+    /// produced each token, which the scope ops don't. They are reported in order once the line
+    /// is parsed, even if parsing fails. If parsing fails, its error is returned rather than one
+    /// that occurred while reporting the events.
+    ///
+    /// [`parse_line`]: #method.parse_line
+    pub fn parse_line_with_observer(
+        &mut self,
+        line: &str,
+        syntax_set: &SyntaxSet,
+        observer: &mut dyn ParseObserver,
+    ) -> Result<Vec<(usize, ScopeStackOp)>, ParsingError> {
+        self.trace = Some(Vec::new());
+        let result = self.parse_line(line, syntax_set);
+        let mut event_result = Ok(());
+        for record in self.trace.take().unwrap_or_default() {
+            match record.to_event(syntax_set) {
+                Ok(event) => observer.event(&event),
+                Err(e) => {
+                    event_result = Err(e);
+                    break;
+                }
+            }
+        }
+        // An error of the parser is more relevant than one while reporting its steps
+        let ops = result?;
+        event_result.map(|_| ops)
+    }
+
+    /// Parses a line like [`parse_line`], and sets `match_starts` to the indexes of the ops where
+    /// each match starts, for callers that have to tell the ops of separate matches apart
+    ///
+    /// [`parse_line`]: #method.parse_line
+    pub(crate) fn parse_line_with_match_starts(
+        &mut self,
+        line: &str,
+        syntax_set: &SyntaxSet,
+        match_starts: &mut Vec<usize>,
+    ) -> Result<Vec<(usize, ScopeStackOp)>, ParsingError> {
+        match_starts.clear();
+        self.match_starts = Some(std::mem::take(match_starts));
+        let result = self.parse_line(line, syntax_set);
+        *match_starts = self.match_starts.take().unwrap_or_default();
+        result
+    }
+
+    /// Parses `line` from `start` to the end, unless a `fail` has to rewind to the branch point
+    /// with the returned index
+    fn parse_rest_of_line(
+        &mut self,
+        line: &str,
+        lines_back: usize,
+        syntax_set: &SyntaxSet,
+        start: &mut usize,
+        non_consuming_push_at: &mut (usize, usize),
+        ops: &mut Vec<(usize, ScopeStackOp)>,
+    ) -> Result<Option<usize>, ParsingError> {
+        let mut regions = Region::new();
+        let fnv = BuildHasherDefault::<FnvHasher>::default();
+        let mut search_cache = SearchCache {
+            regions: HashMap::with_capacity_and_hasher(128, fnv),
+            searches: 0,
+            stats: self.profiler.as_ref().map(|_| HashMap::default()),
+            #[cfg(feature = "regex-hybrid")]
+            sets: HashMap::default(),
+        };
+
+        let result = loop {
+            match self.parse_next_token(
+                line,
+                lines_back,
+                syntax_set,
+                start,
+                &mut search_cache,
+                &mut regions,
+                non_consuming_push_at,
+                ops,
+            ) {
+                Ok(TokenResult::Continue) => {}
+                Ok(TokenResult::EndOfLine) => break Ok(None),
+                Ok(TokenResult::Rewind(index)) => break Ok(Some(index)),
+                Err(e) => break Err(e),
+            }
+        };
+        // The searches count even if parsing failed, for example because it was cancelled
+        if let Some(ref mut budget) = self.line_budget {
+            budget.searches += search_cache.searches;
+        }
+        if let (Some(profiler), Some(stats)) = (&self.profiler, &search_cache.stats) {
+            profiler.add(stats);
+        }
+        result
+    }
+
+    /// Saves the ops before the branch points on a line that was parsed completely, so that a
+    /// `fail` on a later line can rewind to them
+    fn finish_branch_line(&mut self, lines_back: usize, ops: &[(usize, ScopeStackOp)]) {
+        if self.branch_lookahead == 0 {
+            return;
+        }
+        for bp in self.branch_points.iter_mut() {
+            if bp.lines_back == lines_back && bp.line_ops.is_none() {
+                bp.line_ops = Some(ops[..bp.ops_len].to_vec());
+            }
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn parse_next_token(
         &mut self,
         line: &str,
+        lines_back: usize,
         syntax_set: &SyntaxSet,
         start: &mut usize,
         search_cache: &mut SearchCache,
         regions: &mut Region,
         non_consuming_push_at: &mut (usize, usize),
         ops: &mut Vec<(usize, ScopeStackOp)>,
-    ) -> Result<bool, ParsingError> {
+    ) -> Result<TokenResult, ParsingError> {
+        if self.limit_exceeded(search_cache.searches) {
+            return Ok(TokenResult::EndOfLine);
+        }
+        // Everything was popped, the next line fails with `MissingMainContext`
+        if self.stack.is_empty() {
+            return Ok(TokenResult::EndOfLine);
+        }
+
         let check_pop_loop = {
             let (pos, stack_depth) = *non_consuming_push_at;
             pos == *start && stack_depth == self.stack.len()
@@ -296,31 +1102,67 @@ impl ParseState {
                 // loop.
 
                 // println!("pop_would_loop for match {:?}, start {}", reg_match, *start);
-
-                // nth(1) gets the next character if there is one. Need to do
-                // this instead of just += 1 because we have byte indices and
-                // unicode characters can be more than 1 byte.
-                if let Some((i, _)) = line[*start..].char_indices().nth(1) {
-                    *start += i;
-                    return Ok(true);
-                } else {
-                    // End of line, no character to advance and no point trying
-                    // any more patterns.
-                    return Ok(false);
-                }
+                self.record_match(&reg_match, Some(*start));
+                return Ok(Self::advance_one_char(line, start));
             }
 
             let match_end = reg_match.regions.pos(0).unwrap().1;
-
             let consuming = match_end > *start;
+            self.record_match(&reg_match, None);
+
+            let match_pattern = reg_match.context.match_at(reg_match.pat_index)?;
+            let operation = match match_pattern.operation {
+                MatchOperation::Fail(ref name) => {
+                    if let Some(index) = self.branch_point_to_rewind(name) {
+                        return Ok(TokenResult::Rewind(index));
+                    }
+                    // Without a branch point to rewind to, a `fail` does nothing. It
+                    // mustn't match again at the same position though.
+                    if !consuming {
+                        self.record_match(&reg_match, Some(*start));
+                        return Ok(Self::advance_one_char(line, start));
+                    }
+                    Cow::Owned(MatchOperation::None)
+                }
+                MatchOperation::Branch {
+                    ref name,
+                    ref contexts,
+                } => {
+                    let alternative = match self.branch_retry.take() {
+                        Some((retry_lines_back, pos, alternative))
+                            if retry_lines_back == lines_back && pos == *start =>
+                        {
+                            alternative
+                        }
+                        _ => 0,
+                    };
+                    self.branch_points.push(BranchPoint {
+                        name: name.clone(),
+                        alternative,
+                        alternatives: contexts.len(),
+                        lines_back,
+                        pos: *start,
+                        ops_len: ops.len(),
+                        line_ops: None,
+                        stack: self.stack.clone(),
+                        proto_starts: self.proto_starts.clone(),
+                        non_consuming_push_at: *non_consuming_push_at,
+                    });
+                    // Only a restored state with another branch can have too many alternatives
+                    let context = contexts
+                        .get(alternative)
+                        .ok_or(ParsingError::InvalidSavedState)?;
+                    Cow::Owned(MatchOperation::Push(vec![context.clone()]))
+                }
+                ref op => Cow::Borrowed(op),
+            };
+
             if !consuming {
                 // The match doesn't consume any characters. If this is a
                 // "push", remember the position and stack size so that we can
                 // check the next "pop" for loops. Otherwise leave the state,
                 // e.g. non-consuming "set" could also result in a loop.
-                let context = reg_match.context;
-                let match_pattern = context.match_at(reg_match.pat_index)?;
-                if let MatchOperation::Push(_) = match_pattern.operation {
+                if let MatchOperation::Push(_) = *operation {
                     *non_consuming_push_at = (match_end, self.stack.len() + 1);
                 }
             }
@@ -328,7 +1170,7 @@ impl ParseState {
             *start = match_end;
 
             // ignore `with_prototype`s below this if a context is pushed
-            if reg_match.from_with_prototype {
+            if reg_match.origin == MatchOrigin::WithPrototype {
                 // use current height, since we're before the actual push
                 self.proto_starts.push(self.stack.len());
             }
@@ -337,11 +1179,89 @@ impl ParseState {
                 let id = &self.stack[self.stack.len() - 1].context;
                 syntax_set.get_context(id)?
             };
-            self.exec_pattern(line, &reg_match, level_context, syntax_set, ops)?;
+            self.exec_pattern(line, &reg_match, &operation, level_context, syntax_set, ops)?;
+
+            Ok(TokenResult::Continue)
+        } else {
+            Ok(TokenResult::EndOfLine)
+        }
+    }
+
+    /// Checks the limits for the current line, remembering the first one that is exceeded
+    fn limit_exceeded(&mut self, uncounted_searches: usize) -> bool {
+        if self.exceeded_limit.is_some() {
+            return true;
+        }
+        let budget = match self.line_budget {
+            Some(ref budget) => budget,
+            None => return false,
+        };
+        if self
+            .limits
+            .max_searches
+            .is_some_and(|max| budget.searches + uncounted_searches > max)
+        {
+            self.exceeded_limit = Some(ParseLimit::Searches);
+        } else if budget
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.exceeded_limit = Some(ParseLimit::Duration);
+        }
+        self.exceeded_limit.is_some()
+    }
 
-            Ok(true)
+    /// Records a `PatternMatched` event, or a `LoopPrevented` one at the given position
+    fn record_match(&mut self, reg_match: &RegexMatch<'_>, loop_prevented_at: Option<usize>) {
+        let trace = match self.trace {
+            Some(ref mut trace) => trace,
+            None => return,
+        };
+        let context = match reg_match.context_id {
+            Some(id) => id,
+            None => return,
+        };
+        let pattern = reg_match.pat_index;
+        trace.push(match loop_prevented_at {
+            Some(position) => TraceRecord::LoopPrevented {
+                context,
+                pattern,
+                position,
+            },
+            None => {
+                let (match_start, match_end) = reg_match.regions.pos(0).unwrap();
+                TraceRecord::PatternMatched {
+                    context,
+                    pattern,
+                    range: match_start..match_end,
+                    origin: reg_match.origin,
+                }
+            }
+        });
+    }
+
+    fn advance_one_char(line: &str, start: &mut usize) -> TokenResult {
+        // nth(1) gets the next character if there is one. Need to do
+        // this instead of just += 1 because we have byte indices and
+        // unicode characters can be more than 1 byte.
+        if let Some((i, _)) = line[*start..].char_indices().nth(1) {
+            *start += i;
+            TokenResult::Continue
         } else {
-            Ok(false)
+            // End of line, no character to advance and no point trying
+            // any more patterns.
+            TokenResult::EndOfLine
+        }
+    }
+
+    /// Returns the index of the branch point a `fail` rewinds to, if it has another alternative
+    fn branch_point_to_rewind(&self, name: &str) -> Option<usize> {
+        let index = self.branch_points.iter().rposition(|bp| bp.name == name)?;
+        let bp = &self.branch_points[index];
+        if bp.alternative + 1 < bp.alternatives {
+            Some(index)
+        } else {
+            None
         }
     }
 
@@ -369,11 +1289,17 @@ impl ParseState {
             let with_prototypes = self.stack[proto_start..].iter().flat_map(|lvl| {
                 lvl.prototypes
                     .iter()
-                    .map(move |ctx| (true, ctx, lvl.captures.as_ref()))
+                    .map(move |ctx| (MatchOrigin::WithPrototype, ctx, lvl.captures.as_ref()))
             });
-            let cur_prototype = prototype.into_iter().map(|ctx| (false, ctx, None));
-            let cur_context =
-                Some((false, &cur_level.context, cur_level.captures.as_ref())).into_iter();
+            let cur_prototype = prototype
+                .into_iter()
+                .map(|ctx| (MatchOrigin::Prototype, ctx, None));
+            let cur_context = Some((
+                MatchOrigin::Context,
+                &cur_level.context,
+                cur_level.captures.as_ref(),
+            ))
+            .into_iter();
             with_prototypes.chain(cur_prototype).chain(cur_context)
         };
 
@@ -384,14 +1310,39 @@ impl ParseState {
         let mut best_match: Option<RegexMatch<'_>> = None;
         let mut pop_would_loop = false;
 
-        for (from_with_proto, ctx, captures) in context_chain {
-            for (pat_context, pat_index) in context_iter(syntax_set, syntax_set.get_context(ctx)?) {
+        for (origin, ctx, captures) in context_chain {
+            // Searching the patterns of a pattern set together finds the only one of them that
+            // can be the best match. The profiler needs the searches of all patterns though.
+            #[cfg(feature = "regex-hybrid")]
+            let mut set_search = SetSearch {
+                set: syntax_set
+                    .get_pattern_set(ctx)?
+                    .filter(|_| !check_pop_loop && self.profiler.is_none()),
+                found: None,
+                index: None,
+            };
+
+            let mut patterns =
+                context_iter(syntax_set, syntax_set.get_context(ctx)?).with_context_id(*ctx);
+            while let Some((pat_context, pat_index)) = patterns.next() {
+                #[cfg(feature = "regex-hybrid")]
+                if set_search.skips_next(line, start, search_cache) {
+                    continue;
+                }
+
                 let match_pat = pat_context.match_at(pat_index)?;
+                if let Some(CancellationFlag(ref flag)) = self.cancellation_flag {
+                    if flag.load(Ordering::Relaxed) {
+                        return Err(ParsingError::Cancelled);
+                    }
+                }
 
                 if let Some(match_region) =
                     self.search(line, start, match_pat, captures, search_cache, regions)
                 {
                     let (match_start, match_end) = match_region.pos(0).unwrap();
+                    #[cfg(feature = "regex-hybrid")]
+                    set_search.found(match_start, search_cache);
 
                     // println!("matched pattern {:?} at start {} end {}", match_pat.regex_str, match_start, match_end);
 
@@ -407,13 +1358,17 @@ impl ParseState {
                         let consuming = match_end > start;
                         pop_would_loop = check_pop_loop
                             && !consuming
-                            && matches!(match_pat.operation, MatchOperation::Pop);
+                            && matches!(
+                                match_pat.operation,
+                                MatchOperation::Pop | MatchOperation::PopMultiple(_)
+                            );
 
                         best_match = Some(RegexMatch {
                             regions: match_region,
                             context: pat_context,
+                            context_id: patterns.context_id(),
                             pat_index,
-                            from_with_prototype: from_with_proto,
+                            origin,
                             would_loop: pop_would_loop,
                         });
 
@@ -434,40 +1389,65 @@ impl ParseState {
         line: &str,
         start: usize,
         match_pat: &MatchPattern,
-        captures: Option<&(Region, String)>,
+        captures: Option<&Vec<Option<String>>>,
         search_cache: &mut SearchCache,
         regions: &mut Region,
     ) -> Option<Region> {
         // println!("{} - {:?} - {:?}", match_pat.regex_str, match_pat.has_captures, cur_level.captures.is_some());
         let match_ptr = match_pat as *const MatchPattern;
 
-        if let Some(maybe_region) = search_cache.get(&match_ptr) {
+        if let Some(maybe_region) = search_cache.regions.get(&match_ptr) {
             if let Some(ref region) = *maybe_region {
                 let match_start = region.pos(0).unwrap().0;
                 if match_start >= start {
                     // Cached match is valid, return it. Otherwise do another
                     // search below.
+                    if let Some(ref mut stats) = search_cache.stats {
+                        stats.entry(match_ptr).or_default().cache_hits += 1;
+                    }
                     return Some(region.clone());
                 }
             } else {
                 // Didn't find a match earlier, so no point trying to match it again
+                if let Some(ref mut stats) = search_cache.stats {
+                    stats.entry(match_ptr).or_default().cache_hits += 1;
+                }
                 return None;
             }
         }
 
+        // Skip the positions where the regex can't match. The captures don't matter for this,
+        // since backreferences can't be part of the literals that the matches start with.
+        let begin = match match_pat.regex().first_possible_match(line, start) {
+            Some(begin) => begin,
+            None => {
+                if !(match_pat.has_captures && captures.is_some()) {
+                    search_cache.regions.insert(match_pat, None);
+                }
+                return None;
+            }
+        };
+
+        search_cache.searches += 1;
+        let search_started = search_cache.stats.as_ref().map(|_| Instant::now());
         let (matched, can_cache) = match (match_pat.has_captures, captures) {
             (true, Some(captures)) => {
-                let (region, s) = captures;
-                let regex = match_pat.regex_with_refs(region, s);
-                let matched = regex.search(line, start, line.len(), Some(regions));
+                let regex = match_pat.regex_with_captures(captures);
+                let matched = regex.search(line, begin, line.len(), Some(regions));
                 (matched, false)
             }
             _ => {
                 let regex = match_pat.regex();
-                let matched = regex.search(line, start, line.len(), Some(regions));
+                let matched = regex.search(line, begin, line.len(), Some(regions));
                 (matched, true)
             }
         };
+        if let (Some(started), Some(stats)) = (search_started, &mut search_cache.stats) {
+            let stats = stats.entry(match_ptr).or_default();
+            stats.searches += 1;
+            stats.hits += usize::from(matched);
+            stats.time += started.elapsed();
+        }
 
         if matched {
             let (match_start, match_end) = regions.pos(0).unwrap();
@@ -477,14 +1457,16 @@ impl ParseState {
                 _ => true,
             };
             if can_cache && does_something {
-                search_cache.insert(match_pat, Some(regions.clone()));
+                search_cache
+                    .regions
+                    .insert(match_pat, Some(regions.clone()));
             }
             if does_something {
                 // print!("catch {} at {} on {}", match_pat.regex_str, match_start, line);
                 return Some(regions.clone());
             }
         } else if can_cache {
-            search_cache.insert(match_pat, None);
+            search_cache.regions.insert(match_pat, None);
         }
         None
     }
@@ -494,6 +1476,7 @@ impl ParseState {
         &mut self,
         line: &str,
         reg_match: &RegexMatch<'a>,
+        operation: &MatchOperation,
         level_context: &'a Context,
         syntax_set: &'a SyntaxSet,
         ops: &mut Vec<(usize, ScopeStackOp)>,
@@ -503,14 +1486,10 @@ impl ParseState {
         let pat = context.match_at(reg_match.pat_index)?;
         // println!("running pattern {:?} on '{}' at {}, operation {:?}", pat.regex_str, line, match_start, pat.operation);
 
-        self.push_meta_ops(
-            true,
-            match_start,
-            level_context,
-            &pat.operation,
-            syntax_set,
-            ops,
-        )?;
+        if let Some(ref mut starts) = self.match_starts {
+            starts.push(ops.len());
+        }
+        self.push_meta_ops(true, match_start, level_context, operation, syntax_set, ops)?;
         for s in &pat.scope {
             // println!("pushing {:?} at {}", s, match_start);
             ops.push((match_start, ScopeStackOp::Push(*s)));
@@ -536,7 +1515,7 @@ impl ParseState {
                     map.push(((cap_end, i32::MIN), ScopeStackOp::Pop(scopes.len())));
                 }
             }
-            map.sort_by(|a, b| a.0.cmp(&b.0));
+            map.sort_by_key(|a| a.0);
             for ((index, _), op) in map.into_iter() {
                 ops.push((index, op));
             }
@@ -545,16 +1524,9 @@ impl ParseState {
             // println!("popping at {}", match_end);
             ops.push((match_end, ScopeStackOp::Pop(pat.scope.len())));
         }
-        self.push_meta_ops(
-            false,
-            match_end,
-            level_context,
-            &pat.operation,
-            syntax_set,
-            ops,
-        )?;
+        self.push_meta_ops(false, match_end, level_context, operation, syntax_set, ops)?;
 
-        self.perform_op(line, &reg_match.regions, pat, syntax_set)
+        self.perform_op(line, &reg_match.regions, pat, operation, syntax_set)
     }
 
     fn push_meta_ops(
@@ -570,7 +1542,35 @@ impl ParseState {
         //          match_op,
         //          initial);
         // println!("{:?}", cur_context.meta_scope);
+        let version = {
+            let id = &self.stack[self.stack.len() - 1].context;
+            syntax_set.syntaxes()[id.syntax_index].version()
+        };
         match *match_op {
+            // When popping multiple contexts, the matched text gets the meta scopes of all of them
+            MatchOperation::PopMultiple(count) => {
+                let contexts = self.top_contexts(count, syntax_set)?;
+                if initial {
+                    Self::push_unapply_meta_ops(index, &contexts, ops);
+                    for ctx in contexts.iter().rev() {
+                        if let Some(clear_amount) = ctx.clear_scopes {
+                            ops.push((index, ScopeStackOp::Clear(clear_amount)));
+                        }
+                        for scope in ctx.meta_scope.iter() {
+                            ops.push((index, ScopeStackOp::Push(*scope)));
+                        }
+                    }
+                } else {
+                    for ctx in contexts {
+                        if !ctx.meta_scope.is_empty() {
+                            ops.push((index, ScopeStackOp::Pop(ctx.meta_scope.len())));
+                        }
+                        if ctx.clear_scopes.is_some() {
+                            ops.push((index, ScopeStackOp::Restore));
+                        }
+                    }
+                }
+            }
             MatchOperation::Pop => {
                 let v = if initial {
                     &cur_context.meta_content_scope
@@ -586,6 +1586,30 @@ impl ParseState {
                     ops.push((index, ScopeStackOp::Restore))
                 }
             }
+            // Version 2 syntaxes don't have the quirks of version 1 described below:
+            // - a `set` is a `pop` of the current context followed by a `push`, so the matched
+            //   text only gets the meta scopes of the new contexts
+            // - when multiple contexts are pushed, they are applied one after another, so the
+            //   `clear_scopes` of a context also clears the `meta_content_scope` of the contexts
+            //   below it
+            MatchOperation::Push(ref context_refs) | MatchOperation::Set(ref context_refs)
+                if version >= 2 =>
+            {
+                let num_popped = if let MatchOperation::Set(_) = *match_op {
+                    1
+                } else {
+                    0
+                };
+                self.push_meta_ops_v2(initial, index, num_popped, context_refs, syntax_set, ops)?;
+            }
+            // `pop` combined with `push` or `set` only exists in version 2, so it always behaves
+            // like a version 2 `set`
+            MatchOperation::PopAndPush(count, ref context_refs) => {
+                self.push_meta_ops_v2(initial, index, count, context_refs, syntax_set, ops)?;
+            }
+            MatchOperation::PopAndSet(count, ref context_refs) => {
+                self.push_meta_ops_v2(initial, index, count + 1, context_refs, syntax_set, ops)?;
+            }
             // for some reason the ST3 behaviour of set is convoluted and is inconsistent with the docs and other ops
             // - the meta_content_scope of the current context is applied to the matched thing, unlike pop
             // - the clear_scopes are applied after the matched token, unlike push
@@ -664,10 +1688,113 @@ impl ParseState {
                     }
                 }
             }
-            MatchOperation::None => (),
+            // `branch_point` and `fail` are turned into the operations they perform before
+            MatchOperation::None | MatchOperation::Branch { .. } | MatchOperation::Fail(_) => (),
+        }
+
+        Ok(())
+    }
+
+    /// Meta scope ops for popping `num_popped` contexts and then pushing `context_refs` the way
+    /// version 2 syntaxes do it
+    fn push_meta_ops_v2(
+        &self,
+        initial: bool,
+        index: usize,
+        num_popped: usize,
+        context_refs: &[ContextReference],
+        syntax_set: &SyntaxSet,
+        ops: &mut Vec<(usize, ScopeStackOp)>,
+    ) -> Result<(), ParsingError> {
+        if initial {
+            let popped = self.top_contexts(num_popped, syntax_set)?;
+            Self::push_unapply_meta_ops(index, &popped, ops);
+            for r in context_refs.iter() {
+                let ctx = r.resolve(syntax_set)?;
+                if let Some(clear_amount) = ctx.clear_scopes {
+                    ops.push((index, ScopeStackOp::Clear(clear_amount)));
+                }
+                for scope in ctx.meta_scope.iter() {
+                    ops.push((index, ScopeStackOp::Push(*scope)));
+                }
+            }
+        } else {
+            let contexts = context_refs
+                .iter()
+                .map(|r| r.resolve(syntax_set))
+                .collect::<Result<Vec<_>, _>>()?;
+            if contexts
+                .iter()
+                .any(|ctx| !ctx.meta_content_scope.is_empty())
+            {
+                // undo what was applied to the matched text in reverse order, then apply
+                // each context including its meta content scope
+                for ctx in contexts.iter().rev() {
+                    if !ctx.meta_scope.is_empty() {
+                        ops.push((index, ScopeStackOp::Pop(ctx.meta_scope.len())));
+                    }
+                    if ctx.clear_scopes.is_some() {
+                        ops.push((index, ScopeStackOp::Restore));
+                    }
+                }
+                for ctx in contexts {
+                    if let Some(clear_amount) = ctx.clear_scopes {
+                        ops.push((index, ScopeStackOp::Clear(clear_amount)));
+                    }
+                    for scope in ctx.meta_scope.iter() {
+                        ops.push((index, ScopeStackOp::Push(*scope)));
+                    }
+                    for scope in ctx.meta_content_scope.iter() {
+                        ops.push((index, ScopeStackOp::Push(*scope)));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the meta scopes of the given contexts, topmost first, and restores the scopes they
+    /// cleared
+    fn push_unapply_meta_ops(
+        index: usize,
+        contexts: &[&Context],
+        ops: &mut Vec<(usize, ScopeStackOp)>,
+    ) {
+        for ctx in contexts {
+            let num_to_pop = ctx.meta_content_scope.len() + ctx.meta_scope.len();
+            if num_to_pop > 0 {
+                ops.push((index, ScopeStackOp::Pop(num_to_pop)));
+            }
+            if ctx.clear_scopes.is_some() {
+                ops.push((index, ScopeStackOp::Restore));
+            }
+        }
+    }
+
+    /// Returns the contexts of the `count` topmost levels of the stack, topmost first
+    fn top_contexts<'a>(
+        &self,
+        count: usize,
+        syntax_set: &'a SyntaxSet,
+    ) -> Result<Vec<&'a Context>, ParsingError> {
+        self.stack
+            .iter()
+            .rev()
+            .take(count)
+            .map(|level| syntax_set.get_context(&level.context))
+            .collect()
+    }
+
+    /// Pops `count` contexts, forgetting the branch points in them
+    fn pop_contexts(&mut self, count: usize) {
+        let depth = self.stack.len().saturating_sub(count);
+        if let Some(ref mut trace) = self.trace {
+            for level in self.stack[depth..].iter().rev() {
+                trace.push(TraceRecord::ContextLeft(level.context));
+            }
         }
-
-        Ok(())
+        self.stack.truncate(depth);
+        self.branch_points.retain(|bp| bp.stack.len() <= depth);
     }
 
     /// Returns true if the stack was changed
@@ -676,21 +1803,36 @@ impl ParseState {
         line: &str,
         regions: &Region,
         pat: &MatchPattern,
+        operation: &MatchOperation,
         syntax_set: &SyntaxSet,
     ) -> Result<bool, ParsingError> {
-        let (ctx_refs, old_proto_ids) = match pat.operation {
+        let (ctx_refs, old_proto_ids) = match *operation {
             MatchOperation::Push(ref ctx_refs) => (ctx_refs, None),
             MatchOperation::Set(ref ctx_refs) => {
                 // a `with_prototype` stays active when the context is `set`
                 // until the context layer in the stack (where the `with_prototype`
                 // was initially applied) is popped off.
-                (ctx_refs, self.stack.pop().map(|s| s.prototypes))
+                (ctx_refs, self.pop_level().map(|s| s.prototypes))
+            }
+            MatchOperation::PopAndPush(count, ref ctx_refs) => {
+                self.pop_contexts(count);
+                (ctx_refs, None)
+            }
+            MatchOperation::PopAndSet(count, ref ctx_refs) => {
+                self.pop_contexts(count);
+                (ctx_refs, self.pop_level().map(|s| s.prototypes))
             }
             MatchOperation::Pop => {
-                self.stack.pop();
+                self.pop_contexts(1);
+                return Ok(true);
+            }
+            MatchOperation::PopMultiple(count) => {
+                self.pop_contexts(count);
                 return Ok(true);
             }
-            MatchOperation::None => return Ok(false),
+            MatchOperation::None | MatchOperation::Branch { .. } | MatchOperation::Fail(_) => {
+                return Ok(false)
+            }
         };
         for (i, r) in ctx_refs.iter().enumerate() {
             let mut proto_ids = if i == 0 {
@@ -721,11 +1863,20 @@ impl ParseState {
                             .any(|id| syntax_set.get_context(id).unwrap().uses_backrefs);
                 }
                 if uses_backrefs {
-                    Some((regions.clone(), line.to_owned()))
+                    // Backreferences can only refer to the groups 0 to 9
+                    let captures = (0..10).map(|i| {
+                        regions
+                            .pos(i)
+                            .map(|(start, end)| line[start..end].to_owned())
+                    });
+                    Some(captures.collect())
                 } else {
                     None
                 }
             };
+            if let Some(ref mut trace) = self.trace {
+                trace.push(TraceRecord::ContextEntered(context_id));
+            }
             self.stack.push(StateLevel {
                 context: context_id,
                 prototypes: proto_ids,
@@ -734,12 +1885,69 @@ impl ParseState {
         }
         Ok(true)
     }
+
+    /// Pops the top level of the stack for a `set`, which keeps the branch points in it
+    fn pop_level(&mut self) -> Option<StateLevel> {
+        let level = self.stack.pop()?;
+        if let Some(ref mut trace) = self.trace {
+            trace.push(TraceRecord::ContextLeft(level.context));
+        }
+        Some(level)
+    }
+}
+
+impl TraceRecord {
+    fn to_event<'a>(&self, syntax_set: &'a SyntaxSet) -> Result<ParseEvent<'a>, ParsingError> {
+        let traced = |id: ContextId| {
+            let syntax = &syntax_set.syntaxes()[id.syntax_index];
+            let name = syntax
+                .context_ids()
+                .iter()
+                .find(|&(_, context_id)| *context_id == id)
+                .map_or("", |(name, _)| name.as_str());
+            TracedContext {
+                id,
+                syntax: &syntax.name,
+                name,
+            }
+        };
+        Ok(match *self {
+            TraceRecord::ContextEntered(id) => ParseEvent::ContextEntered(traced(id)),
+            TraceRecord::ContextLeft(id) => ParseEvent::ContextLeft(traced(id)),
+            TraceRecord::PatternMatched {
+                context,
+                pattern,
+                ref range,
+                origin,
+            } => ParseEvent::PatternMatched {
+                context: traced(context),
+                pattern,
+                regex: syntax_set
+                    .get_context(&context)?
+                    .match_at(pattern)?
+                    .regex
+                    .regex_str(),
+                range: range.clone(),
+                origin,
+            },
+            TraceRecord::LoopPrevented {
+                context,
+                pattern,
+                position,
+            } => ParseEvent::LoopPrevented {
+                context: traced(context),
+                pattern,
+                position,
+            },
+        })
+    }
 }
 
 #[cfg(feature = "yaml-load")]
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parsing::syntax_definition::ParseContextReferenceError;
     use crate::parsing::ScopeStackOp::{Clear, Pop, Push, Restore};
     use crate::parsing::{Scope, ScopeStack, SyntaxSet, SyntaxSetBuilder};
     use crate::util::debug_print_ops;
@@ -771,7 +1979,7 @@ mod tests {
         assert_eq!(&ops1[0..test_ops1.len()], &test_ops1[..]);
 
         let ops2 = ops(&mut state, "def lol(wow = 5)", &ss);
-        let test_ops2 = vec![
+        let test_ops2 = [
             (0, Push(Scope::new("meta.function.ruby").unwrap())),
             (0, Push(Scope::new("keyword.control.def.ruby").unwrap())),
             (3, Pop(2)),
@@ -979,6 +2187,75 @@ mod tests {
         );
     }
 
+    #[test]
+    fn can_limit_parsing() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+            name: test
+            scope: source.test
+            contexts:
+              main:
+                - match: \w+
+                  scope: word.test
+                - match: '"'
+                  push:
+                    - meta_scope: string.test
+                    - match: '"'
+                      pop: true
+                    - match: \w
+                      scope: char.test
+            "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(syntax);
+        let ss = builder.build();
+        let new_state = |limits| {
+            let mut state = ParseState::new(&ss.syntaxes()[0]);
+            state.set_limits(limits);
+            state
+        };
+
+        let mut state = new_state(ParseLimits {
+            max_line_length: Some(10),
+            ..Default::default()
+        });
+        assert_eq!(
+            ops(&mut state, "aaaaa bbbbb\n", &ss),
+            vec![(0, Push(Scope::new("source.test").unwrap()))]
+        );
+        assert_eq!(state.exceeded_limit(), Some(ParseLimit::LineLength));
+        assert_eq!(ops(&mut state, "a\n", &ss).len(), 2);
+        assert_eq!(state.exceeded_limit(), None);
+
+        // The rest of the line keeps the current scopes
+        let line = "\"a b c d e f g h\" i\n";
+        let all_ops = ops(&mut ParseState::new(&ss.syntaxes()[0]), line, &ss);
+        let mut state = new_state(ParseLimits {
+            max_searches: Some(8),
+            ..Default::default()
+        });
+        let limited_ops = ops(&mut state, line, &ss);
+        assert_eq!(state.exceeded_limit(), Some(ParseLimit::Searches));
+        assert!(limited_ops.len() < all_ops.len());
+        assert_eq!(&limited_ops[..], &all_ops[..limited_ops.len()]);
+        assert_eq!(
+            stack_states(limited_ops).last().unwrap(),
+            "<source.test>, <string.test>"
+        );
+        // The next line continues in the string
+        assert_eq!(ops(&mut state, "\"\n", &ss), vec![(1, Pop(1))]);
+
+        let mut state = new_state(ParseLimits {
+            max_duration: Some(Duration::ZERO),
+            ..Default::default()
+        });
+        assert_eq!(ops(&mut state, "a\n", &ss).len(), 1);
+        assert_eq!(state.exceeded_limit(), Some(ParseLimit::Duration));
+    }
+
     #[test]
     fn can_parse_issue25() {
         let ss = SyntaxSet::load_from_folder("testdata/Packages").unwrap();
@@ -1454,6 +2731,296 @@ contexts:
         expect_scope_stacks_with_syntax("/** * */", &["<comment.block.documentation.javadoc>, <punctuation.definition.comment.begin.javadoc>", "<comment.block.documentation.javadoc>, <text.html.javadoc>, <punctuation.definition.comment.javadoc>", "<comment.block.documentation.javadoc>, <punctuation.definition.comment.end.javadoc>"], syntax);
     }
 
+    #[test]
+    fn can_parse_set_with_version_2() {
+        let syntax = r#"
+scope: source.test
+contexts:
+  main:
+    - match: a
+      push: ctx-a
+  ctx-a:
+    - meta_scope: meta.a
+    - meta_content_scope: meta.a.content
+    - match: b
+      scope: b
+      set: ctx-b
+  ctx-b:
+    - meta_scope: meta.b
+    - match: c
+      pop: true
+"#;
+        // In version 1, the matched text keeps the meta scopes of the context that is set away from
+        expect_scope_stacks(
+            "abc",
+            &["<source.test>, <meta.a>, <meta.a.content>, <meta.b>, <b>"],
+            syntax,
+        );
+        expect_scope_stacks(
+            "abc",
+            &["<source.test>, <meta.b>, <b>", "<source.test>, <meta.b>"],
+            &format!("version: 2{}", syntax),
+        );
+    }
+
+    #[test]
+    fn can_parse_multi_push_clear_scopes_with_version_2() {
+        let syntax = r#"
+scope: source.test
+contexts:
+  main:
+    - match: a
+      push: [ctx-a, ctx-b]
+  ctx-a:
+    - meta_content_scope: meta.a
+  ctx-b:
+    - clear_scopes: 1
+    - meta_scope: meta.b
+    - match: b
+      scope: b
+    - match: c
+      pop: true
+"#;
+        expect_scope_stacks("ab", &["<meta.a>, <meta.b>, <b>"], syntax);
+        // The `clear_scopes` of `ctx-b` clears the meta content scope of `ctx-a` below it
+        expect_scope_stacks(
+            "abc",
+            &["<source.test>, <meta.b>, <b>", "<source.test>, <meta.a>"],
+            &format!("version: 2{}", syntax),
+        );
+    }
+
+    #[test]
+    fn can_parse_embed_scope_with_version_2() {
+        let inner = SyntaxDefinition::load_from_str(
+            "name: Inner\nscope: source.inner\ncontexts: {main: [{match: i, scope: inner}]}",
+            true,
+            None,
+        )
+        .unwrap();
+        for (version, expect) in [
+            (
+                1,
+                "<source.outer>, <source.inner.embedded>, <source.inner>, <inner>",
+            ),
+            (2, "<source.outer>, <source.inner.embedded>, <inner>"),
+        ] {
+            let outer = SyntaxDefinition::load_from_str(
+                &format!(
+                    r#"
+version: {}
+scope: source.outer
+contexts:
+  main:
+    - match: o
+      embed: scope:source.inner
+      embed_scope: source.inner.embedded
+      escape: x
+"#,
+                    version
+                ),
+                true,
+                None,
+            )
+            .unwrap();
+            assert_eq!(outer.version, version);
+
+            let mut builder = SyntaxSetBuilder::new();
+            builder.add(outer);
+            builder.add(inner.clone());
+            let ss = builder.build();
+            assert_eq!(ss.syntaxes()[0].version(), version);
+            let mut state = ParseState::new(&ss.syntaxes()[0]);
+            let ops = ops(&mut state, "oix\n", &ss);
+            expect_scope_stacks_for_ops(ops, &[expect]);
+        }
+    }
+
+    #[test]
+    fn can_rewind_to_branch_point() {
+        let syntax = r#"
+scope: source.test
+contexts:
+  main:
+    - match: (?=\w)
+      branch_point: call
+      branch: [call, name]
+  call:
+    - match: \w+(?=\()
+      scope: call
+      pop: true
+    - match: (?=\S)
+      fail: call
+  name:
+    - match: \w+
+      scope: name
+      pop: true
+"#;
+        expect_scope_stacks(
+            "foo bar(",
+            &["<source.test>, <name>", "<source.test>, <call>"],
+            syntax,
+        );
+        let ops = parse("foo bar(", syntax);
+        assert_eq!(ops[1], (0, Push(Scope::new("name").unwrap())));
+        assert_eq!(ops[3], (4, Push(Scope::new("call").unwrap())));
+    }
+
+    #[test]
+    fn can_parse_fail_without_branch_point() {
+        let syntax = r#"
+scope: source.test
+contexts:
+  main:
+    - match: b
+      fail: nothing
+    - match: a
+      scope: a
+"#;
+        expect_scope_stacks("ba", &["<source.test>, <a>"], syntax);
+    }
+
+    #[test]
+    fn can_rewind_to_branch_point_on_previous_line() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+scope: source.test
+contexts:
+  main:
+    - match: (?=\w)
+      branch_point: decl
+      branch: [annotated, plain]
+  annotated:
+    - match: \w+
+      scope: annotated
+    - match: ':'
+      pop: true
+    - match: (?=\S)
+      fail: decl
+  plain:
+    - match: \w+
+      scope: plain
+      pop: true
+"#,
+            true,
+            None,
+        )
+        .unwrap();
+        let ss = link(syntax);
+
+        // Without lookahead, the results of previous lines can't change
+        let mut state = ParseState::new(&ss.syntaxes()[0]);
+        expect_scope_stacks_for_ops(ops(&mut state, "foo\n", &ss), &["<annotated>"]);
+        ops(&mut state, "=\n", &ss);
+        assert_eq!(state.take_rewound_lines(), None);
+
+        let mut state = ParseState::new(&ss.syntaxes()[0]);
+        state.set_branch_lookahead(1);
+        expect_scope_stacks_for_ops(ops(&mut state, "foo\n", &ss), &["<annotated>"]);
+        assert_eq!(ops(&mut state, "=\n", &ss), vec![]);
+        let rewound = state.take_rewound_lines().unwrap();
+        assert_eq!(rewound.len(), 1);
+        expect_scope_stacks_for_ops(rewound[0].clone(), &["<source.test>, <plain>"]);
+        assert_eq!(state.take_rewound_lines(), None);
+
+        // The branch point is too far back to rewind to
+        let mut state = ParseState::new(&ss.syntaxes()[0]);
+        state.set_branch_lookahead(1);
+        ops(&mut state, "foo\n", &ss);
+        ops(&mut state, "\n", &ss);
+        ops(&mut state, "=\n", &ss);
+        assert_eq!(state.take_rewound_lines(), None);
+    }
+
+    #[test]
+    fn can_pop_multiple_contexts() {
+        let syntax = r#"
+scope: source.test
+contexts:
+  main:
+    - match: a
+      push: [ctx-a, ctx-b]
+    - match: c
+      scope: c
+  ctx-a:
+    - meta_scope: meta.a
+  ctx-b:
+    - meta_scope: meta.b
+    - meta_content_scope: meta.b.content
+    - match: b
+      scope: b
+      pop: 2
+"#;
+        expect_scope_stacks(
+            "abc",
+            &[
+                "<source.test>, <meta.a>, <meta.b>, <meta.b.content>",
+                "<source.test>, <meta.a>, <meta.b>, <b>",
+                "<source.test>, <c>",
+            ],
+            syntax,
+        );
+        let states = stack_states(parse("abc", syntax));
+        assert_eq!(states.last().unwrap(), "<source.test>");
+    }
+
+    #[test]
+    fn can_pop_and_push_or_set() {
+        let syntax = r#"
+scope: source.test
+contexts:
+  main:
+    - match: a
+      push: ctx-a
+    - match: x
+      push: [ctx-x, ctx-a]
+    - match: c
+      scope: c
+  ctx-a:
+    - meta_scope: meta.a
+    - match: b
+      scope: b
+      pop: 1
+      push: ctx-b
+    - match: s
+      scope: s
+      pop: true
+      set: ctx-b
+  ctx-x:
+    - meta_scope: meta.x
+  ctx-b:
+    - meta_scope: meta.b
+    - match: c
+      scope: c.b
+      pop: true
+"#;
+        expect_scope_stacks(
+            "abcc",
+            &[
+                "<source.test>, <meta.b>, <b>",
+                "<source.test>, <meta.b>, <c.b>",
+                "<source.test>, <c>",
+            ],
+            syntax,
+        );
+        // `set` replaces `ctx-x` after popping `ctx-a`
+        expect_scope_stacks(
+            "xscc",
+            &[
+                "<source.test>, <meta.x>, <meta.a>",
+                "<source.test>, <meta.b>, <s>",
+                "<source.test>, <meta.b>, <c.b>",
+                "<source.test>, <c>",
+            ],
+            syntax,
+        );
+        let states = stack_states(parse("xscc", syntax));
+        assert!(!states
+            .iter()
+            .any(|s| s.contains("meta.x") && s.contains("<s>")));
+        assert_eq!(states.last().unwrap(), "<source.test>");
+    }
+
     #[test]
     fn can_parse_context_included_in_prototype_via_named_reference() {
         let syntax = r#"
@@ -1888,6 +3455,206 @@ contexts:
         expect_scope_stacks_with_syntax("aa", &["<a>", "<b>"], syntax);
     }
 
+    #[test]
+    fn can_save_and_restore_state() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+                name: Heredoc
+                scope: source.heredoc
+                contexts:
+                  main:
+                    - match: <<(\w+)
+                      push: heredoc
+                  heredoc:
+                    - meta_scope: string.heredoc
+                    - match: ^\1$
+                      pop: true
+                "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(syntax);
+        let ss = builder.build();
+
+        let mut state = ParseState::new(&ss.syntaxes()[0]);
+        state.set_limits(ParseLimits {
+            max_searches: Some(100),
+            ..Default::default()
+        });
+        ops(&mut state, "<<EOF\n", &ss);
+        let json = serde_json::to_string(&state.save(&ss)).unwrap();
+        let saved: SavedParseState = serde_json::from_str(&json).unwrap();
+        assert_eq!(saved.fingerprint(), ss.fingerprint());
+        let mut restored = saved.restore(&ss).unwrap();
+        assert_eq!(restored, state);
+        // The backreference still refers to the text captured before saving
+        assert_eq!(ops(&mut restored, "ABC\n", &ss), vec![]);
+        assert_eq!(ops(&mut restored, "EOF\n", &ss), vec![(3, Pop(1))]);
+
+        let mut other = SyntaxSetBuilder::new();
+        other.add(
+            SyntaxDefinition::load_from_str(
+                "name: Other\nscope: source.other\ncontexts: {main: []}",
+                true,
+                None,
+            )
+            .unwrap(),
+        );
+        let other = other.build();
+        assert_ne!(other.fingerprint(), ss.fingerprint());
+        let saved: SavedParseState = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            saved.restore(&other),
+            Err(ParsingError::SyntaxSetMismatch)
+        ));
+    }
+
+    #[test]
+    fn cant_restore_invalid_parse_states() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+scope: source.test
+contexts:
+  main:
+    - match: (?=\w)
+      branch_point: decl
+      branch: [annotated, plain]
+  annotated:
+    - match: \w+
+    - match: (?=\S)
+      fail: decl
+  plain:
+    - match: \w+
+      pop: true
+"#,
+            true,
+            None,
+        )
+        .unwrap();
+        let ss = link(syntax);
+        let mut state = ParseState::new(&ss.syntaxes()[0]);
+        state.set_branch_lookahead(1);
+        ops(&mut state, "foo\n", &ss);
+        let saved = serde_json::to_value(state.save(&ss)).unwrap();
+        let restore = |change: &dyn Fn(&mut serde_json::Value)| {
+            let mut saved = saved.clone();
+            change(&mut saved);
+            serde_json::from_value::<SavedParseState>(saved)
+                .unwrap()
+                .restore(&ss)
+        };
+        assert!(restore(&|_| {}).is_ok());
+        assert!(matches!(
+            restore(&|s| s["stack"][0]["context"]["context_index"] = 99.into()),
+            Err(ParsingError::MissingContext(_))
+        ));
+        let invalid: [&dyn Fn(&mut serde_json::Value); 5] = [
+            &|s| s["stack"] = serde_json::json!([]),
+            &|s| s["proto_starts"] = serde_json::json!([2, 1]),
+            &|s| s["branch_points"][0]["pos"] = 100.into(),
+            &|s| s["branch_points"][0]["lines_back"] = 2.into(),
+            &|s| s["branch_points"][0]["alternative"] = 2.into(),
+        ];
+        for change in invalid {
+            assert!(matches!(
+                restore(change),
+                Err(ParsingError::InvalidSavedState)
+            ));
+        }
+
+        // A branch with fewer alternatives than saved fails when it's tried again
+        let mut state = restore(&|s| {
+            s["branch_points"][0]["alternative"] = 1.into();
+            s["branch_points"][0]["alternatives"] = 5.into();
+        })
+        .unwrap();
+        assert!(matches!(
+            state.parse_line("=\n", &ss),
+            Err(ParsingError::InvalidSavedState)
+        ));
+    }
+
+    #[test]
+    fn can_start_in_given_contexts() {
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(
+            SyntaxDefinition::load_from_str(
+                r#"
+                name: Script
+                scope: source.script
+                contexts:
+                  main:
+                    - match: let
+                      scope: keyword
+                      push: expression
+                  expression:
+                    - meta_scope: meta.expression
+                    - match: \d+
+                      scope: constant.numeric
+                    - match: '\('
+                      push: group
+                    - match: ;
+                      pop: true
+                  group:
+                    - meta_content_scope: meta.group
+                    - match: '\)'
+                      pop: true
+                    - include: expression
+                "#,
+                true,
+                None,
+            )
+            .unwrap(),
+        );
+        let ss = builder.build();
+
+        let contexts: Vec<ContextReference> = ["scope:source.script#expression", "group"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let mut stack = "markup.raw".parse::<ScopeStack>().unwrap();
+        let mut state = ParseState::with_contexts(&contexts, &mut stack, &ss).unwrap();
+        assert_eq!(
+            stack,
+            "markup.raw meta.expression meta.group"
+                .parse::<ScopeStack>()
+                .unwrap()
+        );
+        assert_eq!(
+            ops(&mut state, "1)", &ss),
+            vec![
+                (0, Push(Scope::new("constant.numeric").unwrap())),
+                (1, Pop(1)),
+                (1, Pop(1)),
+            ]
+        );
+        assert_eq!(ops(&mut state, "; 1", &ss), vec![(1, Pop(1))]);
+        assert!(matches!(
+            state.parse_line("1", &ss),
+            Err(ParsingError::MissingMainContext)
+        ));
+
+        // Names are looked up in the syntax of the context before them
+        let named: ContextReference = "group".parse().unwrap();
+        assert!(matches!(
+            ParseState::with_contexts(&[named], &mut ScopeStack::new(), &ss),
+            Err(ParsingError::UnresolvedContextReference(_))
+        ));
+        for invalid in ["main#group", "#group", ""] {
+            assert!(matches!(
+                invalid.parse::<ContextReference>(),
+                Err(ParseContextReferenceError::InvalidName(_))
+            ));
+        }
+        let missing: ContextReference = "Packages/Other/Other.sublime-syntax".parse().unwrap();
+        assert!(matches!(
+            missing.resolve(&ss),
+            Err(ParsingError::UnresolvedContextReference(_))
+        ));
+    }
+
     #[test]
     fn can_include_nested_backrefs() {
         let syntax = SyntaxDefinition::load_from_str(
@@ -1916,6 +3683,187 @@ contexts:
         expect_scope_stacks_with_syntax("aa", &["<a>", "<b>"], syntax);
     }
 
+    #[test]
+    fn can_observe_parsing() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+            name: test
+            scope: source.test
+            contexts:
+              prototype:
+                - match: '#'
+                  scope: comment.test
+              main:
+                - match: (?=\w)
+                  push: word
+                - match: \(
+                  push: group
+                  with_prototype:
+                    - match: '!'
+                      scope: bang.test
+                - match: (?=%)
+                  push: empty
+              word:
+                - match: \w+
+                  scope: word.test
+                - match: (?=.)
+                  pop: true
+              group:
+                - match: \)
+                  pop: true
+              empty:
+                - match: ''
+                  pop: true
+            "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let ss = link(syntax);
+        let mut state = ParseState::new(&ss.syntaxes()[0]);
+        let mut events = Vec::new();
+        let mut observer = |event: &ParseEvent<'_>| {
+            events.push(match *event {
+                ParseEvent::ContextEntered(context) => format!("enter {}", context.name),
+                ParseEvent::ContextLeft(context) => format!("leave {}", context.name),
+                ParseEvent::PatternMatched {
+                    context,
+                    pattern,
+                    regex,
+                    ref range,
+                    origin,
+                } => format!(
+                    "{}[{}] {} {:?} {:?}",
+                    context.name, pattern, regex, range, origin
+                ),
+                ParseEvent::LoopPrevented {
+                    context,
+                    pattern,
+                    position,
+                } => format!("loop {}[{}] at {}", context.name, pattern, position),
+            });
+        };
+        let ops = state
+            .parse_line_with_observer("ab #(!)%\n", &ss, &mut observer)
+            .unwrap();
+        // Observing doesn't change the result
+        let mut plain_state = ParseState::new(&ss.syntaxes()[0]);
+        assert_eq!(ops, plain_state.parse_line("ab #(!)%\n", &ss).unwrap());
+        assert_eq!(
+            events,
+            vec![
+                "__start[0]  0..0 Context",
+                "enter __main",
+                "main[0] (?=\\w) 0..0 Context",
+                "enter word",
+                "word[0] \\w+ 0..2 Context",
+                "word[1] (?=.) 2..2 Context",
+                "leave word",
+                "prototype[0] # 3..4 Prototype",
+                "main[1] \\( 4..5 Context",
+                "enter group",
+                "#anon_main_0[0] ! 5..6 WithPrototype",
+                "group[0] \\) 6..7 Context",
+                "leave group",
+                "main[2] (?=%) 7..7 Context",
+                "enter empty",
+                // The non-consuming pop right after the non-consuming push is skipped
+                "loop empty[0] at 7",
+                "empty[0]  8..8 Context",
+                "leave empty",
+            ]
+        );
+
+        // Errors of the parser are returned rather than dropped
+        state.set_cancellation_flag(Arc::new(AtomicBool::new(true)));
+        let result = state.parse_line_with_observer("ab\n", &ss, &mut |_: &ParseEvent<'_>| {});
+        assert!(matches!(result, Err(ParsingError::Cancelled)));
+    }
+
+    #[test]
+    fn can_observe_included_patterns() {
+        let syntax = SyntaxDefinition::load_from_str(
+            "name: test\nscope: source.test\ncontexts: {main: [{include: values}], values: [{include: numbers}], numbers: [{match: '\\d'}]}",
+            true,
+            None,
+        )
+        .unwrap();
+        let ss = link(syntax);
+        let mut state = ParseState::new(&ss.syntaxes()[0]);
+        let mut matched = Vec::new();
+        let mut observer = |event: &ParseEvent<'_>| {
+            if let ParseEvent::PatternMatched { context, .. } = *event {
+                matched.push(context.name.to_owned());
+            }
+        };
+        state
+            .parse_line_with_observer("1\n", &ss, &mut observer)
+            .unwrap();
+        assert_eq!(matched, ["__start", "numbers"]);
+    }
+
+    #[cfg(feature = "regex-hybrid")]
+    #[test]
+    fn pattern_sets_find_the_same_matches() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+            name: test
+            scope: source.test
+            contexts:
+              prototype:
+                - match: '#.*'
+                  scope: comment.test
+              main:
+                - match: 'x*'
+                - match: \b(if|else)\b
+                  scope: keyword.test
+                - match: (?<=\.)\w+
+                  scope: member.test
+                - match: '"'
+                  push: string
+                - include: values
+                - match: \w+
+                  scope: word.test
+              values:
+                - match: \d+
+                  scope: number.test
+                - match: \d+\.\d+
+                  scope: never.test
+                - match: true|false
+                  scope: bool.test
+              string:
+                - match: '"'
+                  pop: true
+                - match: \\.
+                  scope: escape.test
+            "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let ss = link(syntax);
+        let lines = [
+            "if x.y else 12.5 \"a\\\"b\" # if\n",
+            "falsey true 1else if\n",
+            "\"#\" x # \"\n",
+        ];
+
+        // The profiler needs all patterns to be searched, so it doesn't use pattern sets
+        let mut state = ParseState::new(&ss.syntaxes()[0]);
+        let mut profiled_state = ParseState::new(&ss.syntaxes()[0]);
+        profiled_state.set_profiler(Profiler::new());
+        for _ in 0..100 {
+            for line in &lines {
+                assert_eq!(
+                    state.parse_line(line, &ss).unwrap(),
+                    profiled_state.parse_line(line, &ss).unwrap()
+                );
+            }
+        }
+        let main = ss.syntaxes()[0].context_ids()["__main"];
+        assert!(ss.get_pattern_set(&main).unwrap().is_some());
+    }
+
     fn expect_scope_stacks(line_without_newline: &str, expect: &[&str], syntax: &str) {
         println!("Parsing with newlines");
         let line_with_newline = format!("{}\n", line_without_newline);
@@ -0,0 +1,199 @@
+//! Searching many patterns of a context with a single automaton, for the `regex-hybrid` feature.
+use super::syntax_definition::{context_iter, Context, MatchOperation};
+use super::syntax_set::SyntaxSet;
+use once_cell::sync::OnceCell;
+use regex_automata::hybrid::dfa::{Cache, Config, DFA};
+use regex_automata::nfa::thompson::{self, WhichCaptures};
+use regex_automata::util::pool::Pool;
+use regex_automata::{Input, MatchError};
+use std::fmt;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+type CachePool = Pool<Cache, Box<dyn Fn() -> Cache + Send + Sync + UnwindSafe + RefUnwindSafe>>;
+
+/// The patterns of a context (including the patterns of the contexts it includes) that the
+/// `regex` crate can search together
+///
+/// Searching them together finds the leftmost match, and for several patterns matching there the
+/// first one, which is exactly the match that searching them one by one would pick. The other
+/// patterns are still searched one by one: the ones with backreferences or lookarounds that need
+/// the backtracking engine, the ones that refer to captures of the pattern that pushed the
+/// context, since they are only known while parsing, and the ones that can match nothing without
+/// changing the stack, since the parser skips those matches and goes on with the next pattern.
+///
+/// Only the pattern that matches is found, not where it matches, which would need a second
+/// automaton for searching backwards from the end of the match. The parser gets the match from
+/// searching that pattern anyway.
+pub(crate) struct PatternSet {
+    dfa: DFA,
+    caches: CachePool,
+    /// The index of each pattern of the automaton in the patterns of the context
+    indexes: Vec<usize>,
+    /// For each pattern of the context, whether it's in the automaton
+    contains: Vec<bool>,
+}
+
+impl PatternSet {
+    /// Returns `None` if there aren't at least two patterns that can be searched together
+    pub(crate) fn new(syntax_set: &SyntaxSet, context: &Context) -> Option<PatternSet> {
+        let mut hirs = Vec::new();
+        let mut indexes = Vec::new();
+        let mut contains = Vec::new();
+        for (index, (pat_context, pat_index)) in context_iter(syntax_set, context).enumerate() {
+            contains.push(false);
+            let match_pat = pat_context.match_at(pat_index).ok()?;
+            if match_pat.has_captures {
+                continue;
+            }
+            if let Ok(hir) = regex_syntax::Parser::new().parse(match_pat.regex.regex_str()) {
+                let skipped_if_empty = matches!(match_pat.operation, MatchOperation::None);
+                if skipped_if_empty && hir.properties().minimum_len() == Some(0) {
+                    continue;
+                }
+                hirs.push(hir);
+                indexes.push(index);
+                contains[index] = true;
+            }
+        }
+        if hirs.len() < 2 {
+            return None;
+        }
+        let nfa = thompson::Compiler::new()
+            .configure(thompson::Config::new().which_captures(WhichCaptures::None))
+            .build_many_from_hir(&hirs)
+            .ok()?;
+        // Unicode word boundaries make the search fail on non-ASCII text instead of failing here
+        let dfa = DFA::builder()
+            .configure(Config::new().unicode_word_boundary(true))
+            .build_from_nfa(nfa)
+            .ok()?;
+        Some(PatternSet {
+            caches: Self::cache_pool(&dfa),
+            dfa,
+            indexes,
+            contains,
+        })
+    }
+
+    fn cache_pool(dfa: &DFA) -> CachePool {
+        let dfa = dfa.clone();
+        Pool::new(Box::new(move || dfa.create_cache()))
+    }
+
+    /// Whether the pattern with the index in the patterns of the context is in the set
+    pub(crate) fn contains(&self, index: usize) -> bool {
+        self.contains.get(index).copied().unwrap_or(false)
+    }
+
+    /// Returns the index in the patterns of the context of the pattern with the leftmost match
+    /// from `begin`.
+    ///
+    /// Fails if the automaton gives up, e.g. for Unicode word boundaries in non-ASCII text.
+    pub(crate) fn find(&self, text: &str, begin: usize) -> Result<Option<usize>, MatchError> {
+        let input = Input::new(text).span(begin..text.len());
+        let mut cache = self.caches.get();
+        let found = self.dfa.try_search_fwd(&mut cache, &input)?;
+        Ok(found.map(|m| self.indexes[m.pattern().as_usize()]))
+    }
+}
+
+impl Clone for PatternSet {
+    fn clone(&self) -> PatternSet {
+        PatternSet {
+            dfa: self.dfa.clone(),
+            caches: Self::cache_pool(&self.dfa),
+            indexes: self.indexes.clone(),
+            contains: self.contains.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for PatternSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PatternSet")
+            .field("indexes", &self.indexes)
+            .finish()
+    }
+}
+
+/// The pattern set of a context, which is only built once the context has been searched often
+/// enough that the faster searches make up for building it
+#[derive(Debug, Default)]
+pub(crate) struct LazyPatternSet {
+    searches: AtomicUsize,
+    set: OnceCell<Option<PatternSet>>,
+}
+
+impl LazyPatternSet {
+    const SEARCHES_BEFORE_BUILD: usize = 1024;
+
+    /// Returns the pattern set if it has been built, counting the search otherwise
+    pub(crate) fn get(&self, syntax_set: &SyntaxSet, context: &Context) -> Option<&PatternSet> {
+        if let Some(set) = self.set.get() {
+            return set.as_ref();
+        }
+        if self.searches.fetch_add(1, Ordering::Relaxed) < Self::SEARCHES_BEFORE_BUILD {
+            return None;
+        }
+        self.set
+            .get_or_init(|| PatternSet::new(syntax_set, context))
+            .as_ref()
+    }
+}
+
+impl Clone for LazyPatternSet {
+    fn clone(&self) -> LazyPatternSet {
+        LazyPatternSet {
+            searches: AtomicUsize::new(self.searches.load(Ordering::Relaxed)),
+            set: self.set.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "yaml-load")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::{SyntaxDefinition, SyntaxSetBuilder};
+
+    #[test]
+    fn can_find_leftmost_first_match() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+            name: test
+            scope: source.test
+            contexts:
+              main:
+                - match: (?<=x)a
+                - match: ab
+                - include: other
+                - match: '[^\w\s]'
+                  push: other
+              other:
+                - match: a
+                - match: (b)\1
+                - match: b|c
+                - match: \1
+            "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(syntax);
+        let ss = builder.build();
+        let main = ss
+            .get_context(&ss.syntaxes()[0].context_ids()["main"])
+            .unwrap();
+
+        let set = PatternSet::new(&ss, main).unwrap();
+        let contains: Vec<bool> = (0..7).map(|i| set.contains(i)).collect();
+        assert_eq!(contains, [false, true, true, false, true, false, true]);
+        assert_eq!(set.find("cab", 0), Ok(Some(4)));
+        assert_eq!(set.find("cab", 1), Ok(Some(1)));
+        assert_eq!(set.find("cac", 1), Ok(Some(2)));
+        assert_eq!(set.find("x!", 0), Ok(Some(6)));
+        assert_eq!(set.find("cab", 3), Ok(None));
+    }
+}
@@ -0,0 +1,329 @@
+//! Skipping regex searches using the literals that the matches of a regex have to start with.
+//!
+//! Many patterns of syntaxes start with a literal, like keywords, `//` or `"`. Searching the line
+//! for these literals is much faster than running the regex at each position, and if none of
+//! them is found the regex can't match at all.
+//!
+//! The regexes are Oniguruma regexes that `regex-syntax` can't parse in general, e.g. because of
+//! lookbehinds or possessive quantifiers, but only the start of each alternative matters here.
+//! So this is a small conservative analysis: whenever it's not sure what a regex starts with, it
+//! doesn't prefilter it.
+use aho_corasick::{AhoCorasick, MatchKind};
+
+/// Finds the positions in a text where a regex could match
+#[derive(Debug)]
+pub(crate) struct Prefilter {
+    /// Searches for the literal prefixes, or `None` if the regex could match anywhere
+    searcher: Option<AhoCorasick>,
+}
+
+impl Prefilter {
+    pub(crate) fn new(regex_str: &str) -> Prefilter {
+        let searcher = literal_prefixes(regex_str).and_then(|prefixes| {
+            AhoCorasick::builder()
+                .match_kind(MatchKind::LeftmostFirst)
+                .build(prefixes)
+                .ok()
+        });
+        Prefilter { searcher }
+    }
+
+    /// Returns the first position from `begin` where a match could start, or `None` if there is
+    /// none.
+    pub(crate) fn find(&self, text: &str, begin: usize) -> Option<usize> {
+        match self.searcher {
+            Some(ref searcher) => searcher
+                .find(&text.as_bytes()[begin..])
+                .map(|m| begin + m.start()),
+            None => Some(begin),
+        }
+    }
+}
+
+/// Returns literals so that every match of the regex starts with one of them, or `None` if there
+/// are no such literals.
+fn literal_prefixes(regex_str: &str) -> Option<Vec<Vec<u8>>> {
+    let mut analyzer = Analyzer {
+        regex: regex_str,
+        pos: 0,
+    };
+    let prefixes = analyzer.alternatives()?;
+    // An unbalanced `)`
+    if analyzer.pos != regex_str.len() {
+        return None;
+    }
+    Some(prefixes)
+}
+
+struct Analyzer<'a> {
+    regex: &'a str,
+    pos: usize,
+}
+
+impl<'a> Analyzer<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.regex.as_bytes().get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.regex.as_bytes().get(self.pos + offset).copied()
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.regex[self.pos..]
+    }
+
+    /// The prefixes of a list of alternatives up to the `)` that ends the group or the end of the
+    /// regex
+    fn alternatives(&mut self) -> Option<Vec<Vec<u8>>> {
+        let mut prefixes = Vec::new();
+        loop {
+            prefixes.extend(self.prefixes()?);
+            self.skip_alternative()?;
+            if self.peek() == Some(b'|') {
+                self.pos += 1;
+            } else {
+                return Some(prefixes);
+            }
+        }
+    }
+
+    /// The prefixes of an alternative, which leaves the position somewhere in it
+    fn prefixes(&mut self) -> Option<Vec<Vec<u8>>> {
+        let mut literal = Vec::new();
+        loop {
+            let len_before = literal.len();
+            match self.peek() {
+                None | Some(b'|') | Some(b')') => break,
+                // Zero-width assertions at the start don't change where the match starts. `\G`
+                // is not one of them, since the match has to start at the search position.
+                Some(b'^') if literal.is_empty() => self.pos += 1,
+                Some(b'\\') => match self.peek_at(1) {
+                    Some(b'b' | b'B' | b'A') if literal.is_empty() => self.pos += 2,
+                    Some(b't') => self.push_escaped(&mut literal, b'\t'),
+                    Some(b'n') => self.push_escaped(&mut literal, b'\n'),
+                    Some(b'r') => self.push_escaped(&mut literal, b'\r'),
+                    Some(c) if c.is_ascii_punctuation() => self.push_escaped(&mut literal, c),
+                    _ => break,
+                },
+                Some(b'(') if literal.is_empty() => {
+                    let rest = self.rest();
+                    if rest.starts_with("(?=")
+                        || rest.starts_with("(?!")
+                        || rest.starts_with("(?<=")
+                        || rest.starts_with("(?<!")
+                        || rest.starts_with("(?#")
+                    {
+                        // Lookarounds and comments don't consume anything
+                        self.pos += 1;
+                        self.skip_alternatives()?;
+                        self.pos += 1;
+                        continue;
+                    }
+                    let opener_len = if rest.starts_with("(?:") || rest.starts_with("(?>") {
+                        3
+                    } else if rest.starts_with("(?<") || rest.starts_with("(?P<") {
+                        rest.find('>')? + 1
+                    } else if rest.starts_with("(?") {
+                        // Options like `(?i)` change how the rest is matched
+                        self.check_options()?;
+                        break;
+                    } else {
+                        1
+                    };
+                    self.pos += opener_len;
+                    let prefixes = self.alternatives()?;
+                    if self.peek() != Some(b')') {
+                        return None;
+                    }
+                    self.pos += 1;
+                    if matches!(self.peek(), Some(b'?' | b'*' | b'{')) {
+                        // The group is optional
+                        return None;
+                    }
+                    return Some(prefixes);
+                }
+                Some(
+                    b'(' | b'[' | b']' | b'{' | b'}' | b'.' | b'^' | b'$' | b'?' | b'*' | b'+',
+                ) => break,
+                Some(_) => {
+                    let c = self.rest().chars().next().unwrap();
+                    let mut buf = [0; 4];
+                    literal.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                    self.pos += c.len_utf8();
+                }
+            }
+            if literal.len() > len_before {
+                match self.peek() {
+                    // The last character is optional
+                    Some(b'?' | b'*' | b'{') => {
+                        literal.truncate(len_before);
+                        break;
+                    }
+                    // Only the first repetition is required
+                    Some(b'+') => break,
+                    _ => {}
+                }
+            }
+        }
+        if literal.is_empty() {
+            None
+        } else {
+            Some(vec![literal])
+        }
+    }
+
+    fn push_escaped(&mut self, literal: &mut Vec<u8>, c: u8) {
+        literal.push(c);
+        self.pos += 2;
+    }
+
+    /// Fails for a group at the position that sets the `x` option, which makes whitespace and
+    /// comments in the regex ignored
+    fn check_options(&self) -> Option<()> {
+        let options = self.rest()[2..]
+            .split(|c: char| !c.is_ascii_alphabetic() && c != '-')
+            .next()?;
+        if options.contains('x') {
+            None
+        } else {
+            Some(())
+        }
+    }
+
+    /// Skips to the `|` that ends the alternative, the `)` that ends the group or the end
+    fn skip_alternative(&mut self) -> Option<()> {
+        while let Some(c) = self.peek() {
+            match c {
+                b'|' | b')' => break,
+                b'(' => {
+                    if self.rest().starts_with("(?") {
+                        self.check_options()?;
+                    }
+                    self.pos += 1;
+                    self.skip_alternatives()?;
+                    self.pos += 1;
+                }
+                _ => self.skip_atom()?,
+            }
+        }
+        Some(())
+    }
+
+    /// Skips to the `)` that ends the group, failing if there is none
+    fn skip_alternatives(&mut self) -> Option<()> {
+        if self.rest().starts_with("?#") {
+            // Comments can contain anything but `)`
+            self.pos += self.rest().find(')')?;
+            return Some(());
+        }
+        loop {
+            self.skip_alternative()?;
+            match self.peek()? {
+                b'|' => self.pos += 1,
+                _ => return Some(()),
+            }
+        }
+    }
+
+    /// Skips an escape, a character class or another character
+    fn skip_atom(&mut self) -> Option<()> {
+        match self.peek()? {
+            b'\\' => {
+                self.pos += 1;
+                self.pos += self.rest().chars().next()?.len_utf8();
+            }
+            b'[' => self.skip_class()?,
+            _ => self.pos += self.rest().chars().next()?.len_utf8(),
+        }
+        Some(())
+    }
+
+    /// Skips a character class, which can contain nested classes
+    fn skip_class(&mut self) -> Option<()> {
+        self.pos += 1;
+        if self.peek() == Some(b'^') {
+            self.pos += 1;
+        }
+        // A `]` at the start is a literal
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+        }
+        loop {
+            match self.peek()? {
+                b']' => {
+                    self.pos += 1;
+                    return Some(());
+                }
+                b'[' => self.skip_class()?,
+                _ => self.skip_atom()?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefixes(regex: &str) -> Option<Vec<String>> {
+        literal_prefixes(regex).map(|prefixes| {
+            prefixes
+                .into_iter()
+                .map(|p| String::from_utf8(p).unwrap())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn finds_literal_prefixes() {
+        assert_eq!(prefixes(r"\bfn\b"), Some(vec!["fn".to_owned()]));
+        assert_eq!(prefixes(r"//.*$"), Some(vec!["//".to_owned()]));
+        assert_eq!(prefixes(r#"""#), Some(vec!["\"".to_owned()]));
+        assert_eq!(prefixes(r"</?(\w+)"), Some(vec!["<".to_owned()]));
+        assert_eq!(prefixes(r"ab+c"), Some(vec!["ab".to_owned()]));
+        assert_eq!(
+            prefixes(r"\.\.\.|::"),
+            Some(vec!["...".to_owned(), "::".to_owned()])
+        );
+        assert_eq!(
+            prefixes(r"\b(?:if|else)\b(?=\s)"),
+            Some(vec!["if".to_owned(), "else".to_owned()])
+        );
+        assert_eq!(
+            prefixes(r"(?<=\s)(\(|\[[a-z])x"),
+            Some(vec!["(".to_owned(), "[".to_owned()])
+        );
+        assert_eq!(prefixes(r"ü+"), Some(vec!["ü".to_owned()]));
+        assert_eq!(prefixes(r"^\s*#"), None);
+
+        // Alternatives that could start with anything
+        assert_eq!(prefixes(r"if|\w+"), None);
+        assert_eq!(prefixes(r"(if)?x"), None);
+        assert_eq!(prefixes(r"a?b"), None);
+        assert_eq!(prefixes(r"(?i)select"), None);
+        assert_eq!(prefixes(r"\Gfoo"), None);
+        assert_eq!(prefixes(r"[a]bc"), None);
+        assert_eq!(prefixes(r""), None);
+
+        // Things that have to be skipped correctly
+        assert_eq!(
+            prefixes(r"a[)|(]b|c(?#(|)d"),
+            Some(vec!["a".to_owned(), "c".to_owned()])
+        );
+        assert_eq!(prefixes(r"a(?x) b | c"), None);
+        assert_eq!(prefixes(r"a)"), None);
+        assert_eq!(prefixes(r"(a"), None);
+    }
+
+    #[test]
+    fn finds_possible_matches() {
+        let prefilter = Prefilter::new(r"\b(?:if|else)\b");
+        assert_eq!(prefilter.find("x = if y else z", 0), Some(4));
+        assert_eq!(prefilter.find("x = if y else z", 5), Some(9));
+        assert_eq!(prefilter.find("x = if y else z", 10), None);
+
+        let prefilter = Prefilter::new(r"\w+");
+        assert_eq!(prefilter.find("x", 1), Some(1));
+    }
+}
@@ -0,0 +1,368 @@
+use super::syntax_definition::{MatchPattern, Pattern};
+use super::syntax_set::SyntaxSet;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Statistics about the regex searches of a pattern, or of all patterns of a context or syntax
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// The number of regex searches
+    pub searches: usize,
+    /// The number of searches that found a match
+    pub hits: usize,
+    /// The number of times a search was skipped because an earlier search on the same line had
+    /// the result already
+    pub cache_hits: usize,
+    /// The total time of the searches
+    pub time: Duration,
+}
+
+impl SearchStats {
+    fn add(&mut self, other: &SearchStats) {
+        self.searches += other.searches;
+        self.hits += other.hits;
+        self.cache_hits += other.cache_hits;
+        self.time += other.time;
+    }
+}
+
+/// Collects [`SearchStats`] for each pattern while parsing, to find the patterns that make a
+/// syntax slow. See [`ParseState::set_profiler`].
+///
+/// Clones of a profiler collect into the same statistics, so one profiler can be shared by all
+/// the parse states that highlight a corpus, also on different threads. Profiling makes parsing
+/// slower, so it should only be used for finding slow patterns.
+///
+/// [`SearchStats`]: struct.SearchStats.html
+/// [`ParseState::set_profiler`]: struct.ParseState.html#method.set_profiler
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{ParseState, Profiler, SyntaxSet};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let profiler = Profiler::new();
+/// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+/// state.set_profiler(profiler.clone());
+/// state.parse_line("fn main() {}\n", &ss).unwrap();
+///
+/// let report = profiler.report(&ss);
+/// assert!(report.total().searches > 0);
+/// println!("{}", report);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    /// The statistics by the address of the pattern
+    patterns: Arc<Mutex<HashMap<usize, SearchStats>>>,
+}
+
+impl PartialEq for Profiler {
+    fn eq(&self, other: &Profiler) -> bool {
+        Arc::ptr_eq(&self.patterns, &other.patterns)
+    }
+}
+
+impl Eq for Profiler {}
+
+impl Profiler {
+    /// Creates a profiler without any statistics
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    /// Forgets the statistics collected so far
+    pub fn reset(&self) {
+        self.patterns.lock().unwrap().clear();
+    }
+
+    /// Returns the statistics collected so far.
+    ///
+    /// The syntax set has to be the one that was used for parsing, since the patterns are looked
+    /// up in it.
+    pub fn report(&self, syntax_set: &SyntaxSet) -> ProfileReport {
+        let stats = self.patterns.lock().unwrap();
+        let mut patterns = Vec::new();
+        for syntax in syntax_set.syntaxes() {
+            // Patterns of syntaxes that were never loaded can't have been searched
+            let lazy_contexts = match syntax.lazy_contexts.get() {
+                Some(lazy_contexts) => lazy_contexts,
+                None => continue,
+            };
+            let mut names = vec![""; lazy_contexts.contexts.len()];
+            for (name, id) in &lazy_contexts.context_ids {
+                names[id.context_index] = name;
+            }
+            for (context, name) in lazy_contexts.contexts.iter().zip(names) {
+                for (index, pattern) in context.patterns.iter().enumerate() {
+                    let match_pat = match *pattern {
+                        Pattern::Match(ref match_pat) => match_pat,
+                        Pattern::Include(_) => continue,
+                    };
+                    let address = match_pat as *const MatchPattern as usize;
+                    if let Some(stats) = stats.get(&address) {
+                        patterns.push(PatternProfile {
+                            syntax: syntax.name.clone(),
+                            context: name.to_owned(),
+                            pattern: index,
+                            regex: match_pat.regex.regex_str().to_owned(),
+                            stats: *stats,
+                        });
+                    }
+                }
+            }
+        }
+        patterns.sort_by_key(|p| Reverse(p.stats.time));
+        ProfileReport { patterns }
+    }
+
+    /// Adds the statistics of parsing (a part of) a line
+    pub(crate) fn add<'a, I>(&self, stats: I)
+    where
+        I: IntoIterator<Item = (&'a *const MatchPattern, &'a SearchStats)>,
+    {
+        let mut patterns = self.patterns.lock().unwrap();
+        for (&match_pat, stats) in stats {
+            patterns.entry(match_pat as usize).or_default().add(stats);
+        }
+    }
+}
+
+/// The statistics collected by a [`Profiler`]
+///
+/// All lists are sorted by the time of the searches, slowest first. The `Display` implementation
+/// prints them as tables.
+///
+/// [`Profiler`]: struct.Profiler.html
+#[derive(Debug, Clone)]
+pub struct ProfileReport {
+    patterns: Vec<PatternProfile>,
+}
+
+/// The statistics of a pattern in a [`ProfileReport`]
+///
+/// [`ProfileReport`]: struct.ProfileReport.html
+#[derive(Debug, Clone)]
+pub struct PatternProfile {
+    /// The name of the syntax
+    pub syntax: String,
+    /// The name of the context, anonymous contexts have generated names like `#anon_main_0`
+    pub context: String,
+    /// The index of the pattern in the context
+    pub pattern: usize,
+    /// The regex of the pattern, with backreferences not substituted
+    pub regex: String,
+    /// The statistics of the searches of the pattern
+    pub stats: SearchStats,
+}
+
+/// The statistics of all patterns of a context in a [`ProfileReport`]
+///
+/// [`ProfileReport`]: struct.ProfileReport.html
+#[derive(Debug, Clone)]
+pub struct ContextProfile {
+    /// The name of the syntax
+    pub syntax: String,
+    /// The name of the context, anonymous contexts have generated names like `#anon_main_0`
+    pub context: String,
+    /// The statistics of the patterns of the context added up, not counting included patterns
+    pub stats: SearchStats,
+}
+
+/// The statistics of all patterns of a syntax in a [`ProfileReport`]
+///
+/// [`ProfileReport`]: struct.ProfileReport.html
+#[derive(Debug, Clone)]
+pub struct SyntaxProfile {
+    /// The name of the syntax
+    pub syntax: String,
+    /// The statistics of the patterns of the syntax added up
+    pub stats: SearchStats,
+}
+
+impl ProfileReport {
+    /// The patterns that were searched
+    pub fn patterns(&self) -> &[PatternProfile] {
+        &self.patterns
+    }
+
+    /// The statistics of the patterns added up by context
+    pub fn contexts(&self) -> Vec<ContextProfile> {
+        let mut contexts: Vec<ContextProfile> = Vec::new();
+        let mut indexes = HashMap::new();
+        for pattern in &self.patterns {
+            let key = (pattern.syntax.as_str(), pattern.context.as_str());
+            let index = *indexes.entry(key).or_insert_with(|| {
+                contexts.push(ContextProfile {
+                    syntax: pattern.syntax.clone(),
+                    context: pattern.context.clone(),
+                    stats: SearchStats::default(),
+                });
+                contexts.len() - 1
+            });
+            contexts[index].stats.add(&pattern.stats);
+        }
+        contexts.sort_by_key(|p| Reverse(p.stats.time));
+        contexts
+    }
+
+    /// The statistics of the patterns added up by syntax
+    pub fn syntaxes(&self) -> Vec<SyntaxProfile> {
+        let mut syntaxes: Vec<SyntaxProfile> = Vec::new();
+        for pattern in &self.patterns {
+            match syntaxes.iter_mut().find(|s| s.syntax == pattern.syntax) {
+                Some(syntax) => syntax.stats.add(&pattern.stats),
+                None => syntaxes.push(SyntaxProfile {
+                    syntax: pattern.syntax.clone(),
+                    stats: pattern.stats,
+                }),
+            }
+        }
+        syntaxes.sort_by_key(|p| Reverse(p.stats.time));
+        syntaxes
+    }
+
+    /// The statistics of all patterns added up
+    pub fn total(&self) -> SearchStats {
+        let mut total = SearchStats::default();
+        for pattern in &self.patterns {
+            total.add(&pattern.stats);
+        }
+        total
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_row(f: &mut fmt::Formatter<'_>, stats: &SearchStats, name: &str) -> fmt::Result {
+            writeln!(
+                f,
+                "{:>12.3} {:>10} {:>10} {:>10}  {}",
+                stats.time.as_secs_f64() * 1000.0,
+                stats.searches,
+                stats.hits,
+                stats.cache_hits,
+                name
+            )
+        }
+        let header = format!(
+            "{:>12} {:>10} {:>10} {:>10}  ",
+            "time (ms)", "searches", "hits", "cache hits"
+        );
+
+        writeln!(f, "{}syntax", header)?;
+        for syntax in self.syntaxes() {
+            write_row(f, &syntax.stats, &syntax.syntax)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{}context", header)?;
+        for context in self.contexts() {
+            let name = format!("{} > {}", context.syntax, context.context);
+            write_row(f, &context.stats, &name)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{}pattern", header)?;
+        for pattern in &self.patterns {
+            let name = format!(
+                "{} > {}[{}]: {}",
+                pattern.syntax, pattern.context, pattern.pattern, pattern.regex
+            );
+            write_row(f, &pattern.stats, &name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "yaml-load")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::{ParseState, SyntaxDefinition, SyntaxSetBuilder};
+
+    #[test]
+    fn can_profile_parsing() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+            name: test
+            scope: source.test
+            contexts:
+              main:
+                - match: \d+
+                  scope: number.test
+                - match: '"'
+                  push: string
+                - match: '#'
+                  scope: never.test
+              string:
+                - match: '"'
+                  pop: true
+            "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(syntax);
+        let ss = builder.build();
+
+        let profiler = Profiler::new();
+        for _ in 0..2 {
+            let mut state = ParseState::new(&ss.syntaxes()[0]);
+            state.set_profiler(profiler.clone());
+            state.parse_line("1 \"a\" 2\n", &ss).unwrap();
+        }
+
+        let report = profiler.report(&ss);
+        let stats = |context: &str, pattern: usize| {
+            let profile = report
+                .patterns()
+                .iter()
+                .find(|p| p.context == context && p.pattern == pattern)
+                .unwrap();
+            (
+                profile.stats.searches,
+                profile.stats.hits,
+                profile.stats.cache_hits,
+            )
+        };
+        // Per line: found at 0 and 6, reused at 5, not found at 7
+        assert_eq!(stats("main", 0), (6, 4, 2));
+        // Per line: found at 2, then skipped since there's no other `"`, which is reused at 7
+        assert_eq!(stats("main", 1), (2, 2, 2));
+        // Never searched since there's no `#`, which is reused at 5 and 7
+        assert_eq!(stats("main", 2), (0, 0, 4));
+        assert_eq!(stats("string", 0), (2, 2, 0));
+
+        let contexts = report.contexts();
+        let main = contexts.iter().find(|c| c.context == "main").unwrap();
+        assert_eq!(main.stats.searches, 8);
+        assert_eq!(report.syntaxes()[0].syntax, "test");
+        assert_eq!(report.syntaxes()[0].stats, report.total());
+        assert!(report.to_string().contains("test > main[0]: \\d+"));
+
+        profiler.reset();
+        assert_eq!(profiler.report(&ss).total(), SearchStats::default());
+    }
+
+    #[test]
+    fn keeps_stats_when_parsing_fails() {
+        let syntax = SyntaxDefinition::load_from_str(
+            "name: test\nscope: source.test\ncontexts: {main: [{match: a, push: missing}]}",
+            true,
+            None,
+        )
+        .unwrap();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(syntax);
+        let ss = builder.build();
+
+        let profiler = Profiler::new();
+        let mut state = ParseState::new(&ss.syntaxes()[0]);
+        state.set_profiler(profiler.clone());
+        assert!(state.parse_line("a\n", &ss).is_err());
+        assert_eq!(profiler.report(&ss).patterns()[0].stats.hits, 1);
+    }
+}
@@ -1,3 +1,4 @@
+use super::prefilter::Prefilter;
 use once_cell::sync::OnceCell;
 use serde::de::{Deserialize, Deserializer};
 use serde::ser::{Serialize, Serializer};
@@ -12,6 +13,7 @@ use std::error::Error;
 pub struct Regex {
     regex_str: String,
     regex: OnceCell<regex_impl::Regex>,
+    prefilter: OnceCell<Prefilter>,
 }
 
 /// A region contains text positions for capture groups in a match result.
@@ -29,6 +31,7 @@ impl Regex {
         Self {
             regex_str,
             regex: OnceCell::new(),
+            prefilter: OnceCell::new(),
         }
     }
 
@@ -65,6 +68,18 @@ impl Regex {
             .search(text, begin, end, region.map(|r| &mut r.region))
     }
 
+    /// Returns the first position from `begin` where the regex could match, or `None` if it
+    /// can't match after `begin`.
+    ///
+    /// This is much faster than searching, since it only looks for the literals that the matches
+    /// have to start with. Like the compiled regex, they are found on first use. For regexes that
+    /// don't start with literals, `begin` is returned.
+    pub(crate) fn first_possible_match(&self, text: &str, begin: usize) -> Option<usize> {
+        self.prefilter
+            .get_or_init(|| Prefilter::new(&self.regex_str))
+            .find(text, begin)
+    }
+
     fn regex(&self) -> &regex_impl::Regex {
         self.regex.get_or_init(|| {
             regex_impl::Regex::new(&self.regex_str).expect("regex string should be pre-tested")
@@ -77,6 +92,7 @@ impl Clone for Regex {
         Regex {
             regex_str: self.regex_str.clone(),
             regex: OnceCell::new(),
+            prefilter: OnceCell::new(),
         }
     }
 }
@@ -130,8 +146,13 @@ impl Default for Region {
     }
 }
 
+#[cfg(not(feature = "regex-hybrid"))]
+use self::backtracking_impl as regex_impl;
+#[cfg(feature = "regex-hybrid")]
+use self::hybrid_impl as regex_impl;
+
 #[cfg(feature = "regex-onig")]
-mod regex_impl {
+mod backtracking_impl {
     pub use onig::Region;
     use onig::{MatchParam, RegexOptions, SearchOptions, Syntax};
     use std::error::Error;
@@ -190,7 +211,7 @@ mod regex_impl {
 
 // If both regex-fancy and regex-onig are requested, this condition makes regex-onig win.
 #[cfg(all(feature = "regex-fancy", not(feature = "regex-onig")))]
-mod regex_impl {
+mod backtracking_impl {
     use std::error::Error;
 
     #[derive(Debug)]
@@ -260,6 +281,124 @@ mod regex_impl {
                 None
             }
         }
+
+        #[cfg(feature = "regex-hybrid")]
+        pub fn len(&self) -> usize {
+            self.positions.len()
+        }
+    }
+}
+
+// Regexes that don't need backtracking are compiled with the `regex` crate, the others with the
+// backtracking engine from above. The `regex` crate can't compile lookarounds and backreferences,
+// so it is simply tried first.
+#[cfg(feature = "regex-hybrid")]
+mod hybrid_impl {
+    use super::backtracking_impl;
+    use std::error::Error;
+
+    #[derive(Debug)]
+    pub enum Regex {
+        // Boxed to keep `MatchPattern` small
+        Automaton(Box<regex::Regex>),
+        Backtracking(backtracking_impl::Regex),
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Region {
+        positions: Vec<Option<(usize, usize)>>,
+        /// Reused for the searches of the backtracking engine
+        backtracking: backtracking_impl::Region,
+    }
+
+    impl PartialEq for Region {
+        fn eq(&self, other: &Region) -> bool {
+            self.positions == other.positions
+        }
+    }
+
+    impl Eq for Region {}
+
+    pub fn new_region() -> Region {
+        Region {
+            positions: Vec::with_capacity(8),
+            backtracking: backtracking_impl::new_region(),
+        }
+    }
+
+    impl Regex {
+        pub fn new(regex_str: &str) -> Result<Regex, Box<dyn Error + Send + Sync + 'static>> {
+            match regex::Regex::new(regex_str) {
+                Ok(regex) => Ok(Regex::Automaton(Box::new(regex))),
+                Err(_) => backtracking_impl::Regex::new(regex_str).map(Regex::Backtracking),
+            }
+        }
+
+        pub fn is_match(&self, text: &str) -> bool {
+            match *self {
+                Regex::Automaton(ref regex) => regex.is_match(text),
+                Regex::Backtracking(ref regex) => regex.is_match(text),
+            }
+        }
+
+        pub fn search(
+            &self,
+            text: &str,
+            begin: usize,
+            end: usize,
+            region: Option<&mut Region>,
+        ) -> bool {
+            let regex = match *self {
+                Regex::Automaton(ref regex) => regex,
+                Regex::Backtracking(ref regex) => {
+                    return match region {
+                        Some(region) => {
+                            let matched =
+                                regex.search(text, begin, end, Some(&mut region.backtracking));
+                            if matched {
+                                let backtracking = &region.backtracking;
+                                region.positions.clear();
+                                region
+                                    .positions
+                                    .extend((0..backtracking.len()).map(|i| backtracking.pos(i)));
+                            }
+                            matched
+                        }
+                        None => regex.search(text, begin, end, None),
+                    };
+                }
+            };
+            let text = &text[..end];
+            match region {
+                // Finding the capture groups is slower, so they are only searched if there are any
+                Some(region) if regex.captures_len() > 1 => {
+                    if let Some(captures) = regex.captures_at(text, begin) {
+                        region.positions.clear();
+                        let positions = captures.iter().map(|m| m.map(|m| (m.start(), m.end())));
+                        region.positions.extend(positions);
+                        true
+                    } else {
+                        false
+                    }
+                }
+                Some(region) => {
+                    if let Some(m) = regex.find_at(text, begin) {
+                        region.positions.clear();
+                        region.positions.push(Some((m.start(), m.end())));
+                        true
+                    } else {
+                        false
+                    }
+                }
+                None => regex.find_at(text, begin).is_some(),
+            }
+        }
+    }
+
+    impl Region {
+        pub fn pos(&self, i: usize) -> Option<(usize, usize)> {
+            self.positions.get(i).copied().flatten()
+        }
     }
 }
 
@@ -276,6 +415,26 @@ mod tests {
         assert!(regex.regex.get().is_some());
     }
 
+    #[cfg(feature = "regex-hybrid")]
+    #[test]
+    fn hybrid_falls_back_to_backtracking() {
+        let regex = Regex::new(String::from(r"(a)|(b)+"));
+        assert!(matches!(regex.regex(), regex_impl::Regex::Automaton(_)));
+        let mut region = Region::new();
+        assert!(regex.search("xbb", 0, 3, Some(&mut region)));
+        assert_eq!(region.pos(0), Some((1, 3)));
+        assert_eq!(region.pos(1), None);
+        assert_eq!(region.pos(2), Some((2, 3)));
+
+        let regex = Regex::new(String::from(r"(?<=x)(b)\1"));
+        assert!(matches!(regex.regex(), regex_impl::Regex::Backtracking(_)));
+        assert!(regex.search("bbxbb", 1, 5, Some(&mut region)));
+        assert_eq!(region.pos(0), Some((3, 5)));
+        assert_eq!(region.pos(1), Some((3, 4)));
+        assert_eq!(region.pos(2), None);
+        assert!(!regex.search("bbxbb", 4, 5, None));
+    }
+
     #[test]
     fn serde_as_string() {
         let pattern: Regex = serde_json::from_str("\"just a string\"").unwrap();
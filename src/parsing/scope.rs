@@ -5,8 +5,6 @@ use std::fmt;
 use std::mem;
 use std::str::FromStr;
 use std::sync::Mutex;
-use std::u16;
-use std::u64;
 
 use once_cell::sync::Lazy;
 use serde::de::{Deserialize, Deserializer, Error, Visitor};
@@ -118,7 +116,7 @@ pub enum ClearAmount {
 /// The change from a `ScopeStackOp` can be applied via [`ScopeStack::apply`].
 ///
 /// [`ScopeStack::apply`]: struct.ScopeStack.html#method.apply
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ScopeStackOp {
     Push(Scope),
     Pop(usize),
@@ -379,7 +377,7 @@ impl<'de> Deserialize<'de> for Scope {
 
 /// Wrapper to get around the fact Rust `f64` doesn't implement `Ord` and there is no non-NaN
 /// float type
-#[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialOrd, PartialEq, Serialize, Deserialize)]
 pub struct MatchPower(pub f64);
 
 impl Eq for MatchPower {}
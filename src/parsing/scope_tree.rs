@@ -0,0 +1,433 @@
+use super::parser::ParseState;
+use super::scope::{BasicScopeStackOp, Scope, ScopeStack};
+use super::syntax_set::{SyntaxReference, SyntaxSet};
+use crate::highlighting::{ScopeSelector, ScopeSelectors};
+use crate::Error;
+use std::ops::Range;
+
+/// A scope in a [`ScopeTree`] with the byte range of the text it applies to, which can span
+/// several lines
+///
+/// [`ScopeTree`]: struct.ScopeTree.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeNode {
+    /// The scope the node applies to its range
+    pub scope: Scope,
+    /// The byte range in the whole text
+    pub range: Range<usize>,
+    /// The index of the node this one is nested in, if any
+    pub parent: Option<usize>,
+    /// The indexes of the nodes nested in this one, in order
+    pub children: Vec<usize>,
+}
+
+/// The scopes of a text as a tree of nested ranges, built with a [`ScopeTreeBuilder`]
+///
+/// Nodes are referred to by their index in [`nodes`], which are in the order they start in, with
+/// each node before the nodes nested in it.
+///
+/// [`ScopeTreeBuilder`]: struct.ScopeTreeBuilder.html
+/// [`nodes`]: #method.nodes
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::ScopeSelector;
+/// use syntect::parsing::{ScopeTreeBuilder, SyntaxSet};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let mut builder = ScopeTreeBuilder::new(ss.find_syntax_by_extension("rs").unwrap());
+/// for line in ["fn main(\n", "    x: u8,\n", ") {}\n"] {
+///     builder.parse_line(line, &ss).unwrap();
+/// }
+/// let tree = builder.build();
+///
+/// let selector = "meta.function entity.name.function".parse::<ScopeSelector>().unwrap();
+/// let name = tree.nodes_matching(&selector)[0];
+/// assert_eq!(tree.nodes()[name].range, 3..7);
+/// let param = tree.innermost_at(13).unwrap();
+/// assert_eq!(tree.nodes()[param].scope.to_string(), "variable.parameter.rust");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScopeTree {
+    nodes: Vec<ScopeNode>,
+    roots: Vec<usize>,
+}
+
+impl ScopeTree {
+    /// All nodes, which the indexes in the nodes and queries refer to
+    pub fn nodes(&self) -> &[ScopeNode] {
+        &self.nodes
+    }
+
+    /// The indexes of the nodes that aren't nested in another node, in order. These can be
+    /// several nodes with the scope of the syntax when it uses `clear_scopes`, see
+    /// [`ScopeTreeBuilder`].
+    ///
+    /// [`ScopeTreeBuilder`]: struct.ScopeTreeBuilder.html
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    /// Returns the indexes of the nodes containing the byte offset, outermost first
+    pub fn path_at(&self, offset: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        let mut siblings = &self.roots;
+        // Siblings don't overlap and are ordered by start, so only the last one starting at or
+        // before the offset can contain it
+        loop {
+            let before = siblings.partition_point(|&index| self.nodes[index].range.start <= offset);
+            match before.checked_sub(1).map(|i| siblings[i]) {
+                Some(index) if self.nodes[index].range.contains(&offset) => {
+                    path.push(index);
+                    siblings = &self.nodes[index].children;
+                }
+                _ => return path,
+            }
+        }
+    }
+
+    /// Returns the index of the most deeply nested node containing the byte offset, if any
+    pub fn innermost_at(&self, offset: usize) -> Option<usize> {
+        self.path_at(offset).last().copied()
+    }
+
+    /// Returns the scope stack of a node, which are the scopes of the nodes it is nested in
+    /// followed by its own scope
+    pub fn scope_stack(&self, index: usize) -> ScopeStack {
+        let mut scopes = Vec::new();
+        let mut current = Some(index);
+        while let Some(index) = current {
+            scopes.push(self.nodes[index].scope);
+            current = self.nodes[index].parent;
+        }
+        scopes.reverse();
+        ScopeStack::from_vec(scopes)
+    }
+
+    /// Returns the indexes of the nodes that the selector matches, in order.
+    ///
+    /// A node matches if the selector matches its scope stack with the last scope of the
+    /// selector matching the scope of the node, so `meta.function entity.name` matches the name
+    /// of a function but not the nodes nested in the name.
+    pub fn nodes_matching(&self, selector: &ScopeSelector) -> Vec<usize> {
+        let mut matching = Vec::new();
+        let mut stack = Vec::new();
+        let mut parents: Vec<usize> = Vec::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            // The nodes are in pre-order, so the parent of a node is on the stack
+            while parents.last().copied() != node.parent {
+                parents.pop();
+                stack.pop();
+            }
+            parents.push(index);
+            stack.push(node.scope);
+
+            let last_matches = selector
+                .path
+                .as_slice()
+                .last()
+                .into_iter()
+                .all(|scope| scope.is_prefix_of(node.scope));
+            if last_matches && selector.does_match(&stack).is_some() {
+                matching.push(index);
+            }
+        }
+        matching
+    }
+
+    /// Like [`nodes_matching`] for any of the selectors
+    ///
+    /// [`nodes_matching`]: #method.nodes_matching
+    pub(crate) fn nodes_matching_any(&self, selectors: &ScopeSelectors) -> Vec<usize> {
+        let mut indexes: Vec<usize> = selectors
+            .selectors
+            .iter()
+            .flat_map(|selector| self.nodes_matching(selector))
+            .collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        indexes
+    }
+
+    /// Opens a node for the scope, or reopens the previous sibling if it has the same scope, ends
+    /// at the offset and is one of the `continuable` nodes
+    fn open_node(
+        &mut self,
+        open: &mut Vec<usize>,
+        scope: Scope,
+        offset: usize,
+        continuable: &[usize],
+    ) {
+        let index = self.nodes.len();
+        let parent = open.last().copied();
+        let previous = match parent {
+            Some(parent) => self.nodes[parent].children.last(),
+            None => self.roots.last(),
+        };
+        if let Some(&previous) = previous {
+            let node = &self.nodes[previous];
+            if node.scope == scope && node.range.end == offset && continuable.contains(&previous) {
+                open.push(previous);
+                return;
+            }
+        }
+        match parent {
+            Some(parent) => self.nodes[parent].children.push(index),
+            None => self.roots.push(index),
+        }
+        self.nodes.push(ScopeNode {
+            scope,
+            range: offset..offset,
+            parent,
+            children: Vec::new(),
+        });
+        open.push(index);
+    }
+
+    /// Closes the innermost open node and returns its index
+    fn close_node(&mut self, open: &mut Vec<usize>, offset: usize) -> Option<usize> {
+        let index = open.pop()?;
+        self.nodes[index].range.end = offset;
+        Some(index)
+    }
+}
+
+/// Builds a [`ScopeTree`] by parsing a text line by line
+///
+/// When a match pops a scope and pushes it again at the same position, which the parser does
+/// with the meta scopes of contexts that a match changes, the node continues instead of being
+/// split in two. Adjacent ranges of the same scope from separate matches stay separate nodes.
+///
+/// Scopes removed with `clear_scopes` end where they are cleared and start again as new nodes
+/// where they are restored, as the text in between doesn't have them. Clearing all scopes splits
+/// the scope of the syntax into several roots, with the nodes of the cleared text between them.
+///
+/// The tree has to be built from all lines of a text, starting at the first one. Lines that a
+/// `fail` rewinds to are not updated, so the parse state should not allow rewinding to previous
+/// lines, see [`ParseState::set_branch_lookahead`].
+///
+/// [`ScopeTree`]: struct.ScopeTree.html
+/// [`ParseState::set_branch_lookahead`]: struct.ParseState.html#method.set_branch_lookahead
+#[derive(Debug, Clone)]
+pub struct ScopeTreeBuilder {
+    state: ParseState,
+    stack: ScopeStack,
+    tree: ScopeTree,
+    /// The indexes of the nodes of the scopes on the stack
+    open: Vec<usize>,
+    /// The offset of the start of the next line
+    offset: usize,
+}
+
+impl ScopeTreeBuilder {
+    /// Creates a builder for parsing a text with a syntax from the start
+    pub fn new(syntax: &SyntaxReference) -> ScopeTreeBuilder {
+        ScopeTreeBuilder::with_state(ParseState::new(syntax), ScopeStack::new())
+    }
+
+    /// Creates a builder that continues from a parse state and the scope stack at that point,
+    /// e.g. from [`ParseState::with_contexts`]. The scopes already on the stack become nodes
+    /// that start at offset 0.
+    ///
+    /// [`ParseState::with_contexts`]: struct.ParseState.html#method.with_contexts
+    pub fn with_state(state: ParseState, stack: ScopeStack) -> ScopeTreeBuilder {
+        let mut tree = ScopeTree::default();
+        let mut open = Vec::new();
+        for scope in stack.as_slice() {
+            tree.open_node(&mut open, *scope, 0, &[]);
+        }
+        ScopeTreeBuilder {
+            state,
+            stack,
+            tree,
+            open,
+            offset: 0,
+        }
+    }
+
+    /// Parses the next line of the text and adds its scopes to the tree
+    pub fn parse_line(&mut self, line: &str, syntax_set: &SyntaxSet) -> Result<(), Error> {
+        let mut match_starts = Vec::new();
+        let ops = self
+            .state
+            .parse_line_with_match_starts(line, syntax_set, &mut match_starts)?;
+        // closures mess with the borrow checker's ability to see different struct fields
+        let tree = &mut self.tree;
+        let open = &mut self.open;
+        // The nodes closed since the start of the current match, which a push in the same match
+        // continues
+        let mut closed = Vec::new();
+        let mut match_starts = match_starts.into_iter().peekable();
+        for (i, (index, op)) in ops.into_iter().enumerate() {
+            // Matches without ops start at the same index as the next one
+            if match_starts.peek() == Some(&i) {
+                closed.clear();
+                while match_starts.next_if_eq(&i).is_some() {}
+            }
+            let offset = self.offset + index;
+            self.stack
+                .apply_with_hook(&op, |basic_op, _| match basic_op {
+                    BasicScopeStackOp::Push(scope) => tree.open_node(open, scope, offset, &closed),
+                    BasicScopeStackOp::Pop => closed.extend(tree.close_node(open, offset)),
+                })?;
+        }
+        self.offset += line.len();
+        Ok(())
+    }
+
+    /// The scope stack after the lines parsed so far
+    #[cfg(feature = "metadata")]
+    pub(crate) fn scope_stack(&self) -> &ScopeStack {
+        &self.stack
+    }
+
+    /// Finishes the tree, with the scopes that are still open ending at the end of the text
+    pub fn build(mut self) -> ScopeTree {
+        while !self.open.is_empty() {
+            self.tree.close_node(&mut self.open, self.offset);
+        }
+        self.tree
+    }
+}
+
+#[cfg(feature = "yaml-load")]
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::parsing::{SyntaxDefinition, SyntaxSetBuilder};
+
+    /// Builds a syntax set with a syntax from each of the sources
+    pub(crate) fn syntax_set(sources: &[&str]) -> SyntaxSet {
+        let mut builder = SyntaxSetBuilder::new();
+        for source in sources {
+            builder.add(SyntaxDefinition::load_from_str(source, true, None).unwrap());
+        }
+        builder.build()
+    }
+
+    /// Parses all lines of the text into a tree
+    pub(crate) fn tree(text: &str, syntax: &SyntaxReference, syntax_set: &SyntaxSet) -> ScopeTree {
+        let mut builder = ScopeTreeBuilder::new(syntax);
+        for line in text.split_inclusive('\n') {
+            builder.parse_line(line, syntax_set).unwrap();
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn can_build_tree_across_lines() {
+        let ss = syntax_set(&[r#"
+                name: Test
+                scope: source.test
+                contexts:
+                  main:
+                    - match: fn
+                      scope: keyword
+                      push: function
+                  function:
+                    - meta_scope: meta.function
+                    - match: \w+
+                      scope: entity.name
+                    - match: '\('
+                      push: params
+                    - match: ;
+                      pop: true
+                  params:
+                    - meta_scope: meta.params
+                    - match: \w+
+                      scope: variable.parameter
+                    - match: ','
+                      set: params
+                    - match: '\)'
+                      pop: true
+                "#]);
+
+        let tree = tree("fn f(a,\nb)();\nx\n", &ss.syntaxes()[0], &ss);
+        let nodes: Vec<(String, Range<usize>, Option<usize>)> = tree
+            .nodes()
+            .iter()
+            .map(|node| (node.scope.to_string(), node.range.clone(), node.parent))
+            .collect();
+        let expected = [
+            ("source.test", 0..16, None),
+            ("meta.function", 0..13, Some(0)),
+            ("keyword", 0..2, Some(1)),
+            ("entity.name", 3..4, Some(1)),
+            ("meta.params", 4..10, Some(1)),
+            ("variable.parameter", 5..6, Some(4)),
+            // The `set` continues the node and nests the comma in another one
+            ("meta.params", 6..7, Some(4)),
+            ("variable.parameter", 8..9, Some(4)),
+            // Popping and pushing again in separate matches starts a new node
+            ("meta.params", 10..12, Some(1)),
+        ];
+        let expected: Vec<(String, Range<usize>, Option<usize>)> = expected
+            .iter()
+            .map(|(scope, range, parent)| (scope.to_string(), range.clone(), *parent))
+            .collect();
+        assert_eq!(nodes, expected);
+        assert_eq!(tree.roots(), &[0]);
+        assert_eq!(tree.nodes()[1].children, vec![2, 3, 4, 8]);
+        assert_eq!(tree.nodes()[4].children, vec![5, 6, 7]);
+
+        assert_eq!(tree.path_at(8), vec![0, 1, 4, 7]);
+        assert_eq!(tree.innermost_at(7), Some(4));
+        assert_eq!(tree.innermost_at(13), Some(0));
+        assert_eq!(tree.innermost_at(16), None);
+        assert_eq!(
+            tree.scope_stack(5).to_string().trim_end(),
+            "source.test meta.function meta.params variable.parameter"
+        );
+
+        let selector = |s: &str| s.parse::<ScopeSelector>().unwrap();
+        assert_eq!(
+            tree.nodes_matching(&selector("meta.params variable")),
+            [5, 7]
+        );
+        assert_eq!(tree.nodes_matching(&selector("meta.function")), [1]);
+        assert_eq!(
+            tree.nodes_matching(&selector("meta.function - meta.params")),
+            [1]
+        );
+        assert_eq!(tree.nodes_matching(&selector("meta - meta.params")), [1]);
+        assert_eq!(tree.nodes_matching(&selector("source")), [0]);
+    }
+
+    #[test]
+    fn clearing_scopes_splits_nodes() {
+        let ss = syntax_set(&[r#"
+                name: Test
+                scope: source.test
+                contexts:
+                  main:
+                    - match: '<'
+                      push: cleared
+                  cleared:
+                    - clear_scopes: true
+                    - meta_scope: meta.cleared
+                    - match: '>'
+                      pop: true
+                "#]);
+
+        let tree = tree("a<b>c\n", &ss.syntaxes()[0], &ss);
+        let roots: Vec<(String, Range<usize>)> = tree
+            .roots()
+            .iter()
+            .map(|&index| {
+                let node = &tree.nodes()[index];
+                (node.scope.to_string(), node.range.clone())
+            })
+            .collect();
+        let expected = [
+            ("source.test", 0..1),
+            ("meta.cleared", 1..4),
+            ("source.test", 4..6),
+        ];
+        let expected: Vec<(String, Range<usize>)> = expected
+            .iter()
+            .map(|(scope, range)| (scope.to_string(), range.clone()))
+            .collect();
+        assert_eq!(roots, expected);
+    }
+}
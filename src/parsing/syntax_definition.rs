@@ -12,6 +12,7 @@ use serde::ser::{Serialize, Serializer};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
 use std::hash::Hash;
+use std::str::FromStr;
 
 pub type CaptureMapping = Vec<(usize, Vec<Scope>)>;
 
@@ -33,19 +34,47 @@ pub struct ContextId {
 /// Some useful public fields are the `name` field which is a human readable name to display in
 /// syntax lists, and the `hidden` field which means hide this syntax from any lists because it is
 /// for internal use.
+///
+/// To fill in a syntax definition yourself, start from [`SyntaxDefinition::default`], since fields
+/// can be added in new versions.
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct SyntaxDefinition {
     pub name: String,
     pub file_extensions: Vec<String>,
     pub scope: Scope,
     pub first_line_match: Option<String>,
     pub hidden: bool,
+    /// The `version` of the syntax definition format, `1` if not specified. Version 2 changes
+    /// how some scopes are applied, see [`ParseState`](../struct.ParseState.html).
+    pub version: u8,
+    /// For TextMate injection grammars, the selector of the scopes their patterns are injected
+    /// into. When building a [`SyntaxSet`](../struct.SyntaxSet.html), the `main` context of this
+    /// syntax is included in the contexts of the other syntaxes that match the selector.
+    pub injection_selector: Option<String>,
     #[serde(serialize_with = "ordered_map")]
     pub variables: HashMap<String, String>,
     #[serde(serialize_with = "ordered_map")]
     pub contexts: HashMap<String, Context>,
 }
 
+impl Default for SyntaxDefinition {
+    /// An empty syntax definition with version 1 of the format
+    fn default() -> SyntaxDefinition {
+        SyntaxDefinition {
+            name: String::new(),
+            file_extensions: Vec::new(),
+            scope: Scope::default(),
+            first_line_match: None,
+            hidden: false,
+            version: 1,
+            injection_selector: None,
+            variables: HashMap::new(),
+            contexts: HashMap::new(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Context {
     pub meta_scope: Vec<Scope>,
@@ -91,6 +120,8 @@ pub struct MatchIter<'a> {
     syntax_set: &'a SyntaxSet,
     ctx_stack: Vec<&'a Context>,
     index_stack: Vec<usize>,
+    /// The IDs of the contexts on the stack, if known
+    id_stack: Vec<Option<ContextId>>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -131,12 +162,30 @@ pub enum ContextReference {
     Direct(ContextId),
 }
 
+/// What a pattern does to the context stack when it matches
+///
+/// New kinds of operations can be added in new versions, so matches on this need a wildcard arm.
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum MatchOperation {
     Push(Vec<ContextReference>),
     Set(Vec<ContextReference>),
     Pop,
     None,
+    /// A `branch_point`: pushes the first of the contexts, and the next one each time a `fail` with
+    /// the same name rewinds the parser to this match
+    Branch {
+        name: String,
+        contexts: Vec<ContextReference>,
+    },
+    /// Rewinds the parser to the last `branch_point` with this name and tries its next alternative
+    Fail(String),
+    /// Pops the given number of contexts and then pushes the contexts, `pop` combined with `push`
+    PopAndPush(usize, Vec<ContextReference>),
+    /// Pops the given number of contexts and then sets the contexts, `pop` combined with `set`
+    PopAndSet(usize, Vec<ContextReference>),
+    /// Pops the given number of contexts, from `pop` with a number greater than 1
+    PopMultiple(usize),
 }
 
 impl<'a> Iterator for MatchIter<'a> {
@@ -169,11 +218,13 @@ impl<'a> Iterator for MatchIter<'a> {
                         };
                         self.ctx_stack.push(ctx_ptr);
                         self.index_stack.push(0);
+                        self.id_stack.push(ctx_ref.id().ok());
                     }
                 }
             } else {
                 self.ctx_stack.pop();
                 self.index_stack.pop();
+                self.id_stack.pop();
             }
         }
     }
@@ -188,6 +239,21 @@ pub fn context_iter<'a>(syntax_set: &'a SyntaxSet, context: &'a Context) -> Matc
         syntax_set,
         ctx_stack: vec![context],
         index_stack: vec![0],
+        id_stack: vec![None],
+    }
+}
+
+impl MatchIter<'_> {
+    /// Sets the ID of the context that is iterated, before iterating
+    pub(crate) fn with_context_id(mut self, id: ContextId) -> Self {
+        self.id_stack[0] = Some(id);
+        self
+    }
+
+    /// The ID of the context of the last returned pattern, which is only known for included
+    /// contexts and the iterated context if it was set
+    pub(crate) fn context_id(&self) -> Option<ContextId> {
+        self.id_stack.last().copied().flatten()
     }
 }
 
@@ -203,10 +269,16 @@ impl Context {
 
 impl ContextReference {
     /// find the pointed to context
+    ///
+    /// References by scope or file are looked up in the syntax set like when linking it, while
+    /// names and inline contexts can only be resolved relative to a syntax.
     pub fn resolve<'a>(&self, syntax_set: &'a SyntaxSet) -> Result<&'a Context, ParsingError> {
         match *self {
             ContextReference::Direct(ref context_id) => syntax_set.get_context(context_id),
-            _ => Err(ParsingError::UnresolvedContextReference(self.clone())),
+            _ => match syntax_set.find_context_id(self, None) {
+                Some(context_id) => syntax_set.get_context(&context_id),
+                None => Err(ParsingError::UnresolvedContextReference(self.clone())),
+            },
         }
     }
 
@@ -219,6 +291,53 @@ impl ContextReference {
     }
 }
 
+/// An invalid [`ContextReference`] in a string
+///
+/// [`ContextReference`]: enum.ContextReference.html
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ParseContextReferenceError {
+    /// The scope of a `scope:` reference is invalid
+    #[error("Invalid scope: {0}")]
+    InvalidScope(#[from] ParseScopeError),
+    /// A context name that is empty or has a `#` part, which only references to other syntaxes
+    /// can have
+    #[error("Invalid context name: {0}")]
+    InvalidName(String),
+}
+
+impl FromStr for ContextReference {
+    type Err = ParseContextReferenceError;
+
+    /// Parses a reference the way it's written in a `.sublime-syntax` file, e.g.
+    /// `scope:source.js#expression`, `Packages/JavaScript/JavaScript.sublime-syntax` or the name
+    /// of a context
+    fn from_str(s: &str) -> Result<ContextReference, ParseContextReferenceError> {
+        let (target, sub_context) = match s.split_once('#') {
+            Some((target, sub_context)) => (target, Some(sub_context.to_owned())),
+            None => (s, None),
+        };
+        if let Some(scope) = target.strip_prefix("scope:") {
+            Ok(ContextReference::ByScope {
+                scope: Scope::new(scope)?,
+                sub_context,
+                with_escape: false,
+            })
+        } else if target.ends_with(".sublime-syntax") {
+            let name = target.rsplit('/').next().unwrap_or(target);
+            Ok(ContextReference::File {
+                name: name.trim_end_matches(".sublime-syntax").to_owned(),
+                sub_context,
+                with_escape: false,
+            })
+        } else if target.is_empty() || sub_context.is_some() {
+            Err(ParseContextReferenceError::InvalidName(s.to_owned()))
+        } else {
+            Ok(ContextReference::Named(target.to_owned()))
+        }
+    }
+}
+
 pub(crate) fn substitute_backrefs_in_regex<F>(regex_str: &str, substituter: F) -> String
 where
     F: Fn(usize) -> Option<String>,
@@ -273,6 +392,15 @@ impl MatchPattern {
         Regex::new(new_regex)
     }
 
+    /// Like `regex_with_refs`, with the texts of the capture groups by index
+    pub(crate) fn regex_with_captures(&self, captures: &[Option<String>]) -> Regex {
+        let new_regex = substitute_backrefs_in_regex(self.regex.regex_str(), |i| {
+            captures.get(i)?.as_deref().map(escape)
+        });
+
+        Regex::new(new_regex)
+    }
+
     pub fn regex(&self) -> &Regex {
         &self.regex
     }
@@ -7,15 +7,25 @@ use super::metadata::{LoadMetadata, Metadata, RawMetadataEntry};
 
 #[cfg(feature = "yaml-load")]
 use super::super::LoadingError;
+#[cfg(feature = "yaml-load")]
+use super::{LocatedSyntaxError, ParseSyntaxError, SyntaxLocation};
 
 use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::File;
+use std::hash::Hasher;
 use std::io::{self, BufRead, BufReader};
 use std::mem;
 use std::path::Path;
+#[cfg(feature = "yaml-load")]
+use std::path::PathBuf;
+use std::str::FromStr;
 
+#[cfg(feature = "regex-hybrid")]
+use super::pattern_set::{LazyPatternSet, PatternSet};
 use super::regex::Regex;
+use crate::highlighting::ScopeSelectors;
 use crate::parsing::syntax_definition::ContextId;
+use fnv::FnvHasher;
 use once_cell::sync::OnceCell;
 use serde_derive::{Deserialize, Serialize};
 
@@ -36,6 +46,8 @@ pub struct SyntaxSet {
 
     #[serde(skip_serializing, skip_deserializing, default = "OnceCell::new")]
     first_line_cache: OnceCell<FirstLineCache>,
+    #[serde(skip_serializing, skip_deserializing, default = "OnceCell::new")]
+    fingerprint: OnceCell<u64>,
     /// Metadata, e.g. indent and commenting information.
     ///
     /// NOTE: if serializing, you should handle metadata manually; that is, you should serialize and
@@ -63,11 +75,18 @@ pub struct SyntaxReference {
 }
 
 /// The lazy-loaded parts of a [`SyntaxReference`].
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize)]
 pub(crate) struct LazyContexts {
     #[serde(serialize_with = "ordered_map")]
     pub(crate) context_ids: HashMap<String, ContextId>,
     pub(crate) contexts: Vec<Context>,
+    /// The `version` of the syntax. It comes last, so that it can be left out of dumps made
+    /// before syntax versions were supported.
+    pub(crate) version: u8,
+    /// The pattern set of each context, built once the context has been searched often enough
+    #[cfg(feature = "regex-hybrid")]
+    #[serde(skip)]
+    pattern_sets: OnceCell<Vec<LazyPatternSet>>,
 }
 
 /// A syntax set builder is used for loading syntax definitions from the file
@@ -96,16 +115,180 @@ pub struct SyntaxSetBuilder {
 #[cfg(feature = "yaml-load")]
 fn load_syntax_file(
     p: &Path,
+    source: &str,
+    lines_include_newline: bool,
+    syntax_files: &[(PathBuf, String)],
+    diagnostics: Option<&mut Vec<LoadingError>>,
+) -> Result<SyntaxDefinition, LoadingError> {
+    let fallback_name = p.file_stem().and_then(|x| x.to_str());
+    let resolve_parent =
+        |extends: &str| find_parent_source(syntax_files, extends).map(str::to_owned);
+    let result = match diagnostics {
+        Some(diagnostics) => SyntaxDefinition::load_from_str_lenient(
+            source,
+            lines_include_newline,
+            fallback_name,
+            resolve_parent,
+        )
+        .map(|(syntax, errors)| {
+            diagnostics.extend(errors.into_iter().map(|e| parse_syntax_error(e, p)));
+            syntax
+        }),
+        None => SyntaxDefinition::load_from_str_with_parents(
+            source,
+            lines_include_newline,
+            fallback_name,
+            resolve_parent,
+        ),
+    };
+    result.map_err(|e| parse_syntax_error(e, p))
+}
+
+/// Loads a TextMate grammar that was parsed into a JSON value, leniently if `diagnostics` are
+/// given
+#[cfg(feature = "yaml-load")]
+fn load_textmate_file(
+    p: &Path,
+    grammar: Result<serde_json::Value, ParseSyntaxError>,
+    fallback_name: Option<&std::ffi::OsStr>,
     lines_include_newline: bool,
+    diagnostics: Option<&mut Vec<LoadingError>>,
 ) -> Result<SyntaxDefinition, LoadingError> {
-    let s = std::fs::read_to_string(p)?;
+    let grammar = grammar.map_err(|e| parse_syntax_error(e, p))?;
+    let fallback_name = fallback_name.and_then(|x| x.to_str());
+    let mut errors = Vec::new();
+    let lenient = diagnostics.is_some();
+    let syntax = super::tmlanguage_load::load_textmate_grammar(
+        &grammar,
+        lines_include_newline,
+        fallback_name,
+        if lenient { Some(&mut errors) } else { None },
+    )
+    .map_err(|e| parse_syntax_error(e, p))?;
+    if let Some(diagnostics) = diagnostics {
+        diagnostics.extend(errors.into_iter().map(|e| parse_syntax_error(e, p)));
+    }
+    Ok(syntax)
+}
 
-    SyntaxDefinition::load_from_str(
-        &s,
+/// Turns an error in a syntax file into a `LoadingError` with the path of the file, and the place
+/// in it if known
+#[cfg(feature = "yaml-load")]
+fn parse_syntax_error(e: impl Into<LocatedSyntaxError>, p: &Path) -> LoadingError {
+    let e = e.into();
+    let path = format!("{}", p.display());
+    if *e.location() == SyntaxLocation::default() {
+        LoadingError::ParseSyntax(e.into_error(), path)
+    } else {
+        LoadingError::LocatedParseSyntax(e, path)
+    }
+}
+
+/// Returns the name of a `.tmLanguage.json` file without the extensions
+#[cfg(feature = "yaml-load")]
+fn tmlanguage_json_name(p: &Path) -> Option<&std::ffi::OsStr> {
+    if p.extension()? != "json" {
+        return None;
+    }
+    let stem = Path::new(p.file_stem()?);
+    if stem.extension()? == "tmLanguage" {
+        stem.file_stem()
+    } else {
+        None
+    }
+}
+
+#[cfg(all(feature = "yaml-load", feature = "plist-load"))]
+fn load_tmlanguage_file(
+    p: &Path,
+    lines_include_newline: bool,
+    diagnostics: Option<&mut Vec<LoadingError>>,
+) -> Result<SyntaxDefinition, LoadingError> {
+    let file = BufReader::new(File::open(p)?);
+    let grammar = crate::highlighting::settings::read_plist(file).map_err(ParseSyntaxError::from);
+    load_textmate_file(
+        p,
+        grammar,
+        p.file_stem(),
         lines_include_newline,
-        p.file_stem().and_then(|x| x.to_str()),
+        diagnostics,
     )
-    .map_err(|e| LoadingError::ParseSyntax(e, format!("{}", p.display())))
+}
+
+/// Finds the source of the syntax referenced by an `extends` key, such as
+/// `Packages/JavaScript/JavaScript.sublime-syntax`.
+///
+/// The loaded paths usually don't start with `Packages/`, so the rest of the reference is matched
+/// against the end of the paths. If no path or more than one path matches, the parent is missing.
+#[cfg(feature = "yaml-load")]
+fn find_parent_source<'a>(syntax_files: &'a [(PathBuf, String)], extends: &str) -> Option<&'a str> {
+    let mut wanted: Vec<&str> = extends.split('/').filter(|c| !c.is_empty()).collect();
+    if wanted.len() > 1 && wanted[0] == "Packages" {
+        wanted.remove(0);
+    }
+    let mut matching = syntax_files.iter().filter(|(path, _)| {
+        let components: Vec<&str> = path.iter().filter_map(|c| c.to_str()).collect();
+        components.ends_with(&wanted)
+    });
+    match (matching.next(), matching.next()) {
+        (Some((_, source)), None) => Some(source.as_str()),
+        _ => None,
+    }
+}
+
+/// Parses a TextMate injection selector like `L:source.js -comment, text.html`, returning the
+/// parts with the `L:` prefix, whose patterns take precedence over the ones of the context they're
+/// injected into, and the other parts
+pub(crate) fn parse_injection_selector(
+    selector: &str,
+) -> Result<(ScopeSelectors, ScopeSelectors), ParseScopeError> {
+    let mut left = Vec::new();
+    let mut right = Vec::new();
+    for part in selector.split(',') {
+        let part = part.trim();
+        match part.strip_prefix("L:") {
+            Some(rest) => left.push(rest),
+            None => right.push(part.strip_prefix("R:").unwrap_or(part)),
+        }
+    }
+    let parse = |parts: Vec<&str>| {
+        if parts.is_empty() {
+            Ok(ScopeSelectors::default())
+        } else {
+            ScopeSelectors::from_str(&parts.join(", "))
+        }
+    };
+    Ok((parse(left)?, parse(right)?))
+}
+
+/// Calls `f` with each context reference of the patterns of a context
+fn for_each_context_ref(context: &mut Context, f: &mut impl FnMut(&mut ContextReference)) {
+    for pattern in &mut context.patterns {
+        match *pattern {
+            Pattern::Match(ref mut match_pat) => {
+                match match_pat.operation {
+                    MatchOperation::Push(ref mut context_refs)
+                    | MatchOperation::Set(ref mut context_refs)
+                    | MatchOperation::Branch {
+                        contexts: ref mut context_refs,
+                        ..
+                    }
+                    | MatchOperation::PopAndPush(_, ref mut context_refs)
+                    | MatchOperation::PopAndSet(_, ref mut context_refs) => {
+                        context_refs.iter_mut().for_each(&mut *f)
+                    }
+                    MatchOperation::Pop
+                    | MatchOperation::PopMultiple(_)
+                    | MatchOperation::None
+                    | MatchOperation::Fail(_) => {}
+                }
+                if let Some(ref mut context_ref) = match_pat.with_prototype {
+                    f(context_ref);
+                }
+            }
+            Pattern::Include(ref mut context_ref) => f(context_ref),
+        }
+    }
 }
 
 impl Clone for SyntaxSet {
@@ -115,6 +298,7 @@ impl Clone for SyntaxSet {
             path_syntaxes: self.path_syntaxes.clone(),
             // Will need to be re-initialized
             first_line_cache: OnceCell::new(),
+            fingerprint: self.fingerprint.clone(),
             #[cfg(feature = "metadata")]
             metadata: self.metadata.clone(),
         }
@@ -127,6 +311,7 @@ impl Default for SyntaxSet {
             syntaxes: Vec::new(),
             path_syntaxes: Vec::new(),
             first_line_cache: OnceCell::new(),
+            fingerprint: OnceCell::new(),
             #[cfg(feature = "metadata")]
             metadata: Metadata::default(),
         }
@@ -365,6 +550,9 @@ impl SyntaxSet {
                 scope,
                 first_line_match,
                 hidden,
+                version: lazy_contexts.version,
+                // already injected into the contexts of the other syntaxes
+                injection_selector: None,
                 variables,
                 contexts: builder_contexts,
             };
@@ -393,6 +581,64 @@ impl SyntaxSet {
             .ok_or(ParsingError::MissingContext(*context_id))
     }
 
+    /// Finds the context a reference points to the same way the references in syntaxes are
+    /// linked, with names looked up in the syntax with the index. `Direct` references aren't
+    /// resolved.
+    pub(crate) fn find_context_id(
+        &self,
+        context_ref: &ContextReference,
+        syntax_index: Option<usize>,
+    ) -> Option<ContextId> {
+        SyntaxSetBuilder::find_context_id(context_ref, syntax_index, &self.syntaxes, &|index| {
+            self.syntaxes[index].context_ids()
+        })
+    }
+
+    /// Returns the `PatternSet` of a context, or `None` if it has no patterns that can be
+    /// searched together.
+    #[cfg(feature = "regex-hybrid")]
+    pub(crate) fn get_pattern_set(
+        &self,
+        context_id: &ContextId,
+    ) -> Result<Option<&PatternSet>, ParsingError> {
+        let context = self.get_context(context_id)?;
+        let lazy_contexts = self.syntaxes[context_id.syntax_index].lazy_contexts();
+        let pattern_sets = lazy_contexts.pattern_sets.get_or_init(|| {
+            lazy_contexts
+                .contexts
+                .iter()
+                .map(|_| LazyPatternSet::default())
+                .collect()
+        });
+        Ok(pattern_sets[context_id.context_index].get(self, context))
+    }
+
+    /// A hash of the syntaxes in the set, to check that a [`SavedParseState`] is restored with the
+    /// syntax set it was saved with.
+    ///
+    /// It is the same for a syntax set that is loaded from a dump of this one, but changes when
+    /// any syntax changes, and may change when the same syntaxes are built by another version of
+    /// syntect.
+    ///
+    /// [`SavedParseState`]: struct.SavedParseState.html
+    pub fn fingerprint(&self) -> u64 {
+        *self.fingerprint.get_or_init(|| {
+            let mut hasher = FnvHasher::default();
+            hasher.write_u64(self.syntaxes.len() as u64);
+            for syntax in &self.syntaxes {
+                for part in [
+                    syntax.name.as_bytes(),
+                    syntax.scope.build_string().as_bytes(),
+                    &syntax.serialized_lazy_contexts,
+                ] {
+                    hasher.write_u64(part.len() as u64);
+                    hasher.write(part);
+                }
+            }
+            hasher.finish()
+        })
+    }
+
     fn first_line_cache(&self) -> &FirstLineCache {
         self.first_line_cache
             .get_or_init(|| FirstLineCache::new(self.syntaxes()))
@@ -429,6 +675,9 @@ impl SyntaxSet {
                 Pattern::Match(match_pat) => match &match_pat.operation {
                     MatchOperation::Push(context_refs) => Some(context_refs),
                     MatchOperation::Set(context_refs) => Some(context_refs),
+                    MatchOperation::Branch { contexts, .. } => Some(contexts),
+                    MatchOperation::PopAndPush(_, context_refs) => Some(context_refs),
+                    MatchOperation::PopAndSet(_, context_refs) => Some(context_refs),
                     _ => None,
                 },
                 _ => None,
@@ -449,11 +698,20 @@ impl SyntaxSet {
 }
 
 impl SyntaxReference {
+    /// The `version` of the syntax definition format, see [`SyntaxDefinition::version`]
+    ///
+    /// This loads the contexts of the syntax if they weren't used yet.
+    ///
+    /// [`SyntaxDefinition::version`]: syntax_definition/struct.SyntaxDefinition.html#structfield.version
+    pub fn version(&self) -> u8 {
+        self.lazy_contexts().version
+    }
+
     pub(crate) fn context_ids(&self) -> &HashMap<String, ContextId> {
         &self.lazy_contexts().context_ids
     }
 
-    fn contexts(&self) -> &[Context] {
+    pub(crate) fn contexts(&self) -> &[Context] {
         &self.lazy_contexts().contexts
     }
 
@@ -465,7 +723,27 @@ impl SyntaxReference {
 
 impl LazyContexts {
     fn deserialize(data: &[u8]) -> LazyContexts {
-        crate::dumps::from_reader(data).expect("data is not corrupt or out of sync with the code")
+        LazyContexts::try_deserialize(data)
+            .expect("data is not corrupt or out of sync with the code")
+    }
+
+    fn try_deserialize(data: &[u8]) -> bincode::Result<LazyContexts> {
+        let data = crate::dumps::decompress(data)?;
+        let mut rest = &data[..];
+        let (context_ids, contexts) = bincode::deserialize_from(&mut rest)?;
+        // Older dumps end before the version
+        let version = if rest.is_empty() {
+            1
+        } else {
+            bincode::deserialize_from(&mut rest)?
+        };
+        Ok(LazyContexts {
+            context_ids,
+            contexts,
+            version,
+            #[cfg(feature = "regex-hybrid")]
+            pattern_sets: OnceCell::new(),
+        })
     }
 }
 
@@ -498,6 +776,10 @@ impl SyntaxSetBuilder {
 
     /// Loads all the `.sublime-syntax` files in a folder into this builder.
     ///
+    /// TextMate grammars in `.tmLanguage.json` files, and with the `plist-load` feature in
+    /// `.tmLanguage` files, are loaded as well, unless there is a `.sublime-syntax` file with the
+    /// same name. See [`SyntaxDefinition::load_from_tmlanguage`] for how they are translated.
+    ///
     /// The `lines_include_newline` parameter is used to work around the fact that Sublime Text
     /// normally passes line strings including newline characters (`\n`) to its regex engine. This
     /// results in many syntaxes having regexes matching `\n`, which doesn't work if you don't pass
@@ -509,30 +791,92 @@ impl SyntaxSetBuilder {
     /// In the future I might include a "slow mode" that copies the lines passed in and appends a
     /// newline if there isn't one, but in the interest of performance currently this hacky fix will
     /// have to do.
+    ///
+    /// Syntaxes that inherit from another syntax using `extends` are resolved against the other
+    /// syntaxes in the folder, so the parent syntaxes need to be part of the same folder.
     #[cfg(feature = "yaml-load")]
     pub fn add_from_folder<P: AsRef<Path>>(
         &mut self,
         folder: P,
         lines_include_newline: bool,
     ) -> Result<(), LoadingError> {
+        self.load_folder(folder.as_ref(), lines_include_newline, None)
+    }
+
+    /// Same as [`add_from_folder`](#method.add_from_folder), but doesn't stop at the first error.
+    ///
+    /// Files that can't be read or loaded at all are skipped, and the syntaxes are loaded with
+    /// [`SyntaxDefinition::load_from_str_lenient`], which skips the patterns that can't be
+    /// loaded. All these errors are returned, so that they can be reported while the rest of the
+    /// syntaxes are still usable.
+    #[cfg(feature = "yaml-load")]
+    pub fn add_from_folder_lenient<P: AsRef<Path>>(
+        &mut self,
+        folder: P,
+        lines_include_newline: bool,
+    ) -> Vec<LoadingError> {
+        let mut diagnostics = Vec::new();
+        // Can't fail when collecting the errors
+        let _ = self.load_folder(
+            folder.as_ref(),
+            lines_include_newline,
+            Some(&mut diagnostics),
+        );
+        diagnostics
+    }
+
+    /// Loads the files of a folder, with `diagnostics` collecting the errors instead of failing
+    #[cfg(feature = "yaml-load")]
+    fn load_folder(
+        &mut self,
+        folder: &Path,
+        lines_include_newline: bool,
+        mut diagnostics: Option<&mut Vec<LoadingError>>,
+    ) -> Result<(), LoadingError> {
+        // Returns `None` for errors that are collected
+        fn check<T>(
+            diagnostics: &mut Option<&mut Vec<LoadingError>>,
+            result: Result<T, LoadingError>,
+        ) -> Result<Option<T>, LoadingError> {
+            match (result, diagnostics) {
+                (Ok(value), _) => Ok(Some(value)),
+                (Err(e), Some(diagnostics)) => {
+                    diagnostics.push(e);
+                    Ok(None)
+                }
+                (Err(e), None) => Err(e),
+            }
+        }
+
+        let mut syntax_files = Vec::new();
+        #[cfg(feature = "plist-load")]
+        let mut tmlanguage_files = Vec::new();
+        let mut tmlanguage_json_files = Vec::new();
         for entry in crate::utils::walk_dir(folder).sort_by(|a, b| a.file_name().cmp(b.file_name()))
         {
-            let entry = entry.map_err(LoadingError::WalkDir)?;
+            let entry = match check(&mut diagnostics, entry.map_err(LoadingError::WalkDir))? {
+                Some(entry) => entry,
+                None => continue,
+            };
             if entry
                 .path()
                 .extension()
-                .map_or(false, |e| e == "sublime-syntax")
+                .is_some_and(|e| e == "sublime-syntax")
+            {
+                let source = std::fs::read_to_string(entry.path()).map_err(LoadingError::from);
+                if let Some(source) = check(&mut diagnostics, source)? {
+                    syntax_files.push((entry.path().to_path_buf(), source));
+                }
+            }
+
+            #[cfg(feature = "plist-load")]
             {
-                let syntax = load_syntax_file(entry.path(), lines_include_newline)?;
-                if let Some(path_str) = entry.path().to_str() {
-                    // Split the path up and rejoin with slashes so that syntaxes loaded on Windows
-                    // can still be loaded the same way.
-                    let path = Path::new(path_str);
-                    let path_parts: Vec<_> = path.iter().map(|c| c.to_str().unwrap()).collect();
-                    self.path_syntaxes
-                        .push((path_parts.join("/").to_string(), self.syntaxes.len()));
+                if entry.path().extension() == Some("tmLanguage".as_ref()) {
+                    tmlanguage_files.push(entry.path().to_path_buf());
                 }
-                self.syntaxes.push(syntax);
+            }
+            if tmlanguage_json_name(entry.path()).is_some() {
+                tmlanguage_json_files.push(entry.path().to_path_buf());
             }
 
             #[cfg(feature = "metadata")]
@@ -546,9 +890,77 @@ impl SyntaxSetBuilder {
             }
         }
 
+        // Syntaxes are only loaded once all files have been read, so that parents referenced by
+        // `extends` can be found regardless of the order of the files.
+        for (path, source) in &syntax_files {
+            let syntax = load_syntax_file(
+                path,
+                source,
+                lines_include_newline,
+                &syntax_files,
+                diagnostics.as_deref_mut(),
+            );
+            if let Some(syntax) = check(&mut diagnostics, syntax)? {
+                self.add_with_path(path, syntax);
+            }
+        }
+
+        #[cfg(feature = "plist-load")]
+        for path in &tmlanguage_files {
+            if syntax_files
+                .iter()
+                .any(|(p, _)| p.file_stem() == path.file_stem())
+            {
+                continue;
+            }
+            let syntax =
+                load_tmlanguage_file(path, lines_include_newline, diagnostics.as_deref_mut());
+            if let Some(syntax) = check(&mut diagnostics, syntax)? {
+                self.add_with_path(path, syntax);
+            }
+        }
+
+        for path in &tmlanguage_json_files {
+            let name = tmlanguage_json_name(path);
+            if syntax_files.iter().any(|(p, _)| p.file_stem() == name) {
+                continue;
+            }
+            let source = match check(
+                &mut diagnostics,
+                std::fs::read_to_string(path).map_err(LoadingError::from),
+            )? {
+                Some(source) => source,
+                None => continue,
+            };
+            let grammar = serde_json::from_str(&source).map_err(ParseSyntaxError::from);
+            let syntax = load_textmate_file(
+                path,
+                grammar,
+                name,
+                lines_include_newline,
+                diagnostics.as_deref_mut(),
+            );
+            if let Some(syntax) = check(&mut diagnostics, syntax)? {
+                self.add_with_path(path, syntax);
+            }
+        }
+
         Ok(())
     }
 
+    #[cfg(feature = "yaml-load")]
+    fn add_with_path(&mut self, path: &Path, syntax: SyntaxDefinition) {
+        if let Some(path_str) = path.to_str() {
+            // Split the path up and rejoin with slashes so that syntaxes loaded on Windows
+            // can still be loaded the same way.
+            let path = Path::new(path_str);
+            let path_parts: Vec<_> = path.iter().map(|c| c.to_str().unwrap()).collect();
+            self.path_syntaxes
+                .push((path_parts.join("/").to_string(), self.syntaxes.len()));
+        }
+        self.syntaxes.push(syntax);
+    }
+
     /// Build a [`SyntaxSet`] from the syntaxes that have been added to this
     /// builder.
     ///
@@ -569,6 +981,11 @@ impl SyntaxSetBuilder {
     /// serialize a [`SyntaxSet`] for your program and when you run the program,
     /// directly load the [`SyntaxSet`].
     ///
+    /// A TextMate grammar can include the syntax that includes it with `$base`. As contexts are
+    /// only linked once, the contexts of such a grammar are copied into each syntax that includes
+    /// it by scope, so that `$base` refers to the `main` context of that syntax. In the grammar
+    /// itself, `$base` refers to its own `main` context.
+    ///
     /// [`SyntaxSet`]: struct.SyntaxSet.html
     pub fn build(self) -> SyntaxSet {
         #[cfg(not(feature = "metadata"))]
@@ -584,9 +1001,14 @@ impl SyntaxSetBuilder {
             existing_metadata,
         } = self;
 
+        let mut syntax_definitions = syntax_definitions;
+        Self::apply_injections(&mut syntax_definitions);
+        Self::copy_base_includers(&mut syntax_definitions);
+
         let mut syntaxes = Vec::with_capacity(syntax_definitions.len());
         let mut all_context_ids = Vec::new();
         let mut all_contexts = vec![Vec::new(); syntax_definitions.len()];
+        let mut versions = Vec::with_capacity(syntax_definitions.len());
 
         for (syntax_index, syntax_definition) in syntax_definitions.into_iter().enumerate() {
             let SyntaxDefinition {
@@ -595,6 +1017,8 @@ impl SyntaxSetBuilder {
                 scope,
                 first_line_match,
                 hidden,
+                version,
+                injection_selector: _,
                 variables,
                 contexts,
             } = syntax_definition;
@@ -619,6 +1043,7 @@ impl SyntaxSetBuilder {
                 all_contexts[syntax_index].push(context);
             }
 
+            versions.push(version);
             let syntax = SyntaxReference {
                 name,
                 file_extensions,
@@ -698,10 +1123,13 @@ impl SyntaxSetBuilder {
         //  * the algorithms above
         //  * the borrow checker
         // makes it necessary to set these up as the last step.
-        for syntax in &mut syntaxes {
+        for (syntax, version) in syntaxes.iter_mut().zip(versions) {
             let lazy_contexts = LazyContexts {
                 context_ids: all_context_ids.remove(0),
                 contexts: all_contexts.remove(0),
+                version,
+                #[cfg(feature = "regex-hybrid")]
+                pattern_sets: OnceCell::new(),
             };
 
             syntax.serialized_lazy_contexts = crate::dumps::dump_binary(&lazy_contexts);
@@ -711,6 +1139,7 @@ impl SyntaxSetBuilder {
             syntaxes,
             path_syntaxes,
             first_line_cache: OnceCell::new(),
+            fingerprint: OnceCell::new(),
             #[cfg(feature = "metadata")]
             metadata,
         }
@@ -736,8 +1165,17 @@ impl SyntaxSetBuilder {
                 Pattern::Match(ref match_pat) => {
                     let maybe_context_refs = match match_pat.operation {
                         MatchOperation::Push(ref context_refs)
-                        | MatchOperation::Set(ref context_refs) => Some(context_refs),
-                        MatchOperation::Pop | MatchOperation::None => None,
+                        | MatchOperation::Set(ref context_refs)
+                        | MatchOperation::Branch {
+                            contexts: ref context_refs,
+                            ..
+                        }
+                        | MatchOperation::PopAndPush(_, ref context_refs)
+                        | MatchOperation::PopAndSet(_, ref context_refs) => Some(context_refs),
+                        MatchOperation::Pop
+                        | MatchOperation::PopMultiple(_)
+                        | MatchOperation::None
+                        | MatchOperation::Fail(_) => None,
                     };
                     if let Some(context_refs) = maybe_context_refs {
                         for context_ref in context_refs.iter() {
@@ -816,16 +1254,35 @@ impl SyntaxSetBuilder {
         syntaxes: &[SyntaxReference],
     ) {
         // println!("{:?}", context_ref);
+        let linked_context_id =
+            Self::find_context_id(context_ref, Some(syntax_index), syntaxes, &|index| {
+                &all_context_ids[index]
+            });
+        if let Some(context_id) = linked_context_id {
+            let mut new_ref = ContextReference::Direct(context_id);
+            mem::swap(context_ref, &mut new_ref);
+        }
+    }
+
+    /// The context a reference points to. Names are looked up in the syntax with the index, if
+    /// any, and `Direct` references are left as they are.
+    fn find_context_id<'a>(
+        context_ref: &ContextReference,
+        syntax_index: Option<usize>,
+        syntaxes: &'a [SyntaxReference],
+        context_ids: &dyn Fn(usize) -> &'a HashMap<String, ContextId>,
+    ) -> Option<ContextId> {
         use super::syntax_definition::ContextReference::*;
-        let linked_context_id = match *context_ref {
+        let context_id = match *context_ref {
             Named(ref s) | Inline(ref s) => {
+                let context_ids = context_ids(syntax_index?);
                 // This isn't actually correct, but it is better than nothing/crashing.
                 // This is being phased out anyhow, see https://github.com/sublimehq/Packages/issues/73
                 // Fixes issue #30
-                if s == "$top_level_main" {
-                    all_context_ids[syntax_index].get("main")
+                if s == "$top_level_main" || s == "$base" {
+                    context_ids.get("main")
                 } else {
-                    all_context_ids[syntax_index].get(s)
+                    context_ids.get(s)
                 }
             }
             ByScope {
@@ -833,10 +1290,10 @@ impl SyntaxSetBuilder {
                 ref sub_context,
                 with_escape,
             } => Self::with_plain_text_fallback(
-                all_context_ids,
+                context_ids,
                 syntaxes,
                 with_escape,
-                Self::find_id(sub_context, all_context_ids, syntaxes, |index_and_syntax| {
+                Self::find_id(sub_context, context_ids, syntaxes, |index_and_syntax| {
                     index_and_syntax.1.scope == scope
                 }),
             ),
@@ -845,23 +1302,20 @@ impl SyntaxSetBuilder {
                 ref sub_context,
                 with_escape,
             } => Self::with_plain_text_fallback(
-                all_context_ids,
+                context_ids,
                 syntaxes,
                 with_escape,
-                Self::find_id(sub_context, all_context_ids, syntaxes, |index_and_syntax| {
+                Self::find_id(sub_context, context_ids, syntaxes, |index_and_syntax| {
                     &index_and_syntax.1.name == name
                 }),
             ),
             Direct(_) => None,
         };
-        if let Some(context_id) = linked_context_id {
-            let mut new_ref = Direct(*context_id);
-            mem::swap(context_ref, &mut new_ref);
-        }
+        context_id.copied()
     }
 
     fn with_plain_text_fallback<'a>(
-        all_context_ids: &'a [HashMap<String, ContextId>],
+        context_ids: &dyn Fn(usize) -> &'a HashMap<String, ContextId>,
         syntaxes: &'a [SyntaxReference],
         with_escape: bool,
         context_id: Option<&'a ContextId>,
@@ -874,7 +1328,7 @@ impl SyntaxSetBuilder {
                 // how Sublime Text behaves. It should be a safe thing to do
                 // since `embed`s always includes an `escape` to get out of
                 // the `embed`.
-                Self::find_id(&None, all_context_ids, syntaxes, |index_and_syntax| {
+                Self::find_id(&None, context_ids, syntaxes, |index_and_syntax| {
                     index_and_syntax.1.name == "Plain Text"
                 })
             } else {
@@ -885,7 +1339,7 @@ impl SyntaxSetBuilder {
 
     fn find_id<'a>(
         sub_context: &Option<String>,
-        all_context_ids: &'a [HashMap<String, ContextId>],
+        context_ids: &dyn Fn(usize) -> &'a HashMap<String, ContextId>,
         syntaxes: &'a [SyntaxReference],
         predicate: impl FnMut(&(usize, &SyntaxReference)) -> bool,
     ) -> Option<&'a ContextId> {
@@ -895,7 +1349,159 @@ impl SyntaxSetBuilder {
             .enumerate()
             .rev()
             .find(predicate)
-            .and_then(|index_and_syntax| all_context_ids[index_and_syntax.0].get(context_name))
+            .and_then(|index_and_syntax| context_ids(index_and_syntax.0).get(context_name))
+    }
+
+    /// Includes the `main` context of each injection grammar in the contexts of the other syntaxes
+    /// that match its [`SyntaxDefinition::injection_selector`]. The selector is matched against
+    /// the top level scope and the meta scopes of each context.
+    fn apply_injections(definitions: &mut [SyntaxDefinition]) {
+        let injections: Vec<(Scope, ScopeSelectors, ScopeSelectors)> = definitions
+            .iter()
+            .filter_map(|d| {
+                let (left, right) =
+                    parse_injection_selector(d.injection_selector.as_ref()?).ok()?;
+                Some((d.scope, left, right))
+            })
+            .collect();
+        if injections.is_empty() {
+            return;
+        }
+
+        for definition in definitions
+            .iter_mut()
+            .filter(|d| d.injection_selector.is_none())
+        {
+            for (name, context) in definition.contexts.iter_mut() {
+                // `__start` and `__main` include `main`, the prototype is included everywhere
+                if name.starts_with("__") || name == "prototype" {
+                    continue;
+                }
+                let mut scopes = vec![definition.scope];
+                scopes.extend(&context.meta_scope);
+                scopes.extend(&context.meta_content_scope);
+                for (scope, left, right) in injections.iter() {
+                    let include = Pattern::Include(ContextReference::ByScope {
+                        scope: *scope,
+                        sub_context: None,
+                        with_escape: false,
+                    });
+                    if left.does_match(&scopes).is_some() {
+                        context.patterns.insert(0, include);
+                    } else if right.does_match(&scopes).is_some() {
+                        context.patterns.push(include);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Copies the contexts of the syntaxes that include `$base`, directly or through the syntaxes
+    /// they include by scope, into the syntaxes that include them by scope. The references by
+    /// scope to them are changed to the copies, in which `$base` is linked to the `main` context
+    /// of the including syntax.
+    fn copy_base_includers(definitions: &mut [SyntaxDefinition]) {
+        let mut uses_base = Vec::with_capacity(definitions.len());
+        let mut included_scopes = Vec::with_capacity(definitions.len());
+        for definition in definitions.iter_mut() {
+            let mut direct = false;
+            let mut scopes = HashSet::new();
+            for context in definition.contexts.values_mut() {
+                for_each_context_ref(context, &mut |context_ref| match context_ref {
+                    ContextReference::Named(name) if name == "$base" => direct = true,
+                    ContextReference::ByScope { scope, .. } => {
+                        scopes.insert(*scope);
+                    }
+                    _ => {}
+                });
+            }
+            uses_base.push(direct);
+            included_scopes.push(scopes);
+        }
+        if !uses_base.contains(&true) {
+            return;
+        }
+
+        // The last syntax with a scope is the one references by scope are linked to
+        let by_scope: HashMap<Scope, usize> = definitions
+            .iter()
+            .enumerate()
+            .map(|(index, definition)| (definition.scope, index))
+            .collect();
+        let included = |index: usize| -> Vec<usize> {
+            included_scopes[index]
+                .iter()
+                .filter_map(|scope| by_scope.get(scope).copied())
+                .collect()
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for index in 0..definitions.len() {
+                if !uses_base[index] && included(index).iter().any(|&i| uses_base[i]) {
+                    uses_base[index] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        // The copies are made from the original contexts before any of them are added
+        let mut copies = Vec::new();
+        for host in 0..definitions.len() {
+            let mut copied = Vec::new();
+            let mut pending = vec![host];
+            while let Some(index) = pending.pop() {
+                for i in included(index) {
+                    if i != host && uses_base[i] && !copied.contains(&i) {
+                        copied.push(i);
+                        pending.push(i);
+                    }
+                }
+            }
+            let prefixes: HashMap<Scope, String> = copied
+                .iter()
+                .map(|&i| (definitions[i].scope, format!("{}#", definitions[i].scope)))
+                .collect();
+            let mut contexts = Vec::new();
+            for &i in &copied {
+                let prefix = &prefixes[&definitions[i].scope];
+                for (name, context) in &definitions[i].contexts {
+                    let mut context = context.clone();
+                    // The prototype of the including syntax doesn't apply to the grammar
+                    context.meta_include_prototype = false;
+                    for_each_context_ref(&mut context, &mut |context_ref| match context_ref {
+                        ContextReference::Named(name) | ContextReference::Inline(name)
+                            if !name.starts_with('$') =>
+                        {
+                            name.insert_str(0, prefix)
+                        }
+                        _ => {}
+                    });
+                    contexts.push((format!("{}{}", prefix, name), context));
+                }
+            }
+            copies.push((prefixes, contexts));
+        }
+
+        for (definition, (prefixes, contexts)) in definitions.iter_mut().zip(copies) {
+            if prefixes.is_empty() {
+                continue;
+            }
+            definition.contexts.extend(contexts);
+            for context in definition.contexts.values_mut() {
+                for_each_context_ref(context, &mut |context_ref| {
+                    if let ContextReference::ByScope {
+                        scope, sub_context, ..
+                    } = context_ref
+                    {
+                        if let Some(prefix) = prefixes.get(scope) {
+                            let name = sub_context.as_deref().unwrap_or("main");
+                            *context_ref = ContextReference::Named(format!("{}{}", prefix, name));
+                        }
+                    }
+                });
+            }
+        }
     }
 
     fn link_match_pat(
@@ -906,8 +1512,17 @@ impl SyntaxSetBuilder {
     ) {
         let maybe_context_refs = match match_pat.operation {
             MatchOperation::Push(ref mut context_refs)
-            | MatchOperation::Set(ref mut context_refs) => Some(context_refs),
-            MatchOperation::Pop | MatchOperation::None => None,
+            | MatchOperation::Set(ref mut context_refs)
+            | MatchOperation::Branch {
+                contexts: ref mut context_refs,
+                ..
+            }
+            | MatchOperation::PopAndPush(_, ref mut context_refs)
+            | MatchOperation::PopAndSet(_, ref mut context_refs) => Some(context_refs),
+            MatchOperation::Pop
+            | MatchOperation::PopMultiple(_)
+            | MatchOperation::None
+            | MatchOperation::Fail(_) => None,
         };
         if let Some(context_refs) = maybe_context_refs {
             for context_ref in context_refs.iter_mut() {
@@ -957,6 +1572,8 @@ mod tests {
             scope: Scope::new("source.cmake").unwrap(),
             first_line_match: None,
             hidden: false,
+            version: 1,
+            injection_selector: None,
             variables: HashMap::new(),
             contexts: HashMap::new(),
         };
@@ -1034,6 +1651,180 @@ mod tests {
         assert_eq!(count, 109);
     }
 
+    #[test]
+    fn can_load_contexts_without_version() {
+        // The lazy contexts of dumps made before syntax versions were supported
+        #[derive(Serialize)]
+        struct OldLazyContexts {
+            context_ids: HashMap<String, ContextId>,
+            contexts: Vec<Context>,
+        }
+        let old = crate::dumps::dump_binary(&OldLazyContexts {
+            context_ids: HashMap::new(),
+            contexts: vec![Context::new(true)],
+        });
+        let lazy_contexts = LazyContexts::deserialize(&old[..]);
+        assert_eq!(lazy_contexts.contexts.len(), 1);
+        assert_eq!(lazy_contexts.version, 1);
+
+        // Truncated dumps fail instead of losing the version
+        let new = crate::dumps::dump_binary(&LazyContexts {
+            context_ids: HashMap::new(),
+            contexts: vec![Context::new(true)],
+            version: 2,
+            #[cfg(feature = "regex-hybrid")]
+            pattern_sets: OnceCell::new(),
+        });
+        assert_eq!(LazyContexts::deserialize(&new[..]).version, 2);
+        assert!(LazyContexts::try_deserialize(&new[..new.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn can_load_syntaxes_with_extends() {
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add_from_folder("testdata/extends", true).unwrap();
+        let ss = builder.build();
+
+        let syntax = ss.find_syntax_by_extension("derived").unwrap();
+        assert_eq!(syntax.scope, Scope::new("source.derived").unwrap());
+        let mut parse_state = ParseState::new(syntax);
+        let ops = parse_state
+            .parse_line("derived \"\\n\"\n", &ss)
+            .expect("#[cfg(test)]");
+        let expected = (0, ScopeStackOp::Push(Scope::new("keyword.base").unwrap()));
+        assert_ops_contain(&ops, &expected);
+        let expected = (
+            9,
+            ScopeStackOp::Push(Scope::new("constant.character.escape.derived").unwrap()),
+        );
+        assert_ops_contain(&ops, &expected);
+
+        // The parent itself is unaffected
+        let syntax = ss.find_syntax_by_extension("base").unwrap();
+        let mut parse_state = ParseState::new(syntax);
+        let ops = parse_state
+            .parse_line("derived base\n", &ss)
+            .expect("#[cfg(test)]");
+        let expected = (8, ScopeStackOp::Push(Scope::new("keyword.base").unwrap()));
+        assert_ops_contain(&ops, &expected);
+        assert!(!ops.contains(&(0, ScopeStackOp::Push(Scope::new("keyword.base").unwrap()))));
+    }
+
+    #[test]
+    fn can_parse_injection_selector() {
+        let (left, right) =
+            parse_injection_selector("L:source.a comment, R:source.b, source.c").unwrap();
+        let matches = |selectors: &ScopeSelectors, scopes: &str| {
+            let stack = ScopeStack::from_str(scopes).unwrap();
+            selectors.does_match(stack.as_slice()).is_some()
+        };
+        // Only the part with the prefix takes precedence
+        assert!(matches(&left, "source.a comment"));
+        assert!(!matches(&left, "source.b"));
+        assert!(!matches(&right, "source.a comment"));
+        assert!(matches(&right, "source.b"));
+        assert!(matches(&right, "source.c"));
+
+        let (left, right) = parse_injection_selector("source.a").unwrap();
+        assert!(!matches(&left, "source.a"));
+        assert!(matches(&right, "source.a"));
+    }
+
+    #[test]
+    fn can_find_parent_source() {
+        let files = [
+            ("syntaxes/A/Foo.sublime-syntax", "a"),
+            ("syntaxes/B/Foo.sublime-syntax", "b"),
+            ("syntaxes/B/Bar.sublime-syntax", "bar"),
+        ];
+        let files: Vec<(PathBuf, String)> = files
+            .iter()
+            .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+            .collect();
+        let find = |extends| find_parent_source(&files, extends);
+        assert_eq!(find("Packages/B/Foo.sublime-syntax"), Some("b"));
+        assert_eq!(find("Packages/B/Bar.sublime-syntax"), Some("bar"));
+        // Only the file name matching isn't enough, and neither is matching more than one file
+        assert_eq!(find("Packages/C/Bar.sublime-syntax"), None);
+        assert_eq!(find("Foo.sublime-syntax"), None);
+    }
+
+    #[cfg(feature = "plist-load")]
+    #[test]
+    fn can_load_tmlanguage_from_folder() {
+        let mut builder = SyntaxSetBuilder::new();
+        builder
+            .add_from_folder("testdata/tmlanguage", true)
+            .unwrap();
+        let ss = builder.build();
+        assert_eq!(ss.syntaxes().len(), 3);
+
+        let syntax = ss.find_syntax_by_extension("sample").unwrap();
+        assert_eq!(syntax.scope, Scope::new("source.sample").unwrap());
+        let mut parse_state = ParseState::new(syntax);
+        let ops = parse_state
+            .parse_line("a sample\n", &ss)
+            .expect("#[cfg(test)]");
+        let expected = (2, ScopeStackOp::Push(Scope::new("keyword.sample").unwrap()));
+        assert_ops_contain(&ops, &expected);
+
+        // The .sublime-syntax file is preferred over the .tmLanguage file of the same name
+        let syntax = ss.find_syntax_by_extension("shadowed").unwrap();
+        assert_eq!(syntax.scope, Scope::new("source.shadowed").unwrap());
+
+        // The name of a JSON grammar defaults to the file name without extensions
+        let syntax = ss.find_syntax_by_name("SampleJson").unwrap();
+        let mut parse_state = ParseState::new(syntax);
+        let ops = parse_state
+            .parse_line("sample\n", &ss)
+            .expect("#[cfg(test)]");
+        let expected = (
+            0,
+            ScopeStackOp::Push(Scope::new("keyword.samplejson").unwrap()),
+        );
+        assert_ops_contain(&ops, &expected);
+    }
+
+    #[test]
+    fn can_load_folder_leniently() {
+        assert!(SyntaxSetBuilder::new()
+            .add_from_folder("testdata/lenient", true)
+            .is_err());
+
+        let mut builder = SyntaxSetBuilder::new();
+        let diagnostics = builder.add_from_folder_lenient("testdata/lenient", true);
+        let messages: Vec<String> = diagnostics.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages.len(), 4, "{:?}", messages);
+        let expected = [
+            "testdata/lenient/Broken.sublime-syntax: line 8, column 7, context main, pattern 0: Error while compiling regex '[a'",
+            "testdata/lenient/Broken.sublime-syntax: line 10, column 7, context main, pattern 1: Unknown variable: missing",
+            "testdata/lenient/Broken.sublime-syntax: line 17, column 11, context #anon_main_0, pattern 2: Missing mandatory key in YAML file: match",
+            "testdata/lenient/Unloadable.sublime-syntax: line 6, column 3: Context 'main' is missing",
+        ];
+        for (message, expected) in messages.iter().zip(expected) {
+            assert!(message.starts_with(expected), "{}", message);
+        }
+        match &diagnostics[3] {
+            LoadingError::LocatedParseSyntax(e, path) => {
+                assert_eq!(path, "testdata/lenient/Unloadable.sublime-syntax");
+                assert_eq!(e.location().position, Some((6, 3)));
+            }
+            e => panic!("expected a located error, got {:?}", e),
+        }
+
+        let ss = builder.build();
+        assert_eq!(ss.syntaxes().len(), 1);
+        let syntax = ss.find_syntax_by_extension("broken").unwrap();
+        let mut parse_state = ParseState::new(syntax);
+        let ops = parse_state
+            .parse_line("\"a\" keyword\n", &ss)
+            .expect("#[cfg(test)]");
+        let expected = (0, ScopeStackOp::Push(Scope::new("string.broken").unwrap()));
+        assert_ops_contain(&ops, &expected);
+        let expected = (4, ScopeStackOp::Push(Scope::new("keyword.broken").unwrap()));
+        assert_ops_contain(&ops, &expected);
+    }
+
     #[test]
     fn can_clone() {
         let cloned_syntax_set = {
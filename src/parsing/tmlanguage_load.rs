@@ -0,0 +1,492 @@
+//! Loading of TextMate grammars by translating them to the structure of a `.sublime-syntax` file
+use super::scope::*;
+use super::syntax_definition::SyntaxDefinition;
+use super::syntax_set::parse_injection_selector;
+use super::yaml_load::{LocatedSyntaxError, ParseSyntaxError};
+#[cfg(feature = "plist-load")]
+use crate::highlighting::settings::read_plist;
+use crate::highlighting::ScopeSelectors;
+use serde_json::Value as Settings;
+use std::collections::HashMap;
+#[cfg(feature = "plist-load")]
+use std::io::{Read, Seek};
+use yaml_rust::yaml::Hash;
+use yaml_rust::Yaml;
+
+impl SyntaxDefinition {
+    /// Loads a TextMate grammar from a `.tmLanguage` file in plist format.
+    ///
+    /// The grammar is translated to the equivalent `.sublime-syntax` contexts:
+    ///
+    /// - the top level `patterns` become the `main` context and each `repository` entry becomes a
+    ///   context of the same name, including nested repositories
+    /// - `begin`/`end` rules push a context with `name` as its `meta_scope` and `contentName` as
+    ///   its `meta_content_scope`, which is popped by the `end` match
+    /// - `begin`/`while` rules pop their context at the start of the first line that doesn't
+    ///   match `while`
+    /// - `$self` includes the grammar's own `main` context. `$base` includes the `main` context of
+    ///   the syntax that includes the grammar by scope, or of the grammar itself if it is used on
+    ///   its own, see [`SyntaxSetBuilder::build`].
+    /// - `injections` are included in the contexts whose scopes match the selector: `main` and
+    ///   the contexts pushed by `begin` rules. Since the scopes are only known statically, the
+    ///   selector is matched against the top level scope and the scopes of the `begin` rules that
+    ///   lead to the context.
+    /// - a grammar with an `injectionSelector` is loaded the same way as by
+    ///   [`load_from_tmlanguage_json`]
+    ///
+    /// Captures with their own `patterns` are only assigned their `name`.
+    ///
+    /// See [`load_from_str`] for the other parameters.
+    ///
+    /// [`load_from_str`]: #method.load_from_str
+    /// [`load_from_tmlanguage_json`]: #method.load_from_tmlanguage_json
+    /// [`SyntaxSetBuilder::build`]: ../struct.SyntaxSetBuilder.html#method.build
+    #[cfg(feature = "plist-load")]
+    pub fn load_from_tmlanguage<R: Read + Seek>(
+        reader: R,
+        lines_include_newline: bool,
+        fallback_name: Option<&str>,
+    ) -> Result<SyntaxDefinition, ParseSyntaxError> {
+        let grammar = read_plist(reader)?;
+        load_textmate_grammar(&grammar, lines_include_newline, fallback_name, None)
+            .map_err(LocatedSyntaxError::into_error)
+    }
+}
+
+/// Loads a TextMate grammar that was parsed into a JSON value, leniently if `diagnostics` are
+/// given
+pub(crate) fn load_textmate_grammar(
+    grammar: &Settings,
+    lines_include_newline: bool,
+    fallback_name: Option<&str>,
+    diagnostics: Option<&mut Vec<LocatedSyntaxError>>,
+) -> Result<SyntaxDefinition, LocatedSyntaxError> {
+    let injection_selector = match grammar.get("injectionSelector") {
+        Some(selector) => {
+            let selector = selector.as_str().ok_or(ParseSyntaxError::TypeMismatch)?;
+            parse_injection_selector(selector).map_err(ParseSyntaxError::InvalidScope)?;
+            Some(selector.to_owned())
+        }
+        None => None,
+    };
+    let doc = textmate_grammar_to_yaml(grammar)?;
+    let mut syntax = SyntaxDefinition::load_from_yaml(
+        &doc,
+        HashMap::new(),
+        lines_include_newline,
+        fallback_name,
+        diagnostics,
+    )?;
+    if injection_selector.is_some() {
+        syntax.hidden = true;
+        syntax.injection_selector = injection_selector;
+    }
+    Ok(syntax)
+}
+
+/// Translates a TextMate grammar to a YAML document with the structure of a `.sublime-syntax` file
+fn textmate_grammar_to_yaml(grammar: &Settings) -> Result<Yaml, ParseSyntaxError> {
+    let scope_name = grammar
+        .get("scopeName")
+        .ok_or(ParseSyntaxError::MissingMandatoryKey("scopeName"))?
+        .as_str()
+        .ok_or(ParseSyntaxError::TypeMismatch)?;
+    let top_level_scope = Scope::new(scope_name).map_err(ParseSyntaxError::InvalidScope)?;
+
+    let mut injections = Vec::new();
+    if let Some(map) = grammar.get("injections") {
+        let map = map.as_object().ok_or(ParseSyntaxError::TypeMismatch)?;
+        for (i, (selector, rule)) in map.iter().enumerate() {
+            let (left, right) =
+                parse_injection_selector(selector).map_err(ParseSyntaxError::InvalidScope)?;
+            injections.push(Injection {
+                context: format!("__injection_{}", i),
+                left,
+                right,
+                rule,
+            });
+        }
+    }
+    let converter = Converter { injections };
+
+    let mut contexts = Hash::new();
+    let mut repository = Vec::new();
+    collect_repositories(grammar, &mut repository);
+    for (name, rule) in repository {
+        let key = yaml_str(name);
+        if !contexts.contains_key(&key) {
+            let entries = converter.rules(std::slice::from_ref(rule), &[top_level_scope])?;
+            contexts.insert(key, Yaml::Array(entries));
+        }
+    }
+    let patterns = grammar.get("patterns").map_or(Ok(&[][..]), as_rules)?;
+    let main = converter.context(Vec::new(), patterns, &[top_level_scope])?;
+    contexts.insert(yaml_str("main"), Yaml::Array(main));
+    for injection in &converter.injections {
+        let entries = converter.rules(std::slice::from_ref(injection.rule), &[top_level_scope])?;
+        contexts.insert(yaml_str(&injection.context), Yaml::Array(entries));
+    }
+
+    let mut doc = Hash::new();
+    if let Some(name) = grammar.get("name").and_then(Settings::as_str) {
+        doc.insert(yaml_str("name"), yaml_str(name));
+    }
+    doc.insert(yaml_str("scope"), yaml_str(scope_name));
+    if let Some(file_types) = grammar.get("fileTypes").and_then(Settings::as_array) {
+        let extensions = file_types
+            .iter()
+            .filter_map(|t| t.as_str().map(yaml_str))
+            .collect();
+        doc.insert(yaml_str("file_extensions"), Yaml::Array(extensions));
+    }
+    if let Some(first_line) = grammar.get("firstLineMatch").and_then(Settings::as_str) {
+        doc.insert(yaml_str("first_line_match"), yaml_str(first_line));
+    }
+    if grammar.get("hideFromUser").is_some_and(is_true) {
+        doc.insert(yaml_str("hidden"), Yaml::Boolean(true));
+    }
+    doc.insert(yaml_str("contexts"), Yaml::Hash(contexts));
+    Ok(Yaml::Hash(doc))
+}
+
+struct Injection<'a> {
+    /// Name of the context containing the patterns of the injection
+    context: String,
+    /// The parts of the selector with the `L:` prefix, for contexts in which the patterns take
+    /// precedence over the ones of the context
+    left: ScopeSelectors,
+    /// The other parts of the selector
+    right: ScopeSelectors,
+    rule: &'a Settings,
+}
+
+struct Converter<'a> {
+    injections: Vec<Injection<'a>>,
+}
+
+impl<'a> Converter<'a> {
+    /// Entries of a context with the given `scopes` that matches `rules`, including the
+    /// injections for these scopes
+    fn context(
+        &self,
+        mut entries: Vec<Yaml>,
+        rules: &[Settings],
+        scopes: &[Scope],
+    ) -> Result<Vec<Yaml>, ParseSyntaxError> {
+        let is_left = |injection: &&Injection<'_>| injection.left.does_match(scopes).is_some();
+        for injection in self.injections.iter().filter(is_left) {
+            entries.push(include(&injection.context));
+        }
+        entries.extend(self.rules(rules, scopes)?);
+        for injection in self
+            .injections
+            .iter()
+            .filter(|injection| !is_left(injection) && injection.right.does_match(scopes).is_some())
+        {
+            entries.push(include(&injection.context));
+        }
+        Ok(entries)
+    }
+
+    /// Translates each rule to a context entry. `scopes` are the scopes of the context the rules
+    /// are in.
+    fn rules(&self, rules: &[Settings], scopes: &[Scope]) -> Result<Vec<Yaml>, ParseSyntaxError> {
+        let mut entries = Vec::new();
+        for rule in rules {
+            if let Some(entry) = self.rule(rule, scopes)? {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    fn rule(&self, rule: &Settings, scopes: &[Scope]) -> Result<Option<Yaml>, ParseSyntaxError> {
+        let rule = rule.as_object().ok_or(ParseSyntaxError::TypeMismatch)?;
+        if rule.get("disabled").is_some_and(is_true) {
+            return Ok(None);
+        }
+        let get_str = |key: &'static str| -> Result<Option<&str>, ParseSyntaxError> {
+            rule.get(key)
+                .map(|v| v.as_str().ok_or(ParseSyntaxError::TypeMismatch))
+                .transpose()
+        };
+        let patterns = rule.get("patterns").map_or(Ok(&[][..]), as_rules)?;
+
+        let mut entry = Hash::new();
+        if let Some(reference) = get_str("include")? {
+            return Ok(Some(include(&translate_include(reference))));
+        } else if let Some(regex) = get_str("match")? {
+            entry.insert(yaml_str("match"), yaml_str(regex));
+            if let Some(name) = get_str("name")? {
+                entry.insert(yaml_str("scope"), yaml_str(name));
+            }
+            insert_captures(&mut entry, rule.get("captures"));
+        } else if let Some(regex) = get_str("begin")? {
+            entry.insert(yaml_str("match"), yaml_str(regex));
+            insert_captures(
+                &mut entry,
+                rule.get("beginCaptures").or(rule.get("captures")),
+            );
+
+            let mut meta = Vec::new();
+            let mut context_scopes = scopes.to_vec();
+            for (key, meta_key) in [
+                ("name", "meta_scope"),
+                ("contentName", "meta_content_scope"),
+            ] {
+                if let Some(scope) = get_str(key)? {
+                    let mut meta_entry = Hash::new();
+                    meta_entry.insert(yaml_str(meta_key), yaml_str(scope));
+                    meta.push(Yaml::Hash(meta_entry));
+                    if !self.injections.is_empty() {
+                        for s in scope.split_whitespace() {
+                            context_scopes
+                                .push(Scope::new(s).map_err(ParseSyntaxError::InvalidScope)?);
+                        }
+                    }
+                }
+            }
+
+            let mut context = if let Some(end) = get_str("end")? {
+                let mut end_entry = Hash::new();
+                end_entry.insert(yaml_str("match"), yaml_str(end));
+                end_entry.insert(yaml_str("pop"), Yaml::Boolean(true));
+                insert_captures(
+                    &mut end_entry,
+                    rule.get("endCaptures").or(rule.get("captures")),
+                );
+                if rule.get("applyEndPatternLast").is_some_and(is_true) {
+                    let mut context = self.context(meta, patterns, &context_scopes)?;
+                    context.push(Yaml::Hash(end_entry));
+                    context
+                } else {
+                    meta.push(Yaml::Hash(end_entry));
+                    self.context(meta, patterns, &context_scopes)?
+                }
+            } else if let Some(condition) = get_str("while")? {
+                let mut pop_entry = Hash::new();
+                pop_entry.insert(
+                    yaml_str("match"),
+                    yaml_str(&format!("^(?!(?:{}))", condition)),
+                );
+                pop_entry.insert(yaml_str("pop"), Yaml::Boolean(true));
+                meta.push(Yaml::Hash(pop_entry));
+                let mut while_entry = Hash::new();
+                while_entry.insert(yaml_str("match"), yaml_str(&format!("^(?:{})", condition)));
+                insert_captures(
+                    &mut while_entry,
+                    rule.get("whileCaptures").or(rule.get("captures")),
+                );
+                meta.push(Yaml::Hash(while_entry));
+                self.context(meta, patterns, &context_scopes)?
+            } else {
+                return Err(ParseSyntaxError::MissingMandatoryKey("end"));
+            };
+            if context.is_empty() {
+                // An empty list would be an empty inline context for `push`, but a list of
+                // context names otherwise
+                context.push(Yaml::Hash(Hash::new()));
+            }
+            entry.insert(yaml_str("push"), Yaml::Array(context));
+        } else if !patterns.is_empty() {
+            // A rule that only groups other rules
+            let entries = self.rules(patterns, scopes)?;
+            entry.insert(yaml_str("include"), Yaml::Array(entries));
+        } else {
+            return Ok(None);
+        }
+        Ok(Some(Yaml::Hash(entry)))
+    }
+}
+
+/// Collects the `repository` entries of `value` and of the rules nested in it, outermost first
+fn collect_repositories<'a>(value: &'a Settings, out: &mut Vec<(&'a str, &'a Settings)>) {
+    if let Some(map) = value.get("repository").and_then(Settings::as_object) {
+        out.extend(map.iter().map(|(name, rule)| (name.as_str(), rule)));
+        for rule in map.values() {
+            collect_repositories(rule, out);
+        }
+    }
+    if let Some(rules) = value.get("patterns").and_then(Settings::as_array) {
+        for rule in rules {
+            collect_repositories(rule, out);
+        }
+    }
+}
+
+/// Translates a TextMate `include` to the equivalent `.sublime-syntax` reference
+pub(crate) fn translate_include(reference: &str) -> String {
+    match reference {
+        "$self" => "main".to_owned(),
+        // Resolved when building the syntax set
+        "$base" => "$base".to_owned(),
+        _ => match reference.strip_prefix('#') {
+            Some(name) => name.to_owned(),
+            None => format!("scope:{}", reference),
+        },
+    }
+}
+
+fn insert_captures(entry: &mut Hash, captures: Option<&Settings>) {
+    let captures = match captures.and_then(Settings::as_object) {
+        Some(captures) => captures,
+        None => return,
+    };
+    let mut map = Hash::new();
+    for (index, capture) in captures {
+        if let (Ok(index), Some(name)) = (
+            index.parse::<i64>(),
+            capture.get("name").and_then(Settings::as_str),
+        ) {
+            map.insert(Yaml::Integer(index), yaml_str(name));
+        }
+    }
+    if !map.is_empty() {
+        entry.insert(yaml_str("captures"), Yaml::Hash(map));
+    }
+}
+
+fn include(reference: &str) -> Yaml {
+    let mut entry = Hash::new();
+    entry.insert(yaml_str("include"), yaml_str(reference));
+    Yaml::Hash(entry)
+}
+
+fn as_rules(value: &Settings) -> Result<&[Settings], ParseSyntaxError> {
+    value
+        .as_array()
+        .map(Vec::as_slice)
+        .ok_or(ParseSyntaxError::TypeMismatch)
+}
+
+/// Booleans are often written as `1` in TextMate grammars
+fn is_true(value: &Settings) -> bool {
+    value.as_bool().unwrap_or(false) || value.as_i64() == Some(1)
+}
+
+fn yaml_str(s: &str) -> Yaml {
+    Yaml::String(s.to_owned())
+}
+
+#[cfg(feature = "plist-load")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::syntax_definition::*;
+    use crate::parsing::{ParseState, ScopeStack, SyntaxSetBuilder};
+    use std::io::Cursor;
+
+    const GRAMMAR: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>name</key>
+  <string>Test</string>
+  <key>scopeName</key>
+  <string>source.tm</string>
+  <key>fileTypes</key>
+  <array><string>tm</string></array>
+  <key>patterns</key>
+  <array>
+    <dict><key>include</key><string>#strings</string></dict>
+    <dict>
+      <key>match</key><string>\b(let)\s+(\w+)</string>
+      <key>captures</key>
+      <dict>
+        <key>1</key><dict><key>name</key><string>keyword.tm</string></dict>
+        <key>2</key><dict><key>name</key><string>variable.tm</string></dict>
+      </dict>
+    </dict>
+    <dict>
+      <key>begin</key><string>^&gt;</string>
+      <key>while</key><string>^&gt;</string>
+      <key>name</key><string>markup.quote.tm</string>
+    </dict>
+  </array>
+  <key>repository</key>
+  <dict>
+    <key>strings</key>
+    <dict>
+      <key>begin</key><string>"</string>
+      <key>beginCaptures</key>
+      <dict><key>0</key><dict><key>name</key><string>punctuation.begin.tm</string></dict></dict>
+      <key>end</key><string>"</string>
+      <key>name</key><string>string.tm</string>
+      <key>contentName</key><string>content.tm</string>
+      <key>patterns</key>
+      <array><dict><key>match</key><string>\\.</string><key>name</key><string>escape.tm</string></dict></array>
+    </dict>
+  </dict>
+  <key>injections</key>
+  <dict>
+    <key>L:string.tm</key>
+    <dict>
+      <key>patterns</key>
+      <array><dict><key>match</key><string>TODO</string><key>name</key><string>todo.tm</string></dict></array>
+    </dict>
+  </dict>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn can_load_tmlanguage() {
+        let defn =
+            SyntaxDefinition::load_from_tmlanguage(Cursor::new(GRAMMAR), true, None).unwrap();
+        assert_eq!(defn.name, "Test");
+        assert_eq!(defn.scope, Scope::new("source.tm").unwrap());
+        assert_eq!(defn.file_extensions, vec!["tm".to_owned()]);
+        assert!(defn.contexts.contains_key("strings"));
+        assert!(defn.contexts.contains_key("__injection_0"));
+        match defn.contexts["main"].patterns[0] {
+            Pattern::Include(ContextReference::Named(ref name)) => assert_eq!(name, "strings"),
+            _ => unreachable!("Expected include of repository entry"),
+        }
+
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(defn);
+        let ss = builder.build();
+        let stacks = |lines: &[&str]| {
+            let mut state = ParseState::new(&ss.syntaxes()[0]);
+            let mut stack = ScopeStack::new();
+            let mut stacks = Vec::new();
+            for line in lines {
+                for (_, op) in state.parse_line(line, &ss).unwrap() {
+                    stack.apply(&op).unwrap();
+                    stacks.push(format!("{:?}", stack.as_slice()));
+                }
+            }
+            stacks
+        };
+
+        let string_stacks = stacks(&["let x = \"a\\n TODO\"\n"]);
+        for expected in [
+            "<source.tm>, <keyword.tm>",
+            "<source.tm>, <variable.tm>",
+            "<source.tm>, <string.tm>, <punctuation.begin.tm>",
+            "<source.tm>, <string.tm>, <content.tm>, <escape.tm>",
+            "<source.tm>, <string.tm>, <content.tm>, <todo.tm>",
+        ] {
+            assert!(
+                string_stacks.iter().any(|s| s.contains(expected)),
+                "missing {}",
+                expected
+            );
+        }
+
+        let quote_stacks = stacks(&["> a\n", "> b\n", "c\n"]);
+        assert!(quote_stacks
+            .iter()
+            .any(|s| s.contains("<source.tm>, <markup.quote.tm>")));
+        assert_eq!(quote_stacks.last().unwrap(), "[<source.tm>]");
+    }
+
+    #[test]
+    fn errors_on_missing_scope_name() {
+        let plist = GRAMMAR.replace("scopeName", "notScopeName");
+        match SyntaxDefinition::load_from_tmlanguage(Cursor::new(plist), true, None) {
+            Err(ParseSyntaxError::MissingMandatoryKey(key)) => assert_eq!(key, "scopeName"),
+            _ => unreachable!("Expected missing scopeName"),
+        }
+    }
+}
@@ -0,0 +1,521 @@
+//! Writing syntax definitions back to the `.sublime-syntax` format
+use super::scope::*;
+use super::syntax_definition::*;
+use std::collections::HashSet;
+use yaml_rust::yaml::Hash;
+use yaml_rust::{EmitError, Yaml, YamlEmitter};
+
+/// An error writing a syntax definition with [`to_sublime_syntax`]
+///
+/// [`to_sublime_syntax`]: struct.SyntaxDefinition.html#method.to_sublime_syntax
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum EmitSyntaxError {
+    /// A context reference that was already linked by a [`SyntaxSet`] can't be turned back into
+    /// a name
+    ///
+    /// [`SyntaxSet`]: ../struct.SyntaxSet.html
+    #[error("Can't emit a reference to a linked context")]
+    LinkedReference,
+    /// An inline context reference to a context that doesn't exist
+    #[error("Inline context not found: {0}")]
+    MissingContext(String),
+    /// Error from the YAML emitter
+    #[error("Error while emitting YAML: {0}")]
+    Emit(#[from] EmitError),
+}
+
+impl SyntaxDefinition {
+    /// Serializes the syntax definition to a `.sublime-syntax` file.
+    ///
+    /// Loading the result with [`load_from_str`] gives back the same definition. Inline contexts
+    /// are written as anonymous contexts where they are used, `embed`s are written as `embed`
+    /// and the contexts that are added when loading, like `__start`, are left out.
+    ///
+    /// This does not give back the file the definition was loaded from: the `{{variable}}`
+    /// references in regexes are substituted when loading and can't be recovered, so regexes are
+    /// written with the variables already substituted. The `variables` are still written, but
+    /// editing them in the result has no effect on the regexes. The `$` anchors that loading with
+    /// `lines_include_newline` rewrites are written as `$` again.
+    ///
+    /// Fails for definitions taken from a built [`SyntaxSet`], whose references are already
+    /// linked.
+    ///
+    /// [`load_from_str`]: #method.load_from_str
+    /// [`SyntaxSet`]: ../struct.SyntaxSet.html
+    pub fn to_sublime_syntax(&self) -> Result<String, EmitSyntaxError> {
+        let doc = Emitter::new(self).top_level()?;
+        let mut out = String::from("%YAML 1.2\n");
+        YamlEmitter::new(&mut out).dump(&doc)?;
+        out.push('\n');
+        Ok(out)
+    }
+}
+
+struct Emitter<'a> {
+    defn: &'a SyntaxDefinition,
+    /// Contexts that are written inline where they are referenced
+    inline: HashSet<&'a str>,
+}
+
+impl<'a> Emitter<'a> {
+    fn new(defn: &'a SyntaxDefinition) -> Emitter<'a> {
+        let mut inline = HashSet::new();
+        let mut add = |reference: &'a ContextReference| {
+            if let ContextReference::Inline(ref name) = *reference {
+                inline.insert(name.as_str());
+            }
+        };
+        for context in defn.contexts.values() {
+            for pattern in &context.patterns {
+                match *pattern {
+                    Pattern::Include(ref reference) => add(reference),
+                    Pattern::Match(ref pattern) => {
+                        operation_references(&pattern.operation)
+                            .iter()
+                            .for_each(&mut add);
+                        if let Some(ref reference) = pattern.with_prototype {
+                            add(reference);
+                        }
+                    }
+                }
+            }
+        }
+        Emitter { defn, inline }
+    }
+
+    fn top_level(&self) -> Result<Yaml, EmitSyntaxError> {
+        let defn = self.defn;
+        let mut h = Hash::new();
+        insert(&mut h, "name", string(&defn.name));
+        insert(&mut h, "scope", string(&defn.scope.build_string()));
+        if defn.version != 1 {
+            insert(&mut h, "version", Yaml::Integer(i64::from(defn.version)));
+        }
+        if !defn.file_extensions.is_empty() {
+            let extensions = defn.file_extensions.iter().map(|e| string(e)).collect();
+            insert(&mut h, "file_extensions", Yaml::Array(extensions));
+        }
+        if let Some(ref first_line_match) = defn.first_line_match {
+            insert(&mut h, "first_line_match", string(first_line_match));
+        }
+        if defn.hidden {
+            insert(&mut h, "hidden", Yaml::Boolean(true));
+        }
+        if !defn.variables.is_empty() {
+            let mut names: Vec<&String> = defn.variables.keys().collect();
+            names.sort();
+            let mut variables = Hash::new();
+            for name in names {
+                variables.insert(string(name), string(&defn.variables[name]));
+            }
+            insert(&mut h, "variables", Yaml::Hash(variables));
+        }
+
+        let mut names: Vec<&String> = defn
+            .contexts
+            .keys()
+            .filter(|name| {
+                !self.inline.contains(name.as_str())
+                    && name.as_str() != "__start"
+                    && name.as_str() != "__main"
+            })
+            .collect();
+        // `main` and `prototype` first, like most syntax files
+        names.sort_by_key(|name| (name.as_str() != "main", name.as_str() != "prototype", *name));
+        let mut contexts = Hash::new();
+        for name in names {
+            let context = self.context(name, name.as_str() != "prototype")?;
+            contexts.insert(string(name), context);
+        }
+        insert(&mut h, "contexts", Yaml::Hash(contexts));
+        Ok(Yaml::Hash(h))
+    }
+
+    /// `include_prototype` is the value of `meta_include_prototype` when it isn't specified
+    fn context(&self, name: &str, include_prototype: bool) -> Result<Yaml, EmitSyntaxError> {
+        let context = self
+            .defn
+            .contexts
+            .get(name)
+            .ok_or_else(|| EmitSyntaxError::MissingContext(name.to_owned()))?;
+        let mut items = Vec::new();
+
+        if !context.meta_scope.is_empty() {
+            items.push(entry("meta_scope", scopes(&context.meta_scope)));
+        }
+        let mut meta_content_scope = &context.meta_content_scope[..];
+        if name == "main" && meta_content_scope.first() == Some(&self.defn.scope) {
+            // added when loading, see `add_initial_contexts`
+            meta_content_scope = &meta_content_scope[1..];
+        }
+        if !meta_content_scope.is_empty() {
+            items.push(entry("meta_content_scope", scopes(meta_content_scope)));
+        }
+        if context.meta_include_prototype != include_prototype {
+            items.push(entry(
+                "meta_include_prototype",
+                Yaml::Boolean(context.meta_include_prototype),
+            ));
+        }
+        match context.clear_scopes {
+            Some(ClearAmount::All) => items.push(entry("clear_scopes", Yaml::Boolean(true))),
+            Some(ClearAmount::TopN(n)) => {
+                items.push(entry("clear_scopes", Yaml::Integer(n as i64)))
+            }
+            None => {}
+        }
+
+        for pattern in &context.patterns {
+            items.push(match *pattern {
+                Pattern::Include(ref reference) => entry("include", self.reference(reference)?),
+                Pattern::Match(ref pattern) => self.match_pattern(pattern)?,
+            });
+        }
+
+        if items.is_empty() {
+            // an empty list can't be told apart from a list of references when pushing
+            items.push(entry(
+                "meta_include_prototype",
+                Yaml::Boolean(context.meta_include_prototype),
+            ));
+        }
+        Ok(Yaml::Array(items))
+    }
+
+    fn match_pattern(&self, pattern: &MatchPattern) -> Result<Yaml, EmitSyntaxError> {
+        let mut h = Hash::new();
+        insert(
+            &mut h,
+            "match",
+            string(&regex_str(pattern.regex.regex_str())),
+        );
+        if !pattern.scope.is_empty() {
+            insert(&mut h, "scope", scopes(&pattern.scope));
+        }
+        if let Some(ref captures) = pattern.captures {
+            insert(&mut h, "captures", capture_mapping(captures));
+        }
+
+        if let Some(embed) = self.embed(pattern) {
+            let escape = embed.escape;
+            insert(&mut h, "embed", string(&self.reference_str(embed.target)?));
+            if !embed.embed_scope.is_empty() {
+                insert(&mut h, "embed_scope", scopes(embed.embed_scope));
+            }
+            insert(
+                &mut h,
+                "escape",
+                string(&regex_str(escape.regex.regex_str())),
+            );
+            if let Some(ref captures) = escape.captures {
+                insert(&mut h, "escape_captures", capture_mapping(captures));
+            }
+            return Ok(Yaml::Hash(h));
+        }
+
+        match pattern.operation {
+            MatchOperation::Push(ref refs) => insert(&mut h, "push", self.references(refs)?),
+            MatchOperation::Set(ref refs) => insert(&mut h, "set", self.references(refs)?),
+            MatchOperation::Pop => insert(&mut h, "pop", Yaml::Boolean(true)),
+            MatchOperation::PopMultiple(count) => insert(&mut h, "pop", pop_count(count)),
+            MatchOperation::PopAndPush(count, ref refs) => {
+                insert(&mut h, "pop", pop_count(count));
+                insert(&mut h, "push", self.references(refs)?);
+            }
+            MatchOperation::PopAndSet(count, ref refs) => {
+                insert(&mut h, "pop", pop_count(count));
+                insert(&mut h, "set", self.references(refs)?);
+            }
+            MatchOperation::Branch {
+                ref name,
+                ref contexts,
+            } => {
+                insert(&mut h, "branch_point", string(name));
+                let branches = contexts
+                    .iter()
+                    .map(|r| self.reference(r))
+                    .collect::<Result<_, _>>()?;
+                insert(&mut h, "branch", Yaml::Array(branches));
+            }
+            MatchOperation::Fail(ref name) => insert(&mut h, "fail", string(name)),
+            MatchOperation::None => {}
+        }
+        if let Some(ContextReference::Inline(ref name)) = pattern.with_prototype {
+            insert(&mut h, "with_prototype", self.context(name, false)?);
+        }
+        Ok(Yaml::Hash(h))
+    }
+
+    /// Recognizes the push that `embed` is loaded as: an inline context that pops at `escape`,
+    /// followed by the embedded syntax.
+    fn embed(&self, pattern: &'a MatchPattern) -> Option<Embed<'a>> {
+        let refs = match pattern.operation {
+            MatchOperation::Push(ref refs) if refs.len() == 2 => refs,
+            _ => return None,
+        };
+        let escape_context = match refs[0] {
+            ContextReference::Inline(ref name) => self.defn.contexts.get(name)?,
+            _ => return None,
+        };
+        let target = match refs[1] {
+            ContextReference::ByScope {
+                with_escape: true, ..
+            }
+            | ContextReference::File {
+                with_escape: true, ..
+            } => &refs[1],
+            _ => return None,
+        };
+        let escape = match escape_context.patterns[..] {
+            [Pattern::Match(ref escape)] if escape.operation == MatchOperation::Pop => escape,
+            _ => return None,
+        };
+        if escape_context.meta_include_prototype
+            || !escape_context.meta_scope.is_empty()
+            || escape_context.clear_scopes.is_some()
+            || pattern.with_prototype.is_none()
+        {
+            return None;
+        }
+        Some(Embed {
+            target,
+            embed_scope: &escape_context.meta_content_scope,
+            escape,
+        })
+    }
+
+    fn references(&self, refs: &[ContextReference]) -> Result<Yaml, EmitSyntaxError> {
+        match refs {
+            [reference] => self.reference(reference),
+            _ => Ok(Yaml::Array(
+                refs.iter()
+                    .map(|r| self.reference(r))
+                    .collect::<Result<_, _>>()?,
+            )),
+        }
+    }
+
+    fn reference(&self, reference: &ContextReference) -> Result<Yaml, EmitSyntaxError> {
+        match *reference {
+            ContextReference::Inline(ref name) => self.context(name, true),
+            _ => Ok(string(&self.reference_str(reference)?)),
+        }
+    }
+
+    fn reference_str(&self, reference: &ContextReference) -> Result<String, EmitSyntaxError> {
+        let (mut s, sub_context) = match *reference {
+            ContextReference::Named(ref name) => return Ok(name.clone()),
+            ContextReference::ByScope {
+                scope,
+                ref sub_context,
+                with_escape,
+            } => (
+                format!("scope:{}", scope.build_string()),
+                self.sub_context(sub_context, with_escape),
+            ),
+            ContextReference::File {
+                ref name,
+                ref sub_context,
+                with_escape,
+            } => (
+                format!("{}.sublime-syntax", name),
+                self.sub_context(sub_context, with_escape),
+            ),
+            ContextReference::Inline(ref name) => {
+                return Err(EmitSyntaxError::MissingContext(name.clone()))
+            }
+            ContextReference::Direct(_) => return Err(EmitSyntaxError::LinkedReference),
+        };
+        if let Some(sub_context) = sub_context {
+            s.push('#');
+            s.push_str(sub_context);
+        }
+        Ok(s)
+    }
+
+    fn sub_context<'b>(&self, sub_context: &'b Option<String>, embed: bool) -> Option<&'b str> {
+        match sub_context.as_deref() {
+            // the `__main` that an `embed_scope` implies in version 2
+            Some("__main") if embed && self.defn.version >= 2 => None,
+            sub_context => sub_context,
+        }
+    }
+}
+
+struct Embed<'a> {
+    target: &'a ContextReference,
+    embed_scope: &'a [Scope],
+    escape: &'a MatchPattern,
+}
+
+fn operation_references(operation: &MatchOperation) -> &[ContextReference] {
+    match *operation {
+        MatchOperation::Push(ref refs)
+        | MatchOperation::Set(ref refs)
+        | MatchOperation::PopAndPush(_, ref refs)
+        | MatchOperation::PopAndSet(_, ref refs)
+        | MatchOperation::Branch {
+            contexts: ref refs, ..
+        } => refs,
+        MatchOperation::Pop
+        | MatchOperation::PopMultiple(_)
+        | MatchOperation::Fail(_)
+        | MatchOperation::None => &[],
+    }
+}
+
+/// Undoes the rewrite of `$` by `regex_for_newlines`, which is applied again when loading
+fn regex_str(regex: &str) -> String {
+    regex.replace("(?m:$)", "$")
+}
+
+fn pop_count(count: usize) -> Yaml {
+    if count == 1 {
+        Yaml::Boolean(true)
+    } else {
+        Yaml::Integer(count as i64)
+    }
+}
+
+fn capture_mapping(captures: &CaptureMapping) -> Yaml {
+    let mut h = Hash::new();
+    for (index, capture_scopes) in captures {
+        h.insert(Yaml::Integer(*index as i64), scopes(capture_scopes));
+    }
+    Yaml::Hash(h)
+}
+
+fn scopes(scopes: &[Scope]) -> Yaml {
+    let strings: Vec<String> = scopes.iter().map(|s| s.build_string()).collect();
+    Yaml::String(strings.join(" "))
+}
+
+fn string(s: &str) -> Yaml {
+    Yaml::String(s.to_owned())
+}
+
+fn insert(h: &mut Hash, key: &str, value: Yaml) {
+    h.insert(string(key), value);
+}
+
+fn entry(key: &str, value: Yaml) -> Yaml {
+    let mut h = Hash::new();
+    insert(&mut h, key, value);
+    Yaml::Hash(h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trips(defn: &SyntaxDefinition, lines_include_newline: bool) {
+        let yaml = defn.to_sublime_syntax().unwrap();
+        let reloaded = SyntaxDefinition::load_from_str(&yaml, lines_include_newline, None)
+            .unwrap_or_else(|e| panic!("{}\n{}", e, yaml));
+        assert_eq!(&reloaded, defn, "{}", yaml);
+    }
+
+    #[test]
+    fn can_emit_sublime_syntax() {
+        let defn = SyntaxDefinition::load_from_str(
+            r#"
+            name: Emit
+            scope: source.emit
+            version: 2
+            file_extensions: [emit]
+            first_line_match: '^#!emit'
+            hidden: true
+            variables:
+              ident: '[a-z]+'
+            contexts:
+              prototype:
+                - match: '#.*$'
+                  scope: comment.emit
+              main:
+                - meta_content_scope: meta.main.emit
+                - match: '({{ident}})(\()'
+                  captures:
+                    1: entity.name.emit
+                    2: punctuation.emit
+                  push: [args, [{meta_scope: meta.call.emit}, {match: '', pop: true}]]
+                - match: '```'
+                  embed: scope:source.other
+                  embed_scope: markup.raw.emit
+                  escape: '```'
+                  escape_captures:
+                    0: punctuation.emit
+                - match: '<'
+                  push:
+                    - clear_scopes: 1
+                    - match: '>'
+                      pop: true
+                  with_prototype:
+                    - match: '!'
+                      scope: invalid.emit
+                - match: '(?=\w)'
+                  branch_point: word
+                  branch: [keyword, Other.sublime-syntax#word]
+              args:
+                - meta_include_prototype: false
+                - match: '\)'
+                  pop: 2
+                - match: ','
+                  pop: true
+                  set: [[{meta_scope: meta.arg.emit}], args]
+                - include: scope:source.other#args
+              keyword:
+                - match: 'if\b'
+                  scope: keyword.emit
+                  pop: true
+                - match: ''
+                  fail: word
+            "#,
+            true,
+            None,
+        )
+        .unwrap();
+        assert_round_trips(&defn, true);
+
+        let yaml = defn.to_sublime_syntax().unwrap();
+        assert!(yaml.starts_with("%YAML 1.2\n---\n"));
+        assert!(yaml.contains("ident: \"[a-z]+\""));
+        assert!(yaml.contains("embed: \"scope:source.other\""));
+        assert!(!yaml.contains("__main"));
+        assert!(!yaml.contains("#anon_"));
+    }
+
+    #[test]
+    fn errors_on_linked_references() {
+        let mut defn =
+            SyntaxDefinition::load_from_str("scope: source.c\ncontexts: {main: []}", true, None)
+                .unwrap();
+        let main = defn.contexts.get_mut("main").unwrap();
+        main.patterns
+            .push(Pattern::Include(ContextReference::Direct(ContextId {
+                syntax_index: 0,
+                context_index: 0,
+            })));
+        match defn.to_sublime_syntax() {
+            Err(EmitSyntaxError::LinkedReference) => (),
+            _ => unreachable!("Expected a linked reference error"),
+        }
+    }
+
+    #[test]
+    fn can_round_trip_syntax_files() {
+        for path in [
+            "testdata/JSON.sublime-syntax",
+            "testdata/Testing.sublime-syntax",
+            "testdata/embed_escape_test.sublime-syntax",
+            "testdata/parser_tests.sublime-syntax",
+        ] {
+            let s = std::fs::read_to_string(path).unwrap();
+            for lines_include_newline in [true, false] {
+                let defn =
+                    SyntaxDefinition::load_from_str(&s, lines_include_newline, None).unwrap();
+                assert_round_trips(&defn, lines_include_newline);
+            }
+        }
+    }
+}
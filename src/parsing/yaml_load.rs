@@ -1,12 +1,15 @@
 use super::regex::{Regex, Region};
 use super::scope::*;
 use super::syntax_definition::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
 use std::ops::DerefMut;
 use std::path::Path;
+use yaml_rust::parser::{self, MarkedEventReceiver};
+use yaml_rust::scanner::Marker;
 use yaml_rust::yaml::Hash;
-use yaml_rust::{ScanError, Yaml, YamlLoader};
+use yaml_rust::{Event, ScanError, Yaml, YamlLoader};
 
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
@@ -33,10 +36,134 @@ pub enum ParseSyntaxError {
     #[error("Context 'main' is missing")]
     MainMissing,
     /// Some part of the YAML file is the wrong type (e.g a string but should be a list)
-    /// Sorry this doesn't give you any way to narrow down where this is.
-    /// Maybe use Sublime Text to figure it out.
     #[error("Type mismatch")]
     TypeMismatch,
+    /// A syntax `extends` a parent syntax that could not be found
+    #[error("Parent syntax not found: {0}")]
+    MissingParent(String),
+    /// A syntax (indirectly) `extends` itself
+    #[error("Cyclic syntax inheritance involving {0}")]
+    CyclicExtends(String),
+    /// Invalid JSON syntax in a `.tmLanguage.json` file
+    #[error("Invalid JSON: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+    /// Invalid plist syntax in a `.tmLanguage` file
+    #[cfg(feature = "plist-load")]
+    #[error("Invalid plist: {0}")]
+    InvalidPlist(#[from] crate::highlighting::SettingsError),
+    /// A regex uses a variable that isn't defined. Only reported by the lenient loading methods,
+    /// otherwise undefined variables are replaced by an empty string.
+    #[error("Unknown variable: {0}")]
+    UnknownVariable(String),
+}
+
+/// A [`ParseSyntaxError`] with the place in the syntax definition where it occurred
+///
+/// Returned by the loading methods that support `extends`, like
+/// [`SyntaxDefinition::load_from_str_with_parents`].
+///
+/// [`ParseSyntaxError`]: enum.ParseSyntaxError.html
+/// [`SyntaxDefinition::load_from_str_with_parents`]: struct.SyntaxDefinition.html#method.load_from_str_with_parents
+#[derive(Debug)]
+pub struct LocatedSyntaxError {
+    error: ParseSyntaxError,
+    location: Box<SyntaxLocation>,
+}
+
+impl LocatedSyntaxError {
+    fn new(error: ParseSyntaxError, location: SyntaxLocation) -> LocatedSyntaxError {
+        LocatedSyntaxError {
+            error,
+            location: Box::new(location),
+        }
+    }
+
+    /// The error without its location
+    pub fn error(&self) -> &ParseSyntaxError {
+        &self.error
+    }
+
+    /// The place where the error occurred, empty if it isn't known
+    pub fn location(&self) -> &SyntaxLocation {
+        &self.location
+    }
+
+    /// Returns the error without its location
+    pub fn into_error(self) -> ParseSyntaxError {
+        self.error
+    }
+
+    /// Adds the location, unless the error already has a more precise one. Errors in nested
+    /// contexts keep the location in the nested context, unless it's a context that was generated
+    /// while loading and has no position in the file.
+    fn located(mut self, location: SyntaxLocation) -> LocatedSyntaxError {
+        let existing = &*self.location;
+        if *existing == SyntaxLocation::default()
+            || (existing.position.is_none() && location.position.is_some())
+        {
+            *self.location = location;
+        }
+        self
+    }
+}
+
+impl From<ParseSyntaxError> for LocatedSyntaxError {
+    fn from(error: ParseSyntaxError) -> LocatedSyntaxError {
+        LocatedSyntaxError::new(error, SyntaxLocation::default())
+    }
+}
+
+impl fmt::Display for LocatedSyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self.location == SyntaxLocation::default() {
+            write!(f, "{}", self.error)
+        } else {
+            write!(f, "{}: {}", self.location, self.error)
+        }
+    }
+}
+
+impl Error for LocatedSyntaxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// The place in a syntax definition where a [`ParseSyntaxError`] occurred
+///
+/// [`ParseSyntaxError`]: enum.ParseSyntaxError.html
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SyntaxLocation {
+    /// Line and column in the YAML source, both starting at 1. Not known for syntaxes that weren't
+    /// loaded from YAML.
+    pub position: Option<(usize, usize)>,
+    /// For syntaxes that use `extends`, the `extends` value of the parent syntax the position is
+    /// in, or `None` if it's in the syntax itself
+    pub file: Option<String>,
+    /// The name of the context, anonymous contexts have generated names like `#anon_main_0`
+    pub context: Option<String>,
+    /// The index of the entry in the context, counting meta entries like `meta_scope`
+    pub pattern: Option<usize>,
+}
+
+impl fmt::Display for SyntaxLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some((line, column)) = self.position {
+            match self.file {
+                Some(ref file) => parts.push(format!("{} line {}, column {}", file, line, column)),
+                None => parts.push(format!("line {}, column {}", line, column)),
+            }
+        }
+        if let Some(ref context) = self.context {
+            parts.push(format!("context {}", context));
+        }
+        if let Some(pattern) = self.pattern {
+            parts.push(format!("pattern {}", pattern));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
 }
 
 fn get_key<'a, R, F: FnOnce(&'a Yaml) -> Option<R>>(
@@ -61,6 +188,36 @@ struct ParserState<'a> {
     variable_regex: Regex,
     backref_regex: Regex,
     lines_include_newline: bool,
+    version: u8,
+    marks: Marks<'a>,
+    /// When loading leniently, errors are collected here and the entries that caused them are
+    /// skipped
+    diagnostics: Option<&'a mut Vec<LocatedSyntaxError>>,
+}
+
+impl ParserState<'_> {
+    fn location(&self, y: &Yaml, context: Option<&str>, pattern: Option<usize>) -> SyntaxLocation {
+        location(&self.marks, y, context, pattern)
+    }
+}
+
+/// Positions of YAML nodes by address, with the `extends` value of the parent syntax the node is
+/// in, if any
+type Marks<'a> = HashMap<*const Yaml, (Marker, Option<&'a str>)>;
+
+fn location(
+    marks: &Marks<'_>,
+    y: &Yaml,
+    context: Option<&str>,
+    pattern: Option<usize>,
+) -> SyntaxLocation {
+    let mark = marks.get(&(y as *const Yaml));
+    SyntaxLocation {
+        position: mark.map(|(mark, _)| (mark.line(), mark.col() + 1)),
+        file: mark.and_then(|(_, file)| file.map(str::to_owned)),
+        context: context.map(str::to_owned),
+        pattern,
+    }
 }
 
 // `__start` must not include prototypes from the actual syntax definition,
@@ -81,23 +238,166 @@ impl SyntaxDefinition {
     ///
     /// `fallback_name` is an optional name to use when the YAML doesn't provide a `name` key.
     ///
+    /// The `extends` key is ignored, so a syntax that inherits from another one only gets its own
+    /// variables and contexts. Use [`load_from_str_with_parents`](#method.load_from_str_with_parents)
+    /// to merge them with the ones of its parents. Use
+    /// [`load_from_str_located`](#method.load_from_str_located) to get where errors occurred.
+    ///
     /// [`SyntaxSet`]: ../struct.SyntaxSet.html
     pub fn load_from_str(
         s: &str,
         lines_include_newline: bool,
         fallback_name: Option<&str>,
     ) -> Result<SyntaxDefinition, ParseSyntaxError> {
-        let docs = match YamlLoader::load_from_str(s) {
-            Ok(x) => x,
-            Err(e) => return Err(ParseSyntaxError::InvalidYaml(e)),
-        };
-        if docs.is_empty() {
-            return Err(ParseSyntaxError::EmptyFile);
+        SyntaxDefinition::load_from_str_located(s, lines_include_newline, fallback_name)
+            .map_err(LocatedSyntaxError::into_error)
+    }
+
+    /// Same as [`load_from_str`](#method.load_from_str), but errors come with the place where they
+    /// occurred
+    pub fn load_from_str_located(
+        s: &str,
+        lines_include_newline: bool,
+        fallback_name: Option<&str>,
+    ) -> Result<SyntaxDefinition, LocatedSyntaxError> {
+        SyntaxDefinition::load_yaml_source(
+            s,
+            lines_include_newline,
+            fallback_name,
+            None::<&mut fn(&str) -> Option<String>>,
+            None,
+        )
+    }
+
+    /// Same as [`load_from_str`](#method.load_from_str), but also supports syntaxes that inherit
+    /// from other syntaxes using the `extends` key.
+    ///
+    /// `resolve_parent` is called with every value of `extends` (e.g.
+    /// `Packages/JavaScript/JavaScript.sublime-syntax`) and should return the YAML source of that
+    /// syntax, or `None` if it can't be found. Parents can extend other syntaxes themselves.
+    ///
+    /// Like in Sublime Text, the variables and contexts of the parent are inherited. Variables
+    /// and contexts defined in the child replace the ones of the parent, except for contexts using
+    /// `meta_prepend: true` or `meta_append: true`, whose patterns are added before or after the
+    /// ones of the parent context. Since variables are resolved after merging, regexes of the
+    /// parent use the variables as overridden by the child.
+    ///
+    /// Errors come with the place where they occurred. Positions in the parents are reported with
+    /// the `extends` value of the parent.
+    pub fn load_from_str_with_parents<F>(
+        s: &str,
+        lines_include_newline: bool,
+        fallback_name: Option<&str>,
+        mut resolve_parent: F,
+    ) -> Result<SyntaxDefinition, LocatedSyntaxError>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        SyntaxDefinition::load_yaml_source(
+            s,
+            lines_include_newline,
+            fallback_name,
+            Some(&mut resolve_parent),
+            None,
+        )
+    }
+
+    /// Same as [`load_from_str_with_parents`](#method.load_from_str_with_parents), but entries of
+    /// contexts that can't be loaded are skipped instead of failing the whole syntax.
+    ///
+    /// Returns the syntax without the skipped entries together with the errors they caused, such
+    /// as invalid regexes, missing mandatory keys and invalid scopes, and the variables used by
+    /// regexes that aren't defined. Errors that leave nothing to load, like invalid YAML or a
+    /// missing `main` context, are still returned as an error.
+    pub fn load_from_str_lenient<F>(
+        s: &str,
+        lines_include_newline: bool,
+        fallback_name: Option<&str>,
+        mut resolve_parent: F,
+    ) -> Result<(SyntaxDefinition, Vec<LocatedSyntaxError>), LocatedSyntaxError>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        let mut diagnostics = Vec::new();
+        let syntax = SyntaxDefinition::load_yaml_source(
+            s,
+            lines_include_newline,
+            fallback_name,
+            Some(&mut resolve_parent),
+            Some(&mut diagnostics),
+        )?;
+        Ok((syntax, diagnostics))
+    }
+
+    fn load_yaml_source<F>(
+        s: &str,
+        lines_include_newline: bool,
+        fallback_name: Option<&str>,
+        resolve_parent: Option<&mut F>,
+        diagnostics: Option<&mut Vec<LocatedSyntaxError>>,
+    ) -> Result<SyntaxDefinition, LocatedSyntaxError>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        let doc = load_yaml_doc(s)?;
+        let extends = doc
+            .as_hash()
+            .is_some_and(|h| h.contains_key(&yaml_key("extends")));
+        // Without a way to find the parents, `extends` is ignored like any other unknown key
+        match resolve_parent {
+            Some(resolve_parent) if extends => {
+                let marks = yaml_marks(s);
+                let mut parents = Vec::new();
+                let merged =
+                    resolve_extends(doc.clone(), resolve_parent, &mut Vec::new(), &mut parents)
+                        .map_err(|e| {
+                            // Errors of the parents are at the `extends` of the syntax
+                            let doc_marks = marks
+                                .clone()
+                                .map_or_else(HashMap::new, |m| node_marks(&doc, m, None));
+                            let extends = &doc.as_hash().unwrap()[&yaml_key("extends")];
+                            LocatedSyntaxError::new(e, location(&doc_marks, extends, None, None))
+                        })?;
+                let mut sources = vec![(None, doc, marks)];
+                sources.extend(parents);
+                let marks = merged_marks(&merged, &sources);
+                SyntaxDefinition::load_from_yaml(
+                    &merged,
+                    marks,
+                    lines_include_newline,
+                    fallback_name,
+                    diagnostics,
+                )
+            }
+            _ => {
+                let marks = yaml_marks(s).map_or_else(HashMap::new, |m| node_marks(&doc, m, None));
+                SyntaxDefinition::load_from_yaml(
+                    &doc,
+                    marks,
+                    lines_include_newline,
+                    fallback_name,
+                    diagnostics,
+                )
+            }
         }
-        let doc = &docs[0];
+    }
+
+    /// Loads a syntax from a YAML document with the structure of a `.sublime-syntax` file
+    ///
+    /// `marks` are the positions of the nodes of `doc`. If `diagnostics` are given, the syntax is
+    /// loaded leniently and errors are added to them.
+    pub(crate) fn load_from_yaml(
+        doc: &Yaml,
+        marks: Marks<'_>,
+        lines_include_newline: bool,
+        fallback_name: Option<&str>,
+        diagnostics: Option<&mut Vec<LocatedSyntaxError>>,
+    ) -> Result<SyntaxDefinition, LocatedSyntaxError> {
         let mut scope_repo = SCOPE_REPO.lock().unwrap();
         SyntaxDefinition::parse_top_level(
             doc,
+            marks,
+            diagnostics,
             scope_repo.deref_mut(),
             lines_include_newline,
             fallback_name,
@@ -106,11 +406,23 @@ impl SyntaxDefinition {
 
     fn parse_top_level(
         doc: &Yaml,
+        marks: Marks<'_>,
+        diagnostics: Option<&mut Vec<LocatedSyntaxError>>,
         scope_repo: &mut ScopeRepository,
         lines_include_newline: bool,
         fallback_name: Option<&str>,
-    ) -> Result<SyntaxDefinition, ParseSyntaxError> {
-        let h = doc.as_hash().ok_or(ParseSyntaxError::TypeMismatch)?;
+    ) -> Result<SyntaxDefinition, LocatedSyntaxError> {
+        // Errors of a top level key are at its value, or at the start if it's missing
+        let at = |key: &str| {
+            let y = doc.as_hash().and_then(|h| h.get(&yaml_key(key)));
+            location(&marks, y.unwrap_or(doc), None, None)
+        };
+        let h = doc.as_hash().ok_or_else(|| {
+            LocatedSyntaxError::new(
+                ParseSyntaxError::TypeMismatch,
+                location(&marks, doc, None, None),
+            )
+        })?;
 
         let mut variables = HashMap::new();
         if let Ok(map) = get_key(h, "variables", |x| x.as_hash()) {
@@ -120,21 +432,38 @@ impl SyntaxDefinition {
                 }
             }
         }
-        let contexts_hash = get_key(h, "contexts", |x| x.as_hash())?;
-        let top_level_scope = scope_repo
-            .build(get_key(h, "scope", |x| x.as_str())?)
-            .map_err(ParseSyntaxError::InvalidScope)?;
+        let version = match get_key(h, "version", |x| x.as_i64()) {
+            Ok(v) => u8::try_from(v).map_err(|_| ParseSyntaxError::TypeMismatch),
+            Err(ParseSyntaxError::MissingMandatoryKey(_)) => Ok(1),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| LocatedSyntaxError::new(e, at("version")))?;
+        let contexts_hash = get_key(h, "contexts", |x| x.as_hash())
+            .map_err(|e| LocatedSyntaxError::new(e, at("contexts")))?;
+        let scope = get_key(h, "scope", |x| x.as_str())
+            .map_err(|e| LocatedSyntaxError::new(e, at("scope")))?;
+        let scope_location = at("scope");
+        let contexts_location = at("contexts");
         let mut state = ParserState {
             scope_repo,
             variables,
             variable_regex: Regex::new(r"\{\{([A-Za-z0-9_]+)\}\}".into()),
             backref_regex: Regex::new(r"\\\d".into()),
             lines_include_newline,
+            version,
+            marks,
+            diagnostics,
         };
+        let top_level_scope = state.scope_repo.build(scope).map_err(|e| {
+            LocatedSyntaxError::new(ParseSyntaxError::InvalidScope(e), scope_location)
+        })?;
 
         let mut contexts = SyntaxDefinition::parse_contexts(contexts_hash, &mut state)?;
         if !contexts.contains_key("main") {
-            return Err(ParseSyntaxError::MainMissing);
+            return Err(LocatedSyntaxError::new(
+                ParseSyntaxError::MainMissing,
+                contexts_location,
+            ));
         }
 
         SyntaxDefinition::add_initial_contexts(&mut contexts, &mut state, top_level_scope);
@@ -157,6 +486,8 @@ impl SyntaxDefinition {
                 .ok()
                 .map(|s| s.to_owned()),
             hidden: get_key(h, "hidden", |x| x.as_bool()).unwrap_or(false),
+            version,
+            injection_selector: None,
 
             variables: state.variables,
             contexts,
@@ -167,7 +498,7 @@ impl SyntaxDefinition {
     fn parse_contexts(
         map: &Hash,
         state: &mut ParserState<'_>,
-    ) -> Result<HashMap<String, Context>, ParseSyntaxError> {
+    ) -> Result<HashMap<String, Context>, LocatedSyntaxError> {
         let mut contexts = HashMap::new();
         for (key, value) in map.iter() {
             if let (Some(name), Some(val_vec)) = (key.as_str(), value.as_vec()) {
@@ -193,47 +524,25 @@ impl SyntaxDefinition {
         contexts: &mut HashMap<String, Context>,
         is_prototype: bool,
         namer: &mut ContextNamer,
-    ) -> Result<String, ParseSyntaxError> {
+    ) -> Result<String, LocatedSyntaxError> {
         let mut context = Context::new(!is_prototype);
         let name = namer.next();
 
-        for y in vec.iter() {
-            let map = y.as_hash().ok_or(ParseSyntaxError::TypeMismatch)?;
-
-            let mut is_special = false;
-            if let Ok(x) = get_key(map, "meta_scope", |x| x.as_str()) {
-                context.meta_scope = str_to_scopes(x, state.scope_repo)?;
-                is_special = true;
-            }
-            if let Ok(x) = get_key(map, "meta_content_scope", |x| x.as_str()) {
-                context.meta_content_scope = str_to_scopes(x, state.scope_repo)?;
-                is_special = true;
-            }
-            if let Ok(x) = get_key(map, "meta_include_prototype", |x| x.as_bool()) {
-                context.meta_include_prototype = x;
-                is_special = true;
-            }
-            if let Ok(true) = get_key(map, "clear_scopes", |x| x.as_bool()) {
-                context.clear_scopes = Some(ClearAmount::All);
-                is_special = true;
-            }
-            if let Ok(x) = get_key(map, "clear_scopes", |x| x.as_i64()) {
-                context.clear_scopes = Some(ClearAmount::TopN(x as usize));
-                is_special = true;
-            }
-            if !is_special {
-                if let Ok(x) = get_key(map, "include", Some) {
-                    let reference =
-                        SyntaxDefinition::parse_reference(x, state, contexts, namer, false)?;
-                    context.patterns.push(Pattern::Include(reference));
-                } else {
-                    let pattern =
-                        SyntaxDefinition::parse_match_pattern(map, state, contexts, namer)?;
-                    if pattern.has_captures {
-                        context.uses_backrefs = true;
-                    }
-                    context.patterns.push(Pattern::Match(pattern));
+        for (index, y) in vec.iter().enumerate() {
+            let first_diagnostic = state.diagnostics.as_ref().map_or(0, |d| d.len());
+            let result =
+                SyntaxDefinition::parse_context_entry(y, state, contexts, &mut context, namer);
+            let location = state.location(y, Some(&name), Some(index));
+            match state.diagnostics {
+                Some(ref mut diagnostics) => {
+                    diagnostics.extend(result.err());
+                    let located: Vec<_> = diagnostics
+                        .drain(first_diagnostic..)
+                        .map(|e| e.located(location.clone()))
+                        .collect();
+                    diagnostics.extend(located);
                 }
+                None => result.map_err(|e| e.located(location))?,
             }
         }
 
@@ -241,13 +550,65 @@ impl SyntaxDefinition {
         Ok(name)
     }
 
+    fn parse_context_entry(
+        y: &Yaml,
+        state: &mut ParserState<'_>,
+        contexts: &mut HashMap<String, Context>,
+        context: &mut Context,
+        namer: &mut ContextNamer,
+    ) -> Result<(), LocatedSyntaxError> {
+        let map = y.as_hash().ok_or(ParseSyntaxError::TypeMismatch)?;
+
+        let mut is_special = false;
+        if let Ok(x) = get_key(map, "meta_scope", |x| x.as_str()) {
+            context.meta_scope = str_to_scopes(x, state.scope_repo)?;
+            is_special = true;
+        }
+        if let Ok(x) = get_key(map, "meta_content_scope", |x| x.as_str()) {
+            context.meta_content_scope = str_to_scopes(x, state.scope_repo)?;
+            is_special = true;
+        }
+        if let Ok(x) = get_key(map, "meta_include_prototype", |x| x.as_bool()) {
+            context.meta_include_prototype = x;
+            is_special = true;
+        }
+        if let Ok(true) = get_key(map, "clear_scopes", |x| x.as_bool()) {
+            context.clear_scopes = Some(ClearAmount::All);
+            is_special = true;
+        }
+        if let Ok(x) = get_key(map, "clear_scopes", |x| x.as_i64()) {
+            context.clear_scopes = Some(ClearAmount::TopN(x as usize));
+            is_special = true;
+        }
+        // Only relevant when merging with a parent syntax, see `merge_context_yaml`
+        if get_key(map, "meta_prepend", |x| x.as_bool()).is_ok()
+            || get_key(map, "meta_append", |x| x.as_bool()).is_ok()
+        {
+            is_special = true;
+        }
+        if !is_special {
+            if let Ok(x) = get_key(map, "include", Some) {
+                let reference =
+                    SyntaxDefinition::parse_reference(x, state, contexts, namer, false)?;
+                context.patterns.push(Pattern::Include(reference));
+            } else {
+                let pattern = SyntaxDefinition::parse_match_pattern(map, state, contexts, namer)?;
+                if pattern.has_captures {
+                    context.uses_backrefs = true;
+                }
+                context.patterns.push(Pattern::Match(pattern));
+            }
+        }
+        Ok(())
+    }
+
     fn parse_reference(
         y: &Yaml,
         state: &mut ParserState<'_>,
         contexts: &mut HashMap<String, Context>,
         namer: &mut ContextNamer,
         with_escape: bool,
-    ) -> Result<ContextReference, ParseSyntaxError> {
+    ) -> Result<ContextReference, LocatedSyntaxError> {
         if let Some(s) = y.as_str() {
             let parts: Vec<&str> = s.split('#').collect();
             let sub_context = if parts.len() > 1 {
@@ -281,7 +642,7 @@ impl SyntaxDefinition {
             let subname = SyntaxDefinition::parse_context(v, state, contexts, false, namer)?;
             Ok(ContextReference::Inline(subname))
         } else {
-            Err(ParseSyntaxError::TypeMismatch)
+            Err(ParseSyntaxError::TypeMismatch.into())
         }
     }
 
@@ -290,7 +651,7 @@ impl SyntaxDefinition {
         state: &mut ParserState<'_>,
         contexts: &mut HashMap<String, Context>,
         namer: &mut ContextNamer,
-    ) -> Result<MatchPattern, ParseSyntaxError> {
+    ) -> Result<MatchPattern, LocatedSyntaxError> {
         let raw_regex = get_key(map, "match", |x| x.as_str())?;
         let regex_str = Self::parse_regex(raw_regex, state)?;
         // println!("{:?}", regex_str);
@@ -307,16 +668,50 @@ impl SyntaxDefinition {
         };
 
         let mut has_captures = false;
-        let operation = if get_key(map, "pop", Some).is_ok() {
+        // `pop: false` is the same as leaving out `pop`
+        let pop = get_key(map, "pop", Some)
+            .ok()
+            .filter(|y| **y != Yaml::Boolean(false));
+        let operation = if let Some(y) = pop {
             // Thanks @wbond for letting me know this is the correct way to check for captures
             has_captures = state
                 .backref_regex
                 .search(&regex_str, 0, regex_str.len(), None);
-            MatchOperation::Pop
+            // `pop: true` pops one context, `pop: 2` pops two
+            let count = match *y {
+                Yaml::Boolean(true) => 1,
+                Yaml::Integer(n) if n > 0 => n as usize,
+                _ => return Err(ParseSyntaxError::TypeMismatch.into()),
+            };
+            if let Ok(y) = get_key(map, "push", Some) {
+                let contexts = SyntaxDefinition::parse_pushargs(y, state, contexts, namer)?;
+                MatchOperation::PopAndPush(count, contexts)
+            } else if let Ok(y) = get_key(map, "set", Some) {
+                let contexts = SyntaxDefinition::parse_pushargs(y, state, contexts, namer)?;
+                MatchOperation::PopAndSet(count, contexts)
+            } else if count == 1 {
+                MatchOperation::Pop
+            } else {
+                MatchOperation::PopMultiple(count)
+            }
         } else if let Ok(y) = get_key(map, "push", Some) {
             MatchOperation::Push(SyntaxDefinition::parse_pushargs(y, state, contexts, namer)?)
         } else if let Ok(y) = get_key(map, "set", Some) {
             MatchOperation::Set(SyntaxDefinition::parse_pushargs(y, state, contexts, namer)?)
+        } else if let Ok(y) = get_key(map, "branch", Some) {
+            let name = get_key(map, "branch_point", |x| x.as_str())?.to_owned();
+            let contexts = y
+                .as_vec()
+                .ok_or(ParseSyntaxError::TypeMismatch)?
+                .iter()
+                .map(|x| SyntaxDefinition::parse_reference(x, state, contexts, namer, false))
+                .collect::<Result<Vec<_>, _>>()?;
+            if contexts.is_empty() {
+                return Err(ParseSyntaxError::TypeMismatch.into());
+            }
+            MatchOperation::Branch { name, contexts }
+        } else if let Ok(name) = get_key(map, "fail", |x| x.as_str()) {
+            MatchOperation::Fail(name.to_owned())
         } else if let Ok(y) = get_key(map, "embed", Some) {
             // Same as push so we translate it to what it would be
             let mut embed_escape_context_yaml = vec![];
@@ -326,7 +721,8 @@ impl SyntaxDefinition {
                 Yaml::Boolean(false),
             );
             embed_escape_context_yaml.push(Yaml::Hash(commands));
-            if let Ok(s) = get_key(map, "embed_scope", Some) {
+            let embed_scope = get_key(map, "embed_scope", Some).ok();
+            if let Some(s) = embed_scope {
                 commands = Hash::new();
                 commands.insert(Yaml::String("meta_content_scope".to_string()), s.clone());
                 embed_escape_context_yaml.push(Yaml::Hash(commands));
@@ -346,12 +742,29 @@ impl SyntaxDefinition {
                     false,
                     namer,
                 )?;
-                MatchOperation::Push(vec![
-                    ContextReference::Inline(escape_context),
-                    SyntaxDefinition::parse_reference(y, state, contexts, namer, true)?,
-                ])
+                let mut target =
+                    SyntaxDefinition::parse_reference(y, state, contexts, namer, true)?;
+                if state.version >= 2 && embed_scope.is_some() {
+                    // In version 2, `embed_scope` replaces the top level scope of an embedded
+                    // syntax instead of being combined with it. `__main` is the same as `main`,
+                    // but without the top level scope.
+                    if let ContextReference::ByScope {
+                        ref mut sub_context,
+                        ..
+                    }
+                    | ContextReference::File {
+                        ref mut sub_context,
+                        ..
+                    } = target
+                    {
+                        if sub_context.is_none() {
+                            *sub_context = Some("__main".to_owned());
+                        }
+                    }
+                }
+                MatchOperation::Push(vec![ContextReference::Inline(escape_context), target])
             } else {
-                return Err(ParseSyntaxError::MissingMandatoryKey("escape"));
+                return Err(ParseSyntaxError::MissingMandatoryKey("escape").into());
             }
         } else {
             MatchOperation::None
@@ -401,9 +814,9 @@ impl SyntaxDefinition {
         state: &mut ParserState<'_>,
         contexts: &mut HashMap<String, Context>,
         namer: &mut ContextNamer,
-    ) -> Result<Vec<ContextReference>, ParseSyntaxError> {
+    ) -> Result<Vec<ContextReference>, LocatedSyntaxError> {
         // check for a push of multiple items
-        if y.as_vec().map_or(false, |v| {
+        if y.as_vec().is_some_and(|v| {
             !v.is_empty()
                 && (v[0].as_str().is_some()
                     || (v[0].as_vec().is_some() && v[0].as_vec().unwrap()[0].as_hash().is_some()))
@@ -420,8 +833,22 @@ impl SyntaxDefinition {
         }
     }
 
-    fn parse_regex(raw_regex: &str, state: &ParserState<'_>) -> Result<String, ParseSyntaxError> {
-        let regex = Self::resolve_variables(raw_regex, state);
+    fn parse_regex(
+        raw_regex: &str,
+        state: &mut ParserState<'_>,
+    ) -> Result<String, ParseSyntaxError> {
+        let mut unknown_variables = Vec::new();
+        let regex = Self::resolve_variables(raw_regex, state, &mut unknown_variables);
+        if let Some(ref mut diagnostics) = state.diagnostics {
+            for name in unknown_variables {
+                // Only report each variable once
+                if !diagnostics.iter().any(
+                    |d| matches!(d.error, ParseSyntaxError::UnknownVariable(ref n) if *n == name),
+                ) {
+                    diagnostics.push(ParseSyntaxError::UnknownVariable(name).into());
+                }
+            }
+        }
         let regex = replace_posix_char_classes(regex);
         let regex = if state.lines_include_newline {
             regex_for_newlines(regex)
@@ -435,7 +862,11 @@ impl SyntaxDefinition {
         Ok(regex)
     }
 
-    fn resolve_variables(raw_regex: &str, state: &ParserState<'_>) -> String {
+    fn resolve_variables(
+        raw_regex: &str,
+        state: &ParserState<'_>,
+        unknown_variables: &mut Vec<String>,
+    ) -> String {
         let mut result = String::new();
         let mut index = 0;
         let mut region = Region::new();
@@ -449,12 +880,14 @@ impl SyntaxDefinition {
 
             let var_pos = region.pos(1).unwrap();
             let var_name = &raw_regex[var_pos.0..var_pos.1];
-            let var_raw = state
-                .variables
-                .get(var_name)
-                .map(String::as_ref)
-                .unwrap_or("");
-            let var_resolved = Self::resolve_variables(var_raw, state);
+            let var_raw = match state.variables.get(var_name) {
+                Some(var_raw) => var_raw.as_str(),
+                None => {
+                    unknown_variables.push(var_name.to_owned());
+                    ""
+                }
+            };
+            let var_resolved = Self::resolve_variables(var_raw, state, unknown_variables);
             result.push_str(&var_resolved);
 
             index = end;
@@ -550,6 +983,325 @@ impl SyntaxDefinition {
     }
 }
 
+fn load_yaml_doc(s: &str) -> Result<Yaml, ParseSyntaxError> {
+    let docs = YamlLoader::load_from_str(s)?;
+    docs.into_iter().next().ok_or(ParseSyntaxError::EmptyFile)
+}
+
+/// Records the position of every node of the first document, in pre-order
+#[derive(Default)]
+struct MarkRecorder {
+    marks: Vec<Marker>,
+    has_alias: bool,
+    done: bool,
+}
+
+impl MarkedEventReceiver for MarkRecorder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            _ if self.done => {}
+            Event::DocumentEnd => self.done = true,
+            Event::Scalar(..) | Event::SequenceStart(_) | Event::MappingStart(_) => {
+                self.marks.push(mark)
+            }
+            Event::Alias(_) => self.has_alias = true,
+            _ => {}
+        }
+    }
+}
+
+/// The positions of the nodes of the first document in `s`, in the order of `node_marks`.
+/// `None` if they can't be matched up with the nodes, which is the case for aliases since
+/// these are copies of the anchored node.
+fn yaml_marks(s: &str) -> Option<Vec<Marker>> {
+    let mut recorder = MarkRecorder::default();
+    parser::Parser::new(s.chars())
+        .load(&mut recorder, false)
+        .ok()?;
+    if recorder.has_alias {
+        return None;
+    }
+    Some(recorder.marks)
+}
+
+/// Maps the nodes of `doc` to their positions by address, in the parent syntax `file` if given
+fn node_marks<'a>(doc: &Yaml, marks: Vec<Marker>, file: Option<&'a str>) -> Marks<'a> {
+    fn collect<'a>(y: &'a Yaml, nodes: &mut Vec<&'a Yaml>) {
+        nodes.push(y);
+        match *y {
+            Yaml::Array(ref v) => v.iter().for_each(|x| collect(x, nodes)),
+            Yaml::Hash(ref h) => {
+                for (key, value) in h {
+                    collect(key, nodes);
+                    collect(value, nodes);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut nodes = Vec::new();
+    collect(doc, &mut nodes);
+    // Duplicate keys are only kept once
+    if nodes.len() != marks.len() {
+        return HashMap::new();
+    }
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, y)| {
+            // The mark of a block mapping is at the first `:`, use the first key instead
+            let mark = match **y {
+                Yaml::Hash(ref h) if !h.is_empty() => marks[i + 1],
+                _ => marks[i],
+            };
+            (*y as *const Yaml, (mark, file))
+        })
+        .collect()
+}
+
+/// The document of a syntax with the positions of its nodes, and the `extends` value it was
+/// loaded from if it's a parent
+type SyntaxSource = (Option<String>, Yaml, Option<Vec<Marker>>);
+
+/// Maps the nodes of a document that was merged from a syntax and its parents to the positions
+/// of the nodes they were copied from. `sources` are the documents of the syntax and its parents,
+/// with the `extends` value of the parents and the positions of their nodes.
+///
+/// Entries of contexts are matched up with an equal entry of the same context in the sources,
+/// preferring the syntax itself, and the other top level values with the value of the same key.
+fn merged_marks<'a>(merged: &Yaml, sources: &'a [SyntaxSource]) -> Marks<'a> {
+    /// Copies the positions of the nodes of `source` to the equal nodes of `merged`
+    fn copy<'a>(merged: &Yaml, source: &Yaml, source_marks: &Marks<'a>, marks: &mut Marks<'a>) {
+        if let Some(&mark) = source_marks.get(&(source as *const Yaml)) {
+            marks.insert(merged as *const Yaml, mark);
+        }
+        match (merged, source) {
+            (Yaml::Array(merged), Yaml::Array(source)) => {
+                for (merged, source) in merged.iter().zip(source) {
+                    copy(merged, source, source_marks, marks);
+                }
+            }
+            (Yaml::Hash(merged), Yaml::Hash(source)) => {
+                for ((merged_key, merged), (source_key, source)) in merged.iter().zip(source) {
+                    copy(merged_key, source_key, source_marks, marks);
+                    copy(merged, source, source_marks, marks);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let sources: Vec<(&Hash, Marks<'a>)> = sources
+        .iter()
+        .filter_map(|(file, doc, marks)| {
+            let marks = node_marks(doc, marks.clone()?, file.as_deref());
+            Some((doc.as_hash()?, marks))
+        })
+        .collect();
+    let mut marks = HashMap::new();
+    let merged = match merged.as_hash() {
+        Some(merged) => merged,
+        None => return marks,
+    };
+    let contexts_key = yaml_key("contexts");
+    for (key, value) in merged {
+        if *key != contexts_key {
+            if let Some((source, source_marks)) =
+                sources.iter().find(|(h, _)| h.get(key) == Some(value))
+            {
+                let (source_key, source_value) = source.iter().find(|(k, _)| *k == key).unwrap();
+                copy(key, source_key, source_marks, &mut marks);
+                copy(value, source_value, source_marks, &mut marks);
+            }
+            continue;
+        }
+        let contexts = match value.as_hash() {
+            Some(contexts) => contexts,
+            None => continue,
+        };
+        // Entries that were already matched up, so that equal entries get different positions
+        let mut used = HashSet::new();
+        for (name, entries) in contexts {
+            for entry in entries.as_vec().into_iter().flatten() {
+                let found = sources.iter().find_map(|(source, source_marks)| {
+                    let source_entries = source.get(&contexts_key)?.as_hash()?.get(name)?;
+                    let source_entry = source_entries
+                        .as_vec()?
+                        .iter()
+                        .find(|y| *y == entry && !used.contains(&(*y as *const Yaml)))?;
+                    Some((source_entry, source_marks))
+                });
+                if let Some((source_entry, source_marks)) = found {
+                    used.insert(source_entry as *const Yaml);
+                    copy(entry, source_entry, source_marks, &mut marks);
+                }
+            }
+        }
+    }
+    marks
+}
+
+/// Keys that make an entry of a context a "meta" entry instead of a pattern.
+pub(crate) const META_KEYS: &[&str] = &[
+    "meta_scope",
+    "meta_content_scope",
+    "meta_include_prototype",
+    "clear_scopes",
+    "meta_prepend",
+    "meta_append",
+];
+
+fn yaml_key(key: &str) -> Yaml {
+    Yaml::String(key.to_owned())
+}
+
+/// Loads the YAML document of a syntax, merged with its parents if it uses `extends`
+pub(crate) fn load_merged_yaml<F>(s: &str, resolve_parent: &mut F) -> Result<Yaml, ParseSyntaxError>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    resolve_extends(
+        load_yaml_doc(s)?,
+        resolve_parent,
+        &mut Vec::new(),
+        &mut Vec::new(),
+    )
+}
+
+/// Replaces the `extends` key of a syntax with the merged contents of its parents.
+///
+/// `chain` contains the parents that are currently being resolved, to detect cycles. The
+/// documents of all parents are added to `parents` with the positions of their nodes.
+fn resolve_extends<F>(
+    doc: Yaml,
+    resolve_parent: &mut F,
+    chain: &mut Vec<String>,
+    parents: &mut Vec<SyntaxSource>,
+) -> Result<Yaml, ParseSyntaxError>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let parent_names = match doc.as_hash().and_then(|h| h.get(&yaml_key("extends"))) {
+        None => return Ok(doc),
+        Some(Yaml::String(s)) => vec![s.clone()],
+        Some(Yaml::Array(v)) => v
+            .iter()
+            .map(|y| y.as_str().map(str::to_owned))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(ParseSyntaxError::TypeMismatch)?,
+        Some(_) => return Err(ParseSyntaxError::TypeMismatch),
+    };
+
+    let mut base: Option<Hash> = None;
+    for name in parent_names {
+        if chain.contains(&name) {
+            return Err(ParseSyntaxError::CyclicExtends(name));
+        }
+        let source =
+            resolve_parent(&name).ok_or_else(|| ParseSyntaxError::MissingParent(name.clone()))?;
+        let parent = load_yaml_doc(&source)?;
+        parents.push((Some(name.clone()), parent.clone(), yaml_marks(&source)));
+        chain.push(name);
+        let parent = resolve_extends(parent, resolve_parent, chain, parents)?;
+        chain.pop();
+        let parent = parent.into_hash().ok_or(ParseSyntaxError::TypeMismatch)?;
+        // With multiple parents, later ones take precedence over earlier ones
+        base = Some(match base {
+            Some(base) => merge_syntax_yaml(base, parent),
+            None => parent,
+        });
+    }
+
+    let mut child = doc.into_hash().ok_or(ParseSyntaxError::TypeMismatch)?;
+    Ok(Yaml::Hash(match base {
+        Some(base) => merge_syntax_yaml(base, child),
+        None => {
+            child.remove(&yaml_key("extends"));
+            child
+        }
+    }))
+}
+
+/// Merges the variables and contexts of `parent` into `child`. All other top level keys (`name`,
+/// `scope`, `file_extensions`, ...) are not inherited.
+fn merge_syntax_yaml(parent: Hash, mut child: Hash) -> Hash {
+    child.remove(&yaml_key("extends"));
+
+    let mut variables = parent
+        .get(&yaml_key("variables"))
+        .and_then(Yaml::as_hash)
+        .cloned()
+        .unwrap_or_default();
+    if let Some(child_variables) = child.get(&yaml_key("variables")).and_then(Yaml::as_hash) {
+        for (key, value) in child_variables {
+            variables.insert(key.clone(), value.clone());
+        }
+    }
+    child.insert(yaml_key("variables"), Yaml::Hash(variables));
+
+    let mut contexts = parent
+        .get(&yaml_key("contexts"))
+        .and_then(Yaml::as_hash)
+        .cloned()
+        .unwrap_or_default();
+    if let Some(child_contexts) = child.get(&yaml_key("contexts")).and_then(Yaml::as_hash) {
+        for (name, child_context) in child_contexts {
+            let merged = match (
+                contexts.get(name).and_then(Yaml::as_vec),
+                child_context.as_vec(),
+            ) {
+                (Some(parent_context), Some(child_context)) => {
+                    merge_context_yaml(parent_context, child_context)
+                }
+                _ => child_context.clone(),
+            };
+            contexts.insert(name.clone(), merged);
+        }
+    }
+    child.insert(yaml_key("contexts"), Yaml::Hash(contexts));
+
+    child
+}
+
+/// Merges a context of the child syntax with the context of the same name from the parent,
+/// following `meta_prepend`/`meta_append`. Without either, the child context replaces the parent
+/// one. Meta entries of the child always take precedence over the ones of the parent.
+fn merge_context_yaml(parent: &[Yaml], child: &[Yaml]) -> Yaml {
+    let has_flag = |key: &str| {
+        child.iter().any(|y| {
+            y.as_hash()
+                .and_then(|h| h.get(&yaml_key(key)))
+                .and_then(Yaml::as_bool)
+                == Some(true)
+        })
+    };
+    let prepend = has_flag("meta_prepend");
+    if !prepend && !has_flag("meta_append") {
+        return Yaml::Array(child.to_vec());
+    }
+
+    let is_meta = |y: &Yaml| {
+        y.as_hash()
+            .is_some_and(|h| META_KEYS.iter().any(|k| h.contains_key(&yaml_key(k))))
+    };
+    let (parent_meta, parent_patterns): (Vec<Yaml>, Vec<Yaml>) =
+        parent.iter().cloned().partition(is_meta);
+    let (child_meta, child_patterns): (Vec<Yaml>, Vec<Yaml>) =
+        child.iter().cloned().partition(is_meta);
+
+    let mut merged = parent_meta;
+    merged.extend(child_meta);
+    if prepend {
+        merged.extend(child_patterns);
+        merged.extend(parent_patterns);
+    } else {
+        merged.extend(parent_patterns);
+        merged.extend(child_patterns);
+    }
+    Yaml::Array(merged)
+}
+
 struct ContextNamer {
     name: String,
     anonymous_index: Option<usize>,
@@ -815,7 +1567,7 @@ struct Parser<'a> {
 }
 
 impl<'a> Parser<'a> {
-    fn new(bytes: &[u8]) -> Parser {
+    fn new(bytes: &[u8]) -> Parser<'_> {
         Parser { bytes, index: 0 }
     }
 
@@ -887,7 +1639,6 @@ impl<'a> Parser<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parsing::syntax_definition::*;
     use crate::parsing::Scope;
 
     #[test]
@@ -1124,6 +1875,142 @@ mod tests {
         }
     }
 
+    #[test]
+    fn errors_have_locations() {
+        let load = |s: &str| SyntaxDefinition::load_from_str_with_parents(s, false, None, |_| None);
+        let err = load("scope: source.c\ncontexts:\n  main:\n    - match: '[a'").unwrap_err();
+        assert!(matches!(
+            err.error(),
+            ParseSyntaxError::RegexCompileError(..)
+        ));
+        assert_eq!(err.location().position, Some((4, 7)));
+        assert_eq!(err.location().context.as_deref(), Some("main"));
+        assert_eq!(err.location().pattern, Some(0));
+
+        let err = load(
+            "scope: source.c\ncontexts:\n  main:\n    - match: a\n      push:\n        - meta_scope: meta\n        - match: b\n          scope: a.b.c.d.e.f.g.h.i",
+        )
+        .unwrap_err();
+        assert!(matches!(err.error(), ParseSyntaxError::InvalidScope(_)));
+        let location = err.location();
+        assert_eq!(location.position, Some((7, 11)));
+        assert_eq!(location.context.as_deref(), Some("#anon_main_0"));
+        assert_eq!(location.pattern, Some(1));
+        assert!(err
+            .to_string()
+            .starts_with("line 7, column 11, context #anon_main_0, pattern 1: Invalid scope"));
+
+        // The location of a context that is generated for an `embed` is its pattern
+        let location = load(
+            "scope: source.c\ncontexts:\n  main:\n    - match: a\n    - match: b\n      embed: scope:source.x\n      escape: '[c'",
+        )
+        .unwrap_err()
+        .location()
+        .clone();
+        assert_eq!(location.position, Some((5, 7)));
+        assert_eq!(location.context.as_deref(), Some("main"));
+        assert_eq!(location.pattern, Some(1));
+
+        // Errors of the top level keys are at their value, or at the start if they're missing
+        let err = load("scope: source.c\ncontexts:\n  other: []").unwrap_err();
+        assert!(matches!(err.error(), ParseSyntaxError::MainMissing));
+        assert_eq!(err.location().position, Some((3, 3)));
+        let err = load("name: C\ncontexts: {main: []}").unwrap_err();
+        assert!(matches!(
+            err.error(),
+            ParseSyntaxError::MissingMandatoryKey("scope")
+        ));
+        assert_eq!(err.location().position, Some((1, 1)));
+        let err = load("scope: source.c\ncontexts: []").unwrap_err();
+        assert!(matches!(err.error(), ParseSyntaxError::TypeMismatch));
+        assert_eq!(err.to_string(), "line 2, column 11: Type mismatch");
+    }
+
+    #[test]
+    fn errors_in_extended_syntaxes_have_locations() {
+        let load = |s: &str| {
+            SyntaxDefinition::load_from_str_with_parents(s, false, None, |name| {
+                match name {
+                "Parent.sublime-syntax" => {
+                    Some("scope: source.parent\ncontexts:\n  main:\n    - match: a".to_owned())
+                }
+                _ => Some(
+                    "scope: source.broken\ncontexts:\n  main:\n    - match: a\n  other:\n    - match: '[b'"
+                        .to_owned(),
+                ),
+            }
+            })
+        };
+        // An error in the syntax itself
+        let err = load(
+            "extends: Parent.sublime-syntax\nscope: source.child\ncontexts:\n  main:\n    - meta_prepend: true\n    - match: '[c'",
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.error(),
+            ParseSyntaxError::RegexCompileError(..)
+        ));
+        assert_eq!(err.location().position, Some((6, 7)));
+        assert_eq!(err.location().file, None);
+        assert_eq!(err.location().context.as_deref(), Some("main"));
+
+        // An error in a context of the parent
+        let err =
+            load("extends: Broken.sublime-syntax\nscope: source.child\ncontexts: {}").unwrap_err();
+        assert!(matches!(
+            err.error(),
+            ParseSyntaxError::RegexCompileError(..)
+        ));
+        assert_eq!(err.location().position, Some((6, 7)));
+        assert_eq!(
+            err.location().file.as_deref(),
+            Some("Broken.sublime-syntax")
+        );
+        assert!(err
+            .to_string()
+            .starts_with("Broken.sublime-syntax line 6, column 7, context other, pattern 0: "));
+    }
+
+    #[test]
+    fn can_load_leniently() {
+        let (defn, diagnostics) = SyntaxDefinition::load_from_str_lenient(
+            "scope: source.c\ncontexts:\n  main:\n    - match: '[a'\n    - match: '{{x}}{{x}}b'\n      scope: a.b.c.d.e.f.g.h.i\n    - match: c",
+            false,
+            None,
+            |_| None,
+        )
+        .unwrap();
+        assert_eq!(defn.contexts["main"].patterns.len(), 1);
+        assert_eq!(diagnostics.len(), 3);
+        assert!(matches!(
+            diagnostics[0].error(),
+            ParseSyntaxError::RegexCompileError(..)
+        ));
+        assert!(matches!(
+            diagnostics[1].error(),
+            ParseSyntaxError::UnknownVariable(ref name) if name == "x"
+        ));
+        assert!(matches!(
+            diagnostics[2].error(),
+            ParseSyntaxError::InvalidScope(_)
+        ));
+        assert_eq!(diagnostics[2].location().pattern, Some(1));
+
+        let result = SyntaxDefinition::load_from_str_lenient(
+            "scope: source.c\ncontexts: {}",
+            false,
+            None,
+            |_| None,
+        );
+        assert!(matches!(
+            result,
+            Err(LocatedSyntaxError {
+                error: ParseSyntaxError::MainMissing,
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn can_parse_ugly_yaml() {
         let defn: SyntaxDefinition = SyntaxDefinition::load_from_str(
@@ -1236,6 +2123,206 @@ mod tests {
         assert_eq!(def.unwrap().name, "C");
     }
 
+    #[test]
+    fn can_extend_parent_syntax() {
+        let parent = r#"
+        name: Base
+        scope: source.base
+        file_extensions: [base]
+        variables:
+          ident: '[a-z]+'
+        contexts:
+          main:
+            - meta_scope: meta.base
+            - match: '{{ident}}'
+              scope: variable.base
+          string:
+            - match: '"'
+              pop: true
+        "#;
+        let def = SyntaxDefinition::load_from_str_with_parents(
+            r#"
+        name: Derived
+        scope: source.derived
+        extends: Packages/Base/Base.sublime-syntax
+        variables:
+          ident: '[0-9]+'
+        contexts:
+          main:
+            - meta_prepend: true
+            - meta_scope: meta.derived
+            - match: x
+              scope: keyword.derived
+          string:
+            - meta_append: true
+            - match: '\.'
+              scope: constant.character.escape.derived
+          other:
+            - match: y
+        "#,
+            false,
+            None,
+            |name| {
+                assert_eq!(name, "Packages/Base/Base.sublime-syntax");
+                Some(parent.to_owned())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(def.name, "Derived");
+        assert_eq!(def.scope, Scope::new("source.derived").unwrap());
+        assert!(def.file_extensions.is_empty());
+        assert_eq!(def.variables["ident"], "[0-9]+");
+
+        let regexes = |name: &str| -> Vec<String> {
+            def.contexts[name]
+                .patterns
+                .iter()
+                .map(|p| match p {
+                    Pattern::Match(m) => m.regex.regex_str().to_owned(),
+                    Pattern::Include(_) => unreachable!(),
+                })
+                .collect()
+        };
+        // Prepended patterns come first, and the variable of the child is used in the parent
+        assert_eq!(regexes("main"), vec!["x", "[0-9]+"]);
+        assert_eq!(regexes("string"), vec!["\"", "\\."]);
+        assert_eq!(regexes("other"), vec!["y"]);
+        assert_eq!(
+            def.contexts["main"].meta_scope,
+            vec![Scope::new("meta.derived").unwrap()]
+        );
+    }
+
+    #[test]
+    fn errors_on_missing_or_cyclic_parent() {
+        // Without a way to find parents, the syntax is loaded on its own
+        let def = SyntaxDefinition::load_from_str(
+            "name: A\nscope: source.a\nextends: B.sublime-syntax\ncontexts: {main: []}",
+            false,
+            None,
+        )
+        .unwrap();
+        assert!(def.contexts["main"].patterns.is_empty());
+
+        let def = SyntaxDefinition::load_from_str_with_parents(
+            "name: A\nscope: source.a\nextends: B.sublime-syntax\ncontexts: {main: []}",
+            false,
+            None,
+            |_| None,
+        );
+        let err = def.unwrap_err();
+        // Errors of the parents are at the `extends` key
+        assert_eq!(err.location().position, Some((3, 10)));
+        match err.into_error() {
+            ParseSyntaxError::MissingParent(name) => assert_eq!(name, "B.sublime-syntax"),
+            _ => unreachable!("Got unexpected ParseSyntaxError"),
+        }
+
+        let def = SyntaxDefinition::load_from_str_with_parents(
+            "name: A\nscope: source.a\nextends: B.sublime-syntax\ncontexts: {main: []}",
+            false,
+            None,
+            |name| match name {
+                "B.sublime-syntax" => Some("scope: source.b\nextends: C.sublime-syntax".to_owned()),
+                _ => Some("scope: source.c\nextends: B.sublime-syntax".to_owned()),
+            },
+        );
+        match def.unwrap_err().into_error() {
+            ParseSyntaxError::CyclicExtends(name) => assert_eq!(name, "B.sublime-syntax"),
+            _ => unreachable!("Got unexpected ParseSyntaxError"),
+        }
+    }
+
+    #[test]
+    fn can_parse_branch_and_fail() {
+        let def = SyntaxDefinition::load_from_str(
+            r#"
+scope: source.test
+contexts:
+  main:
+    - match: (?=\w)
+      branch_point: word
+      branch: [a, [{match: b, pop: true}]]
+    - match: x
+      fail: word
+  a:
+    - match: a
+      pop: true
+"#,
+            false,
+            None,
+        )
+        .unwrap();
+        let main = &def.contexts["main"];
+        match main.patterns[0] {
+            Pattern::Match(ref pat) => match pat.operation {
+                MatchOperation::Branch {
+                    ref name,
+                    ref contexts,
+                } => {
+                    assert_eq!(name, "word");
+                    assert_eq!(contexts.len(), 2);
+                    assert_eq!(contexts[0], ContextReference::Named("a".to_owned()));
+                    assert!(matches!(contexts[1], ContextReference::Inline(_)));
+                }
+                _ => unreachable!("Expected branch operation"),
+            },
+            _ => unreachable!("Expected match pattern"),
+        }
+        match main.patterns[1] {
+            Pattern::Match(ref pat) => {
+                assert_eq!(pat.operation, MatchOperation::Fail("word".to_owned()))
+            }
+            _ => unreachable!("Expected match pattern"),
+        }
+
+        let def = SyntaxDefinition::load_from_str(
+            "scope: source.test\ncontexts: {main: [{match: a, branch: [main]}]}",
+            false,
+            None,
+        );
+        match def.unwrap_err() {
+            ParseSyntaxError::MissingMandatoryKey(key) => assert_eq!(key, "branch_point"),
+            _ => unreachable!("Got unexpected ParseSyntaxError"),
+        }
+    }
+
+    #[test]
+    fn can_parse_pop_count() {
+        let def = SyntaxDefinition::load_from_str(
+            "scope: source.test\ncontexts: {main: [{match: a, pop: 2}, {match: b, pop: true, push: main}, {match: c, pop: 1, set: [main]}, {match: d, pop: false, push: main}]}",
+            false,
+            None,
+        )
+        .unwrap();
+        let operations: Vec<_> = def.contexts["main"]
+            .patterns
+            .iter()
+            .map(|p| match *p {
+                Pattern::Match(ref pat) => pat.operation.clone(),
+                _ => unreachable!("Expected match pattern"),
+            })
+            .collect();
+        let main = || vec![ContextReference::Named("main".to_owned())];
+        assert_eq!(
+            operations,
+            vec![
+                MatchOperation::PopMultiple(2),
+                MatchOperation::PopAndPush(1, main()),
+                MatchOperation::PopAndSet(1, main()),
+                MatchOperation::Push(main()),
+            ]
+        );
+
+        let def = SyntaxDefinition::load_from_str(
+            "scope: source.test\ncontexts: {main: [{match: a, pop: 0}]}",
+            false,
+            None,
+        );
+        assert!(matches!(def, Err(ParseSyntaxError::TypeMismatch)));
+    }
+
     #[test]
     fn can_rewrite_regex_for_newlines() {
         fn rewrite(s: &str) -> String {
@@ -141,6 +141,9 @@ pub fn as_latex_escaped(v: &[(Style, &str)]) -> String {
 
 /// Print out the various push and pop operations in a vector
 /// with visual alignment to the line. Obviously for debugging.
+///
+/// To see which contexts and patterns produced the operations, use
+/// [`ParseState::parse_line_with_observer`](../parsing/struct.ParseState.html#method.parse_line_with_observer).
 #[cfg(feature = "parsing")]
 pub fn debug_print_ops(line: &str, ops: &[(usize, ScopeStackOp)]) {
     for &(i, ref op) in ops.iter() {
@@ -1,11 +1,7 @@
-use std::{
-    error::Error,
-    fmt::Display,
-    io::{Error as IoError, ErrorKind},
-};
+use std::{error::Error, fmt::Display, io::Error as IoError};
 
 use syntect::{
-    parsing::{ParseScopeError, ParseSyntaxError},
+    parsing::{EmitSyntaxError, ParseScopeError, ParseSyntaxError, ParsingError, SyntaxDefinition},
     LoadingError,
 };
 
@@ -25,9 +21,40 @@ fn loading_error_parse_syntax_display() {
     );
 }
 
+#[test]
+fn loading_error_located_parse_syntax_display() {
+    let error = SyntaxDefinition::load_from_str_located(
+        "scope: source.c\ncontexts:\n  main:\n    - match: a\n      pop: 0",
+        true,
+        None,
+    )
+    .unwrap_err();
+    assert!(matches!(error.error(), ParseSyntaxError::TypeMismatch));
+    assert_eq!(error.location().position, Some((4, 7)));
+    assert_display(
+        LoadingError::LocatedParseSyntax(error, String::from("file.sublime-syntax")),
+        "file.sublime-syntax: line 4, column 7, context main, pattern 0: Type mismatch",
+    );
+}
+
+#[test]
+fn located_syntax_error_display() {
+    let error = SyntaxDefinition::load_from_str_with_parents(
+        "scope: source.c\ncontexts:\n  main:\n    - match: a\n    - match: b\n      pop: 0",
+        true,
+        None,
+        |_| None,
+    )
+    .unwrap_err();
+    assert_display(
+        error,
+        "line 5, column 7, context main, pattern 1: Type mismatch",
+    );
+}
+
 #[test]
 fn loading_error_io_source() {
-    let io_error_source = IoError::new(ErrorKind::Other, "this is an error string");
+    let io_error_source = IoError::other("this is an error string");
     assert_display(
         LoadingError::Io(io_error_source).source().unwrap(),
         "this is an error string",
@@ -50,6 +77,35 @@ fn parse_syntax_error_regex_compile_error_display() {
     );
 }
 
+#[test]
+fn parse_syntax_error_missing_parent_display() {
+    assert_display(
+        ParseSyntaxError::MissingParent("Packages/C++/C.sublime-syntax".to_owned()),
+        "Parent syntax not found: Packages/C++/C.sublime-syntax",
+    );
+}
+
+#[test]
+fn emit_syntax_error_missing_context_display() {
+    assert_display(
+        EmitSyntaxError::MissingContext("#anon_main_0".to_owned()),
+        "Inline context not found: #anon_main_0",
+    );
+}
+
+#[test]
+fn parse_syntax_error_unknown_variable_display() {
+    assert_display(
+        ParseSyntaxError::UnknownVariable("identifier".to_owned()),
+        "Unknown variable: identifier",
+    );
+}
+
+#[test]
+fn parsing_error_cancelled_display() {
+    assert_display(ParsingError::Cancelled, "Parsing was cancelled");
+}
+
 #[test]
 fn parse_scope_error_display() {
     assert_display(
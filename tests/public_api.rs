@@ -3,9 +3,10 @@ fn public_api() {
     // Install a compatible nightly toolchain if it is missing
     rustup_toolchain::install(public_api::MINIMUM_NIGHTLY_RUST_VERSION).unwrap();
 
-    // Build rustdoc JSON
+    // Build rustdoc JSON, with the API that needs metadata from `.tmPreferences` files
     let rustdoc_json = rustdoc_json::Builder::default()
         .toolchain(public_api::MINIMUM_NIGHTLY_RUST_VERSION)
+        .features(["metadata"])
         .build()
         .unwrap();
 